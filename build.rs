@@ -0,0 +1,82 @@
+//! `MMUHTTPD_EMBED=DIR cargo build` bakes DIR into the executable, which then serves it from memory in place of the
+//! current directory (see `src/embed.rs`.)  ETags and compressed variants are worked out here, once, at build time.
+
+#![allow(dead_code)] // the borrowed modules have plenty that only the server itself uses
+
+#[path = "src/checksum.rs"] mod checksum;
+#[path = "src/compress.rs"] mod compress;
+#[path = "src/deflate.rs"]  mod deflate;
+#[path = "src/mime.rs"]     mod mime;
+
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    for borrowed in ["src/checksum.rs", "src/compress.rs", "src/deflate.rs", "src/mime.rs"] { println!("cargo:rerun-if-changed={borrowed}"); } // the `#[path]` modules above
+    println!("cargo:rerun-if-env-changed=MMUHTTPD_EMBED");
+    let out_dir = PathBuf::from(std::env::var_os("OUT_DIR").expect("OUT_DIR not set"));
+
+    let mut files = Vec::new();
+    if let Some(dir) = std::env::var_os("MMUHTTPD_EMBED").filter(|dir| !dir.is_empty()) {
+        let dir = std::fs::canonicalize(&dir).unwrap_or_else(|err| panic!("MMUHTTPD_EMBED: unable to find `{}`: {err}", Path::new(&dir).display()));
+        walk(&dir, "", &mut files);
+    }
+
+    let mut code = String::from("&[\n");
+    for (i, (name, path)) in files.iter().enumerate() {
+        let Some(path) = path.to_str() else { println!("cargo:warning=MMUHTTPD_EMBED: skipping non-UTF-8 path `{}`", path.display()); continue };
+        let content = std::fs::read(path).unwrap_or_else(|err| panic!("MMUHTTPD_EMBED: unable to read `{path}`: {err}"));
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok().and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs());
+        let mut crc = checksum::Crc32::new();
+        crc.update(&content);
+        let etag = format!("\"{:x}-{:08x}\"", content.len(), crc.get()); // by content: build machines' mtimes are meaningless
+
+        let compressible = content.len() as u64 >= compress::MIN_SIZE && mime::by_path(name).map_or(false, compress::is_compressible);
+        let variant = |encoding: compress::Encoding, ext: &str| {
+            if !compressible { return String::from("None") }
+            let mut e = compress::Encoder::new(Vec::new(), encoding).expect("compress into Vec");
+            e.write_all(&content).expect("compress into Vec");
+            let compressed = e.finish().expect("compress into Vec");
+            if compressed.len() >= content.len() { return String::from("None") }
+            let out = out_dir.join(format!("embed-{i}.{ext}"));
+            std::fs::write(&out, compressed).unwrap_or_else(|err| panic!("unable to write `{}`: {err}", out.display()));
+            format!("Some(include_bytes!({:?}) as &[u8])", out.to_str().expect("OUT_DIR should be UTF-8"))
+        };
+        let gzip    = variant(compress::Encoding::Gzip,    "gz");
+        let deflate = variant(compress::Encoding::Deflate, "zz");
+
+        writeln!(code, "    File {{ path: {name:?}, modified: {modified}, etag: {etag:?}, content: include_bytes!({path:?}), gzip: {gzip}, deflate: {deflate} }},").unwrap();
+    }
+    code.push(']');
+
+    let out = out_dir.join("embed.rs");
+    std::fs::write(&out, code).unwrap_or_else(|err| panic!("unable to write `{}`: {err}", out.display()));
+}
+
+/// Collect every file under `dir` as (`/`-separated path relative to the embedded root, real path.)
+fn walk(dir: &Path, prefix: &str, files: &mut Vec<(String, PathBuf)>) {
+    println!("cargo:rerun-if-changed={}", dir.display()); // N.B. catches files being added or removed
+    let mut entries = std::fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<Result<Vec<_>, _>>())
+        .unwrap_or_else(|err| panic!("MMUHTTPD_EMBED: unable to list `{}`: {err}", dir.display()));
+    entries.sort_by_key(|e| e.file_name());
+    for e in entries {
+        let path = e.path();
+        let Some(name) = e.file_name().to_str().map(|name| format!("{prefix}{name}")) else {
+            println!("cargo:warning=MMUHTTPD_EMBED: skipping non-UTF-8 name `{}`", path.display());
+            continue
+        };
+        let Ok(meta) = std::fs::metadata(&path) else { continue }; // e.g. a dangling symlink
+        if meta.is_dir() {
+            walk(&path, &format!("{name}/"), files);
+        } else if meta.is_file() {
+            println!("cargo:rerun-if-changed={}", path.display());
+            files.push((name, path));
+        }
+    }
+}
@@ -0,0 +1,440 @@
+//! Read-only `.zip` (stored or deflated) and `.tar` archives as webroots, read once at startup.
+//!
+//! Archives are indexed into a tree of [`Snapshot`]s up front, so lookups, listings, and PROPFIND work exactly as they
+//! do for real directories.  Member contents are read on demand.
+
+use crate::deflate;
+use crate::fs::dir::{Entry, Snapshot};
+use crate::fs::file::{Opened, Stat};
+use crate::vfs::Vfs;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+
+
+/// An indexed archive.  Paths within it are nominal: `site.zip/sub/a.txt`.
+pub struct Archive {
+    path:       PathBuf,
+    dirs:       HashMap<PathBuf, Arc<Snapshot>>,
+    files:      HashMap<PathBuf, Member>,
+}
+
+struct Member {
+    offset:     u64,    // of the contents (tar) or local file header (zip)
+    zip_local:  bool,   // does `offset` point at a zip local file header?
+    deflated:   bool,
+    compressed: u64,
+    len:        u64,
+    modified:   Option<SystemTime>,
+}
+
+/// Does `path` look like something [`open`] understands?
+pub fn is_archive(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    (ext.eq_ignore_ascii_case("zip") || ext.eq_ignore_ascii_case("tar")) && path.is_file()
+}
+
+/// Index the `.zip` or `.tar` at `path`.
+pub fn open(path: &Path) -> io::Result<Archive> {
+    let mut file = File::open(path)?;
+    let modified = file.metadata()?.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    let is_zip = path.extension().map_or(false, |e| e.eq_ignore_ascii_case("zip"));
+    let listed = if is_zip { read_zip(&mut file)? } else { read_tar(&mut file)? };
+
+    // directories by (sanitized) path: their subdirectories and files (later members replace earlier ones, as in tar)
+    #[derive(Default)] struct Dir { dirs: Vec<String>, files: BTreeMap<String, Member> }
+    let mut dirs = BTreeMap::<Vec<String>, Dir>::new();
+    dirs.insert(Vec::new(), Dir::default());
+    for (name, member) in listed {
+        let Some(mut segments) = sanitize(&name) else { continue };
+        let leaf = match member { Some(_) => segments.pop(), None => None };
+        for depth in 0 .. segments.len() {
+            if dirs.contains_key(&segments[..=depth]) { continue }
+            dirs.insert(segments[..=depth].to_vec(), Dir::default());
+            dirs.get_mut(&segments[..depth]).expect("bug: parent directory missing").dirs.push(segments[depth].clone());
+        }
+        if let (Some(leaf), Some(member)) = (leaf, member) {
+            dirs.get_mut(&segments).expect("bug: directory missing").files.insert(leaf, member);
+        }
+    }
+
+    let mut archive = Archive { path: path.into(), dirs: HashMap::new(), files: HashMap::new() };
+    for (segments, dir) in dirs {
+        let dir_path = segments.iter().fold(archive.path.clone(), |p, s| p.join(s));
+        let mut entries = Vec::new();
+        for name in dir.dirs.iter() {
+            entries.push(Entry::synthetic(name, dir_path.join(name), true));
+        }
+        for (name, member) in dir.files {
+            if dir.dirs.contains(&name) { continue } // both a file and a directory: the directory wins
+            entries.push(Entry::synthetic(&name, dir_path.join(&name), false));
+            archive.files.insert(dir_path.join(&name), member);
+        }
+        archive.dirs.insert(dir_path.clone(), Arc::new(Snapshot::with_entries(modified, modified, dir_path, entries)));
+    }
+    Ok(archive)
+}
+
+impl Vfs for Archive {
+    fn list(&self, path: &Path) -> Option<Arc<Snapshot>> { self.dirs.get(path).cloned() }
+
+    fn stat(&self, path: &Path) -> Option<Stat> {
+        let m = self.files.get(path)?;
+        Some(Stat { len: m.len, created: m.modified, modified: m.modified })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Opened> {
+        let m = self.files.get(path).ok_or(io::ErrorKind::NotFound)?;
+        let mut file = File::open(&self.path)?;
+        let mut offset = m.offset;
+        if m.zip_local {
+            let mut header = [0u8; 30];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut header)?;
+            if u32_at(&header, 0) != 0x04034b50 { return Err(invalid("zip: bad local file header")) }
+            offset += 30 + u64::from(u16_at(&header, 26)) + u64::from(u16_at(&header, 28));
+        }
+        if !m.deflated { return Ok(Opened::File(file, offset)) }
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Opened::Stream(Box::new(deflate::Decoder::new(file.take(m.compressed)))))
+    }
+}
+
+/// Split an archived path into segments, or `None` if it tries to escape (`..`) or is otherwise weird.
+fn sanitize(name: &str) -> Option<Vec<String>> {
+    let segments = name.split('/').filter(|s| !s.is_empty() && *s != ".").map(String::from).collect::<Vec<_>>();
+    if segments.is_empty() || segments.iter().any(|s| s == ".." || s.contains(['\\', '\0'])) { return None }
+    Some(segments)
+}
+
+fn invalid(message: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, message) }
+fn u16_at(b: &[u8], i: usize) -> u16 { u16::from_le_bytes([b[i], b[i+1]]) }
+fn u32_at(b: &[u8], i: usize) -> u32 { u32::from_le_bytes(b[i..i+4].try_into().expect("bug: slice length")) }
+fn u64_at(b: &[u8], i: usize) -> u64 { u64::from_le_bytes(b[i..i+8].try_into().expect("bug: slice length")) }
+
+
+
+/// Every (path, member) of a zip, with `None` members for directories.
+fn read_zip(file: &mut (impl Read + Seek)) -> io::Result<Vec<(String, Option<Member>)>> {
+    // the end of central directory record is followed by a comment of up to 64 KiB
+    let file_len = file.seek(SeekFrom::End(0))?;
+    let tail_len = file_len.min(22 + 0xFFFF);
+    let mut tail = vec![0u8; tail_len as usize];
+    file.seek(SeekFrom::Start(file_len - tail_len))?;
+    file.read_exact(&mut tail)?;
+    if tail.len() < 22 { return Err(invalid("zip: no end of central directory")) }
+    let eocd = (0 ..= tail.len() - 22).rev().find(|&i| u32_at(&tail, i) == 0x06054b50).ok_or_else(|| invalid("zip: no end of central directory"))?;
+    let mut count   = u64::from(u16_at(&tail, eocd + 10));
+    let mut cd_len  = u64::from(u32_at(&tail, eocd + 12));
+    let mut cd_at   = u64::from(u32_at(&tail, eocd + 16));
+
+    if (count == 0xFFFF || cd_len == 0xFFFF_FFFF || cd_at == 0xFFFF_FFFF) && eocd >= 20 && u32_at(&tail, eocd - 20) == 0x07064b50 {
+        let mut zip64 = [0u8; 56];
+        file.seek(SeekFrom::Start(u64_at(&tail, eocd - 20 + 8)))?;
+        file.read_exact(&mut zip64)?;
+        if u32_at(&zip64, 0) != 0x06064b50 { return Err(invalid("zip: bad zip64 end of central directory")) }
+        count   = u64_at(&zip64, 32);
+        cd_len  = u64_at(&zip64, 40);
+        cd_at   = u64_at(&zip64, 48);
+    }
+    if cd_at.saturating_add(cd_len) > file_len { return Err(invalid("zip: central directory out of bounds")) }
+
+    let mut cd = vec![0u8; cd_len as usize];
+    file.seek(SeekFrom::Start(cd_at))?;
+    file.read_exact(&mut cd)?;
+    let mut listed = Vec::new();
+    let mut rest = &cd[..];
+    for _ in 0 .. count {
+        if rest.len() < 46 || u32_at(rest, 0) != 0x02014b50 { return Err(invalid("zip: bad central directory entry")) }
+        let (flags, method) = (u16_at(rest, 8), u16_at(rest, 10));
+        let (name_len, extra_len, comment_len) = (usize::from(u16_at(rest, 28)), usize::from(u16_at(rest, 30)), usize::from(u16_at(rest, 32)));
+        if rest.len() < 46 + name_len + extra_len + comment_len { return Err(invalid("zip: truncated central directory")) }
+        let name = String::from_utf8_lossy(&rest[46 .. 46 + name_len]).into_owned(); // XXX: non-UTF-8 names are CP437, strictly speaking
+        let extra = &rest[46 + name_len .. 46 + name_len + extra_len];
+        let mut member = Member {
+            offset:     u64::from(u32_at(rest, 42)),
+            zip_local:  true,
+            deflated:   method == 8,
+            compressed: u64::from(u32_at(rest, 20)),
+            len:        u64::from(u32_at(rest, 24)),
+            modified:   dos_time(u16_at(rest, 14), u16_at(rest, 12)),
+        };
+        rest = &rest[46 + name_len + extra_len + comment_len ..];
+
+        let mut extra = extra;
+        while extra.len() >= 4 {
+            let (id, len) = (u16_at(extra, 0), usize::from(u16_at(extra, 2)));
+            let Some(data) = extra.get(4 .. 4 + len) else { break };
+            match id {
+                0x0001 => { // zip64: 64-bit versions of whichever fields overflowed, in this order
+                    let mut values = data.chunks_exact(8).map(|v| u64_at(v, 0));
+                    if member.len        == 0xFFFF_FFFF { member.len        = values.next().unwrap_or(member.len); }
+                    if member.compressed == 0xFFFF_FFFF { member.compressed = values.next().unwrap_or(member.compressed); }
+                    if member.offset     == 0xFFFF_FFFF { member.offset     = values.next().unwrap_or(member.offset); }
+                },
+                0x5455 if data.len() >= 5 && data[0] & 1 != 0 => { // extended timestamp: a UTC mtime, unlike the DOS time
+                    member.modified = unix_time(i64::from(u32_at(data, 1) as i32));
+                },
+                _ => {},
+            }
+            extra = &extra[4 + len ..];
+        }
+
+        if name.ends_with('/') {
+            listed.push((name, None));
+        } else if flags & 1 == 0 && matches!(method, 0 | 8) { // XXX: encrypted and otherwise compressed members are skipped
+            if member.offset.saturating_add(member.compressed) > file_len { return Err(invalid("zip: member out of bounds")) }
+            listed.push((name, Some(member)));
+        }
+    }
+    Ok(listed)
+}
+
+/// MS-DOS dates and times have no time zone: treat them as UTC.
+fn dos_time(date: u16, time: u16) -> Option<SystemTime> {
+    let (year, month, day) = (1980 + i64::from(date >> 9), u32::from((date >> 5) & 0xF), u32::from(date & 0x1F));
+    if !(1 ..= 12).contains(&month) || day == 0 { return None }
+    let days = days_from_civil(year, month, day);
+    unix_time(days * 86400 + i64::from(time >> 11) * 3600 + i64::from((time >> 5) & 0x3F) * 60 + i64::from(time & 0x1F) * 2)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (Howard Hinnant's algorithm.)
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn unix_time(seconds: i64) -> Option<SystemTime> {
+    let since = Duration::from_secs(seconds.unsigned_abs());
+    if seconds >= 0 { SystemTime::UNIX_EPOCH.checked_add(since) } else { SystemTime::UNIX_EPOCH.checked_sub(since) }
+}
+
+
+
+/// Every (path, member) of a ustar/GNU/pax tar, with `None` members for directories.
+fn read_tar(file: &mut File) -> io::Result<Vec<(String, Option<Member>)>> {
+    let len = file.metadata()?.len().min(i64::MAX as u64); // N.B. keeps `padded as i64` below from going negative
+    let mut reader = BufReader::new(file);
+    let mut listed = Vec::new();
+    let mut offset = 0u64;
+    let mut long_name = None;   // from a GNU `L` or pax `x` header, for the next member
+    let mut pax_size = None;
+    let mut pax_mtime = None;
+    loop {
+        let mut header = [0u8; 512];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {},
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && offset > 0 => break, // missing end of archive blocks
+            Err(err) => return Err(err),
+        }
+        offset += 512;
+        if header.iter().all(|&b| b == 0) { break } // end of archive
+
+        let checksum = header.iter().enumerate().map(|(i, &b)| if (148 .. 156).contains(&i) { 32 } else { u64::from(b) }).sum::<u64>();
+        if tar_number(&header[148 .. 156]) != Some(checksum) { return Err(invalid("tar: bad header checksum")) }
+        let size = pax_size.take().or_else(|| tar_number(&header[124 .. 136])).ok_or_else(|| invalid("tar: bad size"))?;
+        let mtime = pax_mtime.take().or_else(|| tar_number(&header[136 .. 148]).map(|t| t as i64));
+        let data_at = offset;
+        let padded = size.checked_add(511).ok_or_else(|| invalid("tar: bad size"))? / 512 * 512;
+        if data_at.checked_add(padded).map_or(true, |end| end > len) { return Err(invalid("tar: bad size")) }
+
+        let typeflag = header[156];
+        if matches!(typeflag, b'L' | b'x') {
+            let mut data = Vec::new();
+            (&mut reader).take(size).read_to_end(&mut data)?;
+            if data.len() as u64 != size { return Err(io::ErrorKind::UnexpectedEof.into()) }
+            reader.seek_relative((padded - size) as i64)?;
+            offset += padded;
+            if typeflag == b'L' {
+                long_name = Some(String::from_utf8_lossy(&data).trim_end_matches('\0').to_string());
+            } else {
+                for (key, value) in pax_records(&data) {
+                    match key {
+                        "path"  => long_name = Some(value.into()),
+                        "size"  => pax_size = value.parse().ok(),
+                        "mtime" => pax_mtime = value.split('.').next().and_then(|s| s.parse().ok()),
+                        _       => {},
+                    }
+                }
+            }
+            continue;
+        }
+
+        let name = long_name.take().unwrap_or_else(|| {
+            let field = |b: &[u8]| String::from_utf8_lossy(&b[.. b.iter().position(|&b| b == 0).unwrap_or(b.len())]).into_owned();
+            let (name, prefix) = (field(&header[0 .. 100]), field(&header[345 .. 500]));
+            if &header[257 .. 262] == b"ustar" && !prefix.is_empty() { format!("{prefix}/{name}") } else { name }
+        });
+        match typeflag {
+            b'0' | b'\0' | b'7' if !name.ends_with('/') => listed.push((name, Some(Member {
+                offset:     data_at,
+                zip_local:  false,
+                deflated:   false,
+                compressed: size,
+                len:        size,
+                modified:   mtime.and_then(unix_time),
+            }))),
+            b'5'    => listed.push((name, None)),
+            _       => {}, // XXX: links, devices, fifos, global pax headers... are skipped
+        }
+        reader.seek_relative(padded as i64)?;
+        offset += padded;
+    }
+    Ok(listed)
+}
+
+/// Octal, or base-256 (GNU) if the high bit of the first byte is set.
+fn tar_number(field: &[u8]) -> Option<u64> {
+    if field.first().map_or(false, |&b| b & 0x80 != 0) {
+        return field[1..].iter().try_fold(u64::from(field[0] & 0x7F), |n, &b| n.checked_mul(256).map(|n| n | u64::from(b)));
+    }
+    let digits = std::str::from_utf8(field).ok()?.trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() { return Some(0) }
+    u64::from_str_radix(digits, 8).ok()
+}
+
+/// `"<len> <key>=<value>\n"` records.
+fn pax_records(mut data: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    std::iter::from_fn(move || {
+        let space = data.iter().position(|&b| b == b' ')?;
+        let len = std::str::from_utf8(&data[..space]).ok()?.parse::<usize>().ok()?;
+        let record = data.get(space + 1 .. len)?;
+        data = &data[len..];
+        let (key, value) = std::str::from_utf8(record).ok()?.strip_suffix('\n')?.split_once('=')?;
+        Some((key, value))
+    })
+}
+
+
+
+#[test] fn check_zip() {
+    let mut zip = Vec::new();
+    let mut cd = Vec::new();
+    let mut e = deflate::Encoder::new(Vec::new());
+    io::Write::write_all(&mut e, &b"deflated ".repeat(10)).unwrap();
+    let members = [ // (name, method, data, uncompressed length)
+        ("a.txt",       0, b"stored".to_vec(),  6u32),
+        ("sub/",        0, Vec::new(),          0),
+        ("sub/b.txt",   8, e.finish().unwrap(), 90),
+        ("../evil",     0, b"x".to_vec(),       1),
+    ];
+    for (name, method, data, len) in members.iter() {
+        let offset = zip.len() as u32;
+        let fields = |sig: u32| {
+            let mut h = sig.to_le_bytes().to_vec();
+            h.extend([20, 0, 0, 0]);
+            h.extend((*method as u16).to_le_bytes());
+            h.extend([0, 0, 0x21, 0x56, 0, 0, 0, 0]); // 2023-01-01 00:00, no CRC
+            h.extend((data.len() as u32).to_le_bytes());
+            h.extend(len.to_le_bytes());
+            h.extend((name.len() as u16).to_le_bytes());
+            h.extend([0, 0]);
+            h
+        };
+        zip.extend(fields(0x04034b50));
+        zip.extend(name.as_bytes());
+        zip.extend(data);
+        cd.extend(0x02014b50u32.to_le_bytes());
+        cd.extend([20, 0]);
+        cd.extend(&fields(0)[4..]);
+        cd.extend([0; 10]);
+        cd.extend(offset.to_le_bytes());
+        cd.extend(name.as_bytes());
+    }
+    let cd_at = zip.len() as u32;
+    zip.extend(&cd);
+    zip.extend(0x06054b50u32.to_le_bytes());
+    zip.extend([0, 0, 0, 0, 4, 0, 4, 0]);
+    zip.extend((cd.len() as u32).to_le_bytes());
+    zip.extend(cd_at.to_le_bytes());
+    zip.extend([0, 0]);
+
+    let path = std::env::temp_dir().join(format!("mmuhttpd-check-zip-{}.zip", std::process::id()));
+    std::fs::write(&path, &zip).unwrap();
+    let archive = open(&path).unwrap();
+    let root = archive.list(&path).unwrap();
+    let read = |e: &Entry| { let mut out = Vec::new(); archive.read_range(e.path(), 0 .. archive.stat(e.path()).unwrap().len).unwrap().read_to_end(&mut out).unwrap(); out };
+
+    assert_eq!(root.entries().count(), 2, "../evil should be skipped");
+    assert_eq!(read(root.by_name("a.txt").unwrap()), b"stored");
+    let stat = archive.stat(root.by_name("a.txt").unwrap().path()).unwrap();
+    assert_eq!(stat.modified, dos_time(0x5621, 0)); // 2023-01-01
+    assert_eq!(stat.modified.unwrap().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs(), 1672531200);
+    let sub = root.by_name("sub").unwrap();
+    assert!(sub.is_dir() && archive.stat(sub.path()).is_none());
+    let Some(sub) = archive.list(sub.path()) else { panic!("sub should be a directory") };
+    assert_eq!(read(sub.by_name("b.txt").unwrap()), b"deflated ".repeat(10));
+
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test] fn check_tar() {
+    let checksum = |h: &mut [u8; 512]| {
+        h[148 .. 156].fill(0);
+        let checksum = h.iter().map(|&b| u32::from(b)).sum::<u32>() + 8 * 32;
+        h[148 .. 155].copy_from_slice(format!("{checksum:06o}\0").as_bytes());
+    };
+    let header = |name: &str, typeflag: u8, size: usize| {
+        let mut h = [0u8; 512];
+        h[.. name.len()].copy_from_slice(name.as_bytes());
+        h[124 .. 135].copy_from_slice(format!("{size:011o}").as_bytes());
+        h[136 .. 147].copy_from_slice(b"14354146400"); // 2023-01-01
+        h[156] = typeflag;
+        h[257 .. 263].copy_from_slice(b"ustar\0");
+        checksum(&mut h);
+        h
+    };
+    let pad = |data: &[u8]| { let mut d = data.to_vec(); d.resize((data.len() + 511) / 512 * 512, 0); d };
+    let long = format!("deep/{}.txt", "x".repeat(120));
+    let mut tar = Vec::new();
+    tar.extend(header("a.txt", b'0', 5));
+    tar.extend(pad(b"hello"));
+    tar.extend(header("././@LongLink", b'L', long.len()));
+    tar.extend(pad(long.as_bytes()));
+    tar.extend(header("deep/truncated", b'0', 4));
+    tar.extend(pad(b"long"));
+    tar.extend(header("link", b'2', 0));
+    tar.extend(header("a.txt", b'0', 7));
+    tar.extend(pad(b"replace"));
+    tar.extend([0; 1024]);
+
+    let path = std::env::temp_dir().join(format!("mmuhttpd-check-tar-{}.tar", std::process::id()));
+    std::fs::write(&path, &tar).unwrap();
+    let archive = open(&path).unwrap();
+    let root = archive.list(&path).unwrap();
+    let read = |e: &Entry| { let mut out = String::new(); archive.read_range(e.path(), 0 .. archive.stat(e.path()).unwrap().len).unwrap().read_to_string(&mut out).unwrap(); out };
+
+    let mut names = root.entries().map(|e| e.name_lossy()).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, ["a.txt", "deep"]);
+    assert_eq!(read(root.by_name("a.txt").unwrap()), "replace", "later members win");
+    let deep = archive.list(root.by_name("deep").unwrap().path()).unwrap();
+    assert_eq!(read(deep.by_name(&long[5..]).unwrap()), "long");
+    assert_eq!(archive.stat(deep.by_name(&long[5..]).unwrap().path()).unwrap().modified, unix_time(1672531200));
+
+    tar[148] ^= 1;
+    std::fs::write(&path, &tar).unwrap();
+    assert!(open(&path).is_err(), "bad checksum");
+
+    let mut huge = header("huge", b'0', 0);
+    huge[124 .. 136].copy_from_slice(&[0x80, 0, 0, 0, 0x80, 0, 0, 0, 0, 0, 0, 0]); // base-256 2^63
+    checksum(&mut huge);
+    std::fs::write(&path, [&huge[..], &[0; 1024]].concat()).unwrap();
+    assert!(open(&path).is_err(), "size overflowing i64");
+    std::fs::write(&path, [&header("short", b'0', 2000)[..], &pad(b"only this"), &[0; 1024]].concat()).unwrap();
+    assert!(open(&path).is_err(), "size past the end of the file");
+
+    assert_eq!(tar_number(b"0000644\0"), Some(0o644));
+    assert_eq!(tar_number(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]), Some(256));
+    let _ = std::fs::remove_file(&path);
+}
@@ -0,0 +1,68 @@
+//! Request bodies, framed by either `Content-Length` or `Transfer-Encoding: chunked`.
+
+use crate::chunked;
+use std::io::{self, BufRead, Read};
+
+
+
+/// Largest request body we'll accept.
+pub const DEFAULT_LIMIT : u64 = 256 * 1024 * 1024;
+
+pub enum Body<R: BufRead> {
+    Empty,
+    Length(io::Take<R>),
+    Chunked(chunked::Decoder<R>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)] pub enum Error {
+    /// Malformed `Content-Length`
+    BadRequest,
+
+    /// `Content-Length` exceeds the limit
+    TooLarge,
+
+    /// `Transfer-Encoding` other than `chunked`
+    NotImplemented,
+}
+
+impl<R: BufRead> Body<R> {
+    pub fn new(inner: R, content_length: Option<&str>, transfer_encoding: Option<&str>, limit: u64) -> Result<Self, Error> {
+        if let Some(te) = transfer_encoding {
+            // N.B. Transfer-Encoding overrides Content-Length per https://www.rfc-editor.org/rfc/rfc9112#section-6.3
+            if !te.trim().eq_ignore_ascii_case("chunked") { return Err(Error::NotImplemented) }
+            return Ok(Body::Chunked(chunked::Decoder::new(inner, limit)));
+        }
+
+        let Some(len) = content_length else { return Ok(Body::Empty) };
+        if len.is_empty() || !len.bytes().all(|b| b.is_ascii_digit()) { return Err(Error::BadRequest) }
+        let Ok(len) = len.parse::<u64>() else { return Err(Error::TooLarge) }; // all digits: can only fail by overflowing
+        if len > limit { return Err(Error::TooLarge) }
+        Ok(Body::Length(inner.take(len)))
+    }
+}
+
+impl<R: BufRead> Read for Body<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Body::Empty         => Ok(0),
+            Body::Chunked(body) => body.read(buf),
+            Body::Length(body)  => {
+                let n = body.read(buf)?;
+                if n == 0 && !buf.is_empty() && body.limit() > 0 { return Err(io::ErrorKind::UnexpectedEof.into()) }
+                Ok(n)
+            },
+        }
+    }
+}
+
+/// Did reading a body fail because it exceeded its size limit?
+pub fn is_too_large(err: &io::Error) -> bool { err.get_ref().map_or(false, |e| e.is::<TooLarge>()) }
+
+/// The error to fail reads with when a body exceeds its size limit.
+pub fn too_large() -> io::Error { io::Error::new(io::ErrorKind::InvalidData, TooLarge) }
+
+#[derive(Debug)] struct TooLarge;
+impl std::error::Error for TooLarge {}
+impl std::fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { write!(f, "body exceeds size limit") }
+}
@@ -0,0 +1,79 @@
+/// [CRC-32](https://www.rfc-editor.org/rfc/rfc1952#section-8) (ISO 3309 / ITU-T V.42) as used by gzip and zip.
+#[derive(Clone, Copy)] pub struct Crc32(u32);
+
+impl Default for Crc32 {
+    fn default() -> Self { Self::new() }
+}
+
+impl Crc32 {
+    const TABLE : [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut n = 0;
+        while n < 256 {
+            let mut c = n as u32;
+            let mut k = 0;
+            while k < 8 {
+                c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+                k += 1;
+            }
+            table[n] = c;
+            n += 1;
+        }
+        table
+    };
+
+    pub const fn new() -> Self { Self(0) }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut c = !self.0;
+        for &b in data { c = Self::TABLE[usize::from(c as u8 ^ b)] ^ (c >> 8); }
+        self.0 = !c;
+    }
+
+    pub fn get(&self) -> u32 { self.0 }
+}
+
+
+
+/// [Adler-32](https://www.rfc-editor.org/rfc/rfc1950#section-9) as used by zlib streams (`Content-Encoding: deflate`).
+#[derive(Clone, Copy)] pub struct Adler32 { a: u32, b: u32 }
+
+impl Default for Adler32 {
+    fn default() -> Self { Self::new() }
+}
+
+impl Adler32 {
+    const MOD : u32 = 65521;
+
+    pub const fn new() -> Self { Self { a: 1, b: 0 } }
+
+    pub fn update(&mut self, data: &[u8]) {
+        // 5552 is the largest n such that 255n(n+1)/2 + (n+1)(MOD-1) fits in a u32
+        for chunk in data.chunks(5552) {
+            for &byte in chunk {
+                self.a += u32::from(byte);
+                self.b += self.a;
+            }
+            self.a %= Self::MOD;
+            self.b %= Self::MOD;
+        }
+    }
+
+    pub fn get(&self) -> u32 { (self.b << 16) | self.a }
+}
+
+
+
+#[test] fn check_values() {
+    let mut crc = Crc32::new();
+    crc.update(b"123456789");
+    assert_eq!(0xCBF43926, crc.get());
+
+    let mut adler = Adler32::new();
+    adler.update(b"Wikipedia");
+    assert_eq!(0x11E60398, adler.get());
+
+    let mut big = Adler32::new();
+    big.update(&[0xFF; 100_000]);
+    assert_eq!(0x149A302C, big.get());
+}
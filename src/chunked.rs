@@ -0,0 +1,149 @@
+//! [`Transfer-Encoding: chunked`](https://www.rfc-editor.org/rfc/rfc9112#section-7.1) for bodies of unknown length.
+
+use std::io::{self, BufRead, Read, Write};
+
+
+
+/// Longest chunk-size line (including any chunk extensions) or trailer field line we'll accept.
+const MAX_LINE      : usize = 4 * 1024;
+
+/// Most trailer bytes we'll accept, in total.
+const MAX_TRAILERS  : usize = 8 * 1024;
+
+
+
+/// Writes each non-empty `write` as a single chunk - wrap in a [`std::io::BufWriter`] if your writes are tiny.
+/// Call [`Encoder::finish`] to write the terminating zero-length chunk.
+pub struct Encoder<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W) -> Self { Self { inner } }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() { return Ok(0) }
+        write!(self.inner, "{:X}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+
+
+/// Decodes a chunked body, rejecting bodies larger than `limit` (see [`crate::body::is_too_large`].)
+/// Chunk extensions are ignored.  Trailer fields are checked (and bounded), then discarded: nothing we serve needs them.
+pub struct Decoder<R: BufRead> {
+    inner:      R,
+    state:      State,
+    limit:      u64,
+    read:       u64,
+}
+
+#[derive(Clone, Copy)] enum State {
+    Size,
+    Data(u64),
+    DataEnd,
+    Done,
+}
+
+impl<R: BufRead> Decoder<R> {
+    pub fn new(inner: R, limit: u64) -> Self { Self { inner, state: State::Size, limit, read: 0 } }
+
+    fn read_line(&mut self, max: usize) -> io::Result<String> {
+        let mut line = Vec::new();
+        (&mut self.inner).take(max as u64 + 2).read_until(b'\n', &mut line)?;
+        if !line.ends_with(b"\n") {
+            return Err(if line.len() >= max + 2 { invalid("chunked line too long") } else { io::ErrorKind::UnexpectedEof.into() });
+        }
+        line.pop();
+        if line.ends_with(b"\r") { line.pop(); }
+        if line.len() > max { return Err(invalid("chunked line too long")) }
+        String::from_utf8(line).map_err(|_| invalid("chunked line not valid utf8"))
+    }
+
+    fn read_trailers(&mut self) -> io::Result<()> {
+        let mut budget = MAX_TRAILERS;
+        loop {
+            let line = self.read_line(MAX_LINE.min(budget))?;
+            budget = budget.saturating_sub(line.len() + 2);
+            if line.is_empty() { return Ok(()) }
+            let Some((name, _value)) = line.split_once(':') else { return Err(invalid("malformed trailer field")) };
+            if name.is_empty() || name.contains(|ch: char| ch.is_ascii_whitespace()) { return Err(invalid("malformed trailer field name")) }
+        }
+    }
+}
+
+impl<R: BufRead> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::Size => {
+                    let line = self.read_line(MAX_LINE)?;
+                    let size = line.split(';').next().unwrap_or("").trim();
+                    if size.is_empty() || size.len() > 16 || !size.bytes().all(|b| b.is_ascii_hexdigit()) { return Err(invalid("malformed chunk size")) }
+                    let size = u64::from_str_radix(size, 16).map_err(|_| invalid("malformed chunk size"))?;
+                    if size == 0 {
+                        self.read_trailers()?;
+                        self.state = State::Done;
+                    } else if size > self.limit - self.read {
+                        return Err(crate::body::too_large());
+                    } else {
+                        self.state = State::Data(size);
+                    }
+                },
+                State::Data(remaining) => {
+                    if buf.is_empty() { return Ok(0) }
+                    let max = buf.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+                    let n = self.inner.read(&mut buf[..max])?;
+                    if n == 0 { return Err(io::ErrorKind::UnexpectedEof.into()) }
+                    self.read += n as u64;
+                    self.state = if remaining == n as u64 { State::DataEnd } else { State::Data(remaining - n as u64) };
+                    return Ok(n);
+                },
+                State::DataEnd => {
+                    if !self.read_line(0)?.is_empty() { return Err(invalid("chunk data longer than chunk size")) }
+                    self.state = State::Size;
+                },
+            }
+        }
+    }
+}
+
+fn invalid(msg: &'static str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, msg) }
+
+
+
+#[test] fn check_decoder() {
+    fn decode(mut body: &[u8], limit: u64) -> io::Result<(Vec<u8>, &[u8])> {
+        let mut out = Vec::new();
+        Decoder::new(&mut body, limit).read_to_end(&mut out)?;
+        Ok((out, body))
+    }
+
+    let (body, rest) = decode(b"4\r\nWiki\r\n7;ext=1\r\npedia i\r\nB\r\nn \r\nchunks.\r\n0\r\nExpires: never\r\n\r\nnext", 1024).unwrap();
+    assert_eq!(body, b"Wikipedia in \r\nchunks.");
+    assert_eq!(rest, b"next", "trailers should be consumed, and nothing past them");
+    assert!(decode(b"0\r\nno colon\r\n\r\n", 1024).is_err());
+    assert!(decode(format!("0\r\n{}", "X-Long: value\r\n".repeat(1000)).as_bytes(), 1024).is_err(), "too many trailers");
+
+    assert!(decode(b"0\r\n\r\n", 0).unwrap().0.is_empty());
+    assert!(crate::body::is_too_large(&decode(b"5\r\nhello\r\n0\r\n\r\n", 4).unwrap_err()));
+    assert_eq!(decode(b"5\r\nhello\r\n0\r\n\r\n", 5).unwrap().0, b"hello");
+    assert!(decode(b"5\r\nhello world\r\n0\r\n\r\n", 1024).is_err());
+    assert!(decode(b"zz\r\n", 1024).is_err());
+    assert!(decode(b"+5\r\nhello\r\n0\r\n\r\n", 1024).is_err(), "from_str_radix alone would accept a sign");
+    assert!(decode(b"5\r\nhel", 1024).is_err());
+}
@@ -0,0 +1,122 @@
+//! `Content-Encoding: gzip` / `Content-Encoding: deflate` response compression.
+
+use crate::checksum::*;
+use crate::deflate;
+use std::io::{self, Write};
+
+
+
+/// Files smaller than this aren't worth the CPU (or the chunked encoding overhead.)
+pub const MIN_SIZE : u64 = 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)] pub enum Encoding {
+    /// [RFC 1952](https://www.rfc-editor.org/rfc/rfc1952) gzip framing
+    Gzip,
+
+    /// [RFC 1950](https://www.rfc-editor.org/rfc/rfc1950) zlib framing (which is what HTTP calls "deflate", despite the name)
+    Deflate,
+}
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip      => "gzip",
+            Encoding::Deflate   => "deflate",
+        }
+    }
+
+    /// Pick an encoding based on an `Accept-Encoding` header, preferring gzip on ties.
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut any = None;
+        for item in accept_encoding.split(',') {
+            let mut params = item.split(';');
+            let coding = params.next().unwrap_or("").trim();
+            let q = params.filter_map(|p| p.trim().strip_prefix("q=")).next().map_or(Some(1.0), |q| q.trim().parse::<f32>().ok());
+            let Some(q) = q else { continue }; // malformed q: ignore this coding
+            if      coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip")   { gzip = Some(q) }
+            else if coding.eq_ignore_ascii_case("deflate")                                          { deflate = Some(q) }
+            else if coding == "*"                                                                   { any = Some(q) }
+        }
+        let gzip    = gzip   .or(any).unwrap_or(0.0);
+        let deflate = deflate.or(any).unwrap_or(0.0);
+        if gzip <= 0.0 && deflate <= 0.0    { None }
+        else if gzip >= deflate             { Some(Encoding::Gzip) }
+        else                                { Some(Encoding::Deflate) }
+    }
+}
+
+/// Is `mime` worth compressing?  (Most image/audio/video/archive formats are already compressed.)
+pub fn is_compressible(mime: &str) -> bool {
+    let essence = crate::mime::essence(mime);
+    essence.starts_with("text/") || matches!(essence,
+        "application/json" | "application/wasm" | "image/svg+xml" |
+        "application/xml" | "application/xhtml+xml" | "application/atom+xml" | "application/typescript"
+    )
+}
+
+
+
+/// Compresses everything written to it, in the framing of the selected [`Encoding`].
+pub struct Encoder<W: Write> {
+    deflate:    deflate::Encoder<W>,
+    encoding:   Encoding,
+    crc32:      Crc32,
+    adler32:    Adler32,
+    size:       u32, // mod 2³²
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(mut inner: W, encoding: Encoding) -> io::Result<Self> {
+        match encoding {
+            //                                   ID1   ID2   CM    FLG   MTIME------------------ XFL   OS (255 = unknown)
+            Encoding::Gzip      => inner.write_all(&[0x1F, 0x8B, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF])?,
+            //                                   CMF   FLG (32K window, default compression, no dict, FCHECK)
+            Encoding::Deflate   => inner.write_all(&[0x78, 0x9C])?,
+        }
+        Ok(Self { deflate: deflate::Encoder::new(inner), encoding, crc32: Crc32::new(), adler32: Adler32::new(), size: 0 })
+    }
+
+    pub fn finish(self) -> io::Result<W> {
+        let mut inner = self.deflate.finish()?;
+        match self.encoding {
+            Encoding::Gzip => {
+                inner.write_all(&self.crc32.get().to_le_bytes())?;
+                inner.write_all(&self.size.to_le_bytes())?;
+            },
+            Encoding::Deflate => {
+                inner.write_all(&self.adler32.get().to_be_bytes())?;
+            },
+        }
+        Ok(inner)
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.deflate.write(buf)?;
+        let buf = &buf[..n];
+        match self.encoding {
+            Encoding::Gzip      => self.crc32.update(buf),
+            Encoding::Deflate   => self.adler32.update(buf),
+        }
+        self.size = self.size.wrapping_add(n as u32);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.deflate.flush() }
+}
+
+
+
+#[test] fn check_negotiate() {
+    assert_eq!(Encoding::negotiate(""),                             None);
+    assert_eq!(Encoding::negotiate("identity"),                     None);
+    assert_eq!(Encoding::negotiate("gzip, deflate, br"),            Some(Encoding::Gzip));
+    assert_eq!(Encoding::negotiate("deflate"),                      Some(Encoding::Deflate));
+    assert_eq!(Encoding::negotiate("gzip;q=0.5, deflate"),          Some(Encoding::Deflate));
+    assert_eq!(Encoding::negotiate("gzip;q=0, *"),                  Some(Encoding::Deflate));
+    assert_eq!(Encoding::negotiate("*;q=0"),                        None);
+    assert_eq!(Encoding::negotiate("GZIP"),                         Some(Encoding::Gzip));
+}
@@ -0,0 +1,120 @@
+//! `--cors`: let pages served from other origins (e.g. other dev servers) `fetch()` from us.
+
+use crate::request::Request;
+
+
+
+/// Everything we respond to.
+pub const DEFAULT_METHODS : &str = "GET, HEAD, OPTIONS, PROPFIND";
+
+pub struct Cors {
+    pub origins:        Vec<String>,    // `scheme://host[:port]`s, or `*` for any (only without `credentials`)
+    pub methods:        String,         // for `Access-Control-Allow-Methods`
+    pub headers:        Option<String>, // for `Access-Control-Allow-Headers`, or `None` to allow whatever's asked for
+    pub credentials:    bool,
+    pub max_age:        Option<u64>,
+}
+
+impl Cors {
+    /// Check an origin for `--cors=...`: `*`, or `scheme://host[:port]` without any path.
+    pub fn parse_origin(origin: &str) -> Result<String, String> {
+        let origin = origin.trim();
+        if origin == "*" { return Ok(origin.into()) }
+        let Some((scheme, host)) = origin.split_once("://") else { return Err(format!("expected `*` or `scheme://host[:port]`, but got {origin:?}")) };
+        if scheme.is_empty() || host.is_empty() || host.contains(['/', '?', '#']) || origin.bytes().any(|b| b.is_ascii_control() || b == b' ' || b == b',') {
+            return Err(format!("expected `*` or `scheme://host[:port]` (no path), but got {origin:?}"))
+        }
+        Ok(origin.into())
+    }
+
+    /// Check a comma separated list of `--cors-methods` or `--cors-headers`, normalizing the spacing.
+    pub fn parse_list(list: &str) -> Result<String, String> {
+        let items = list.split(',').map(|i| i.trim()).filter(|i| !i.is_empty()).collect::<Vec<_>>();
+        if let Some(bad) = items.iter().find(|i| !i.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))) {
+            return Err(format!("invalid method or header name {bad:?}"))
+        }
+        Ok(items.join(", "))
+    }
+
+    /// Is `req` a preflight we should answer (an `OPTIONS` with `Access-Control-Request-Method`, from an allowed origin?)
+    pub fn is_preflight(&self, req: &Request) -> bool {
+        req.method == b"OPTIONS" && req.header("Access-Control-Request-Method").is_some() && req.header("Origin").map_or(false, |o| self.allows(o))
+    }
+
+    /// `Access-Control-*` (and `Vary`) headers for the response to `req`, including preflight ones if [`Self::is_preflight`].
+    pub fn headers(&self, req: &Request) -> Vec<(&'static str, String)> {
+        let mut headers = vec![("Vary", String::from("Origin"))]; // N.B. even if there's no `Origin`: caches mustn't reuse this response for one that has
+        let Some(origin) = req.header("Origin").filter(|o| self.allows(o)) else { return headers };
+        let any = self.origins.iter().any(|o| o == "*");
+        debug_assert!(!(any && self.credentials), "bug: credentials for any origin");
+        headers.push(("Access-Control-Allow-Origin", if any { "*".into() } else { origin.into() }));
+        if self.credentials { headers.push(("Access-Control-Allow-Credentials", "true".into())); }
+        if self.is_preflight(req) {
+            headers.push(("Access-Control-Allow-Methods", self.methods.clone()));
+            if let Some(allowed) = self.headers.clone().or_else(|| req.header("Access-Control-Request-Headers").map(String::from)) {
+                headers.push(("Access-Control-Allow-Headers", allowed));
+            }
+            if let Some(max_age) = self.max_age { headers.push(("Access-Control-Max-Age", max_age.to_string())); }
+        }
+        headers
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.origins.iter().any(|o| o == "*" || o.eq_ignore_ascii_case(origin))
+    }
+}
+
+
+
+#[test] fn check_cors() {
+    use crate::request::Version;
+    let request = |method: &'static str, headers: Vec<(&'static str, &'static str)>| Request { method: method.as_bytes(), path: "/", search: "", version: Version::Http11, headers };
+    let header = |headers: &[(&'static str, String)], name: &str| headers.iter().find(|(n, _)| *n == name).map(|(_, v)| v.clone());
+
+    let mut cors = Cors { origins: vec!["http://localhost:8080".into()], methods: "GET, HEAD".into(), headers: None, credentials: false, max_age: Some(600) };
+    let preflight = request("OPTIONS", vec![("Origin", "http://localhost:8080"), ("Access-Control-Request-Method", "GET"), ("Access-Control-Request-Headers", "range")]);
+    assert!(cors.is_preflight(&preflight));
+    let h = cors.headers(&preflight);
+    assert_eq!(header(&h, "Access-Control-Allow-Origin").as_deref(), Some("http://localhost:8080"));
+    assert_eq!(header(&h, "Access-Control-Allow-Methods").as_deref(), Some("GET, HEAD"));
+    assert_eq!(header(&h, "Access-Control-Allow-Headers").as_deref(), Some("range"));
+    assert_eq!(header(&h, "Access-Control-Max-Age").as_deref(), Some("600"));
+
+    let get = request("GET", vec![("Origin", "http://localhost:8080")]);
+    assert!(!cors.is_preflight(&get));
+    assert_eq!(cors.headers(&get).len(), 2);
+    let elsewhere = request("OPTIONS", vec![("Origin", "http://evil.example"), ("Access-Control-Request-Method", "GET")]);
+    assert!(!cors.is_preflight(&elsewhere));
+    assert_eq!(cors.headers(&elsewhere), [("Vary", String::from("Origin"))]);
+
+    cors.credentials = true;
+    assert_eq!(header(&cors.headers(&get), "Access-Control-Allow-Credentials").as_deref(), Some("true"));
+    assert_eq!(header(&cors.headers(&elsewhere), "Access-Control-Allow-Credentials"), None);
+    cors.credentials = false;
+    cors.origins = vec!["*".into()];
+    assert_eq!(header(&cors.headers(&elsewhere), "Access-Control-Allow-Origin").as_deref(), Some("*"));
+
+    assert!(Cors::parse_origin("https://example.com").is_ok());
+    assert!(Cors::parse_origin("https://example.com/").is_err());
+    assert!(Cors::parse_origin("example.com").is_err());
+    assert_eq!(Cors::parse_list("get,HEAD ,").unwrap(), "get, HEAD");
+    assert!(Cors::parse_list("X-A, X B").is_err());
+}
+
+#[test] fn check_served_cors() {
+    use crate::serve::{memory_settings, request};
+    let mut settings = memory_settings(&[("sub/a.txt", b"abcdef"), ("big.txt", &[b'x'; 2000]), ("_redirects", b"/old /sub/a.txt 302\n")]);
+
+    let preflight = [("Origin", "http://localhost:8080"), ("Access-Control-Request-Method", "GET")];
+    assert_eq!(request(&settings, "OPTIONS", "/sub/a.txt", &preflight, b"").status, 405, "not --cors");
+    settings.cors = Some(Cors { origins: vec!["http://localhost:8080".into()], methods: DEFAULT_METHODS.into(), headers: None, credentials: false, max_age: None });
+    let res = request(&settings, "OPTIONS", "/sub/a.txt", &preflight, b"");
+    assert_eq!((res.status, res.header("Access-Control-Allow-Methods")), (204, Some(DEFAULT_METHODS)), "preflights work on files too");
+    assert_eq!(request(&settings, "OPTIONS", "/old", &preflight, b"").status, 204, "not redirected");
+    assert_eq!(request(&settings, "OPTIONS", "/old", &[preflight[0], preflight[1], ("Expect", "bogus")], b"").status, 417);
+    let res = request(&settings, "GET", "/big.txt", &[("Origin", "http://localhost:8080"), ("Accept-Encoding", "gzip")], b"");
+    assert_eq!(res.header("Access-Control-Allow-Origin"), Some("http://localhost:8080"));
+    assert_eq!(res.header("Vary"), Some("Accept-Encoding, Origin"));
+    let res = request(&settings, "GET", "/sub/missing.txt", &[("Origin", "http://localhost:8080")], b"");
+    assert_eq!((res.status, res.header("Access-Control-Allow-Origin")), (404, Some("http://localhost:8080")), "errors are readable too");
+}
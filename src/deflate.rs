@@ -0,0 +1,682 @@
+//! Dependency-free [RFC 1951](https://www.rfc-editor.org/rfc/rfc1951) DEFLATE.
+//!
+//! The encoder is a fairly naive LZ77 (hash chains, greedy matching) feeding per-block stored, fixed, or dynamic
+//! Huffman encoding - whichever comes out smallest.  Not competitive with zlib, but a lot better than nothing.
+//!
+//! The decoder is a straightforward streaming port of the approach in zlib's `contrib/puff`: decoding canonical
+//! Huffman codes a bit at a time is slow-ish, but simple and hard to get wrong.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+
+
+const WINDOW_SIZE   : usize = 32 * 1024;
+const BLOCK_SIZE    : usize = 64 * 1024;
+const MIN_MATCH     : usize = 3;
+const MAX_MATCH     : usize = 258;
+const MAX_CHAIN     : usize = 128;
+const HASH_BITS     : u32   = 15;
+
+const LENGTH_BASE   : [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA  : [u8;  29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DIST_BASE     : [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DIST_EXTRA    : [u8;  30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+const CLEN_ORDER    : [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+
+
+/// Streaming raw DEFLATE encoder.  Call [`Encoder::finish`] to write the final block.
+pub struct Encoder<W: Write> {
+    out:        BitWriter<W>,
+    window:     Vec<u8>,    // up to WINDOW_SIZE bytes of already-encoded history, followed by pending input
+    history:    usize,      // how many bytes of `window` are already-encoded history
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W) -> Self {
+        Self { out: BitWriter::new(inner), window: Vec::with_capacity(WINDOW_SIZE + BLOCK_SIZE), history: 0 }
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.encode_block(true)?;
+        self.out.finish()
+    }
+
+    fn encode_block(&mut self, last: bool) -> io::Result<()> {
+        let mut tokens = Vec::new();
+        lz77(&self.window, self.history, &mut tokens);
+        write_block(&mut self.out, &self.window[self.history..], &tokens, last)?;
+
+        let keep = self.window.len().min(WINDOW_SIZE);
+        let drop = self.window.len() - keep;
+        self.window.drain(..drop);
+        self.history = self.window.len();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let pending = self.window.len() - self.history;
+        let n = buf.len().min(BLOCK_SIZE - pending);
+        self.window.extend_from_slice(&buf[..n]);
+        if self.window.len() - self.history == BLOCK_SIZE { self.encode_block(false)?; }
+        Ok(n)
+    }
+
+    /// N.B. this only flushes whole bytes that have already been encoded - it doesn't force a block boundary.
+    fn flush(&mut self) -> io::Result<()> { self.out.flush() }
+}
+
+
+
+/// Streaming raw DEFLATE decoder.
+pub struct Decoder<R: Read> {
+    input:  BitReader<R>,
+    window: Vec<u8>,    // up to WINDOW_SIZE bytes of history, followed by decoded bytes not yet read
+    unread: usize,      // index of the first byte of `window` not yet read
+    state:  State,
+    last:   bool,       // is the current block the final one?
+}
+
+enum State {
+    Header,
+    Stored(usize), // bytes remaining
+    Huffman(Box<(Table, Table)>), // (literal/length, distance)
+    Done,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self { input: BitReader::new(inner), window: Vec::with_capacity(2 * WINDOW_SIZE), unread: 0, state: State::Header, last: false }
+    }
+
+    /// Decode up to roughly [`WINDOW_SIZE`] more bytes into `window`.
+    fn decode_some(&mut self) -> io::Result<()> {
+        if self.unread > WINDOW_SIZE {
+            let drop = self.unread - WINDOW_SIZE;
+            self.window.drain(..drop);
+            self.unread -= drop;
+        }
+        let target = self.window.len() + WINDOW_SIZE;
+        while self.window.len() < target {
+            match &mut self.state {
+                State::Done => break,
+                State::Header if self.last => self.state = State::Done,
+                State::Header => {
+                    self.last = self.input.bits(1)? == 1;
+                    self.state = match self.input.bits(2)? {
+                        0 => {
+                            self.input.align();
+                            let len = self.input.bits(16)?;
+                            let nlen = self.input.bits(16)?;
+                            if len != !nlen & 0xFFFF { return Err(invalid("stored block length mismatch")) }
+                            State::Stored(len as usize)
+                        },
+                        1 => State::Huffman(Box::new(Table::fixed())),
+                        2 => State::Huffman(Box::new(Table::dynamic(&mut self.input)?)),
+                        _ => return Err(invalid("invalid block type")),
+                    };
+                },
+                State::Stored(remaining) => {
+                    let n = (*remaining).min(target - self.window.len());
+                    self.input.bytes(&mut self.window, n)?;
+                    *remaining -= n;
+                    if *remaining == 0 { self.state = State::Header; }
+                },
+                State::Huffman(tables) => {
+                    let (lit, dist) = &**tables;
+                    while self.window.len() < target {
+                        let symbol = lit.decode(&mut self.input)?;
+                        match symbol {
+                            0 ..= 255   => { self.window.push(symbol as u8); continue },
+                            256         => { self.state = State::Header; break },
+                            _           => {},
+                        }
+
+                        let i = symbol - 257;
+                        if i >= LENGTH_BASE.len() { return Err(invalid("invalid length symbol")) }
+                        let len = usize::from(LENGTH_BASE[i]) + self.input.bits(LENGTH_EXTRA[i].into())? as usize;
+                        let i = dist.decode(&mut self.input)?;
+                        if i >= DIST_BASE.len() { return Err(invalid("invalid distance symbol")) }
+                        let d = usize::from(DIST_BASE[i]) + self.input.bits(DIST_EXTRA[i].into())? as usize;
+                        if d > self.window.len() { return Err(invalid("distance too far back")) }
+                        for _ in 0 .. len { self.window.push(self.window[self.window.len() - d]); }
+                    }
+                },
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.unread == self.window.len() && !matches!(self.state, State::Done) { self.decode_some()?; }
+        let n = buf.len().min(self.window.len() - self.unread);
+        buf[..n].copy_from_slice(&self.window[self.unread .. self.unread + n]);
+        self.unread += n;
+        Ok(n)
+    }
+}
+
+fn invalid(message: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, format!("deflate: {message}")) }
+
+/// Canonical Huffman decoding table: how many codes there are of each length, and the symbols in code order.
+struct Table {
+    count:      [u16; 16],
+    symbols:    Vec<u16>,
+}
+
+impl Table {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut count = [0u16; 16];
+        for &l in lengths { count[usize::from(l)] += 1; }
+        let mut left = 1i32;
+        for &c in count[1..].iter() {
+            left = (left << 1) - i32::from(c);
+            if left < 0 { return Err(invalid("over-subscribed huffman code")) } // N.B. incomplete codes are fine
+        }
+        let mut offsets = [0u16; 16];
+        for len in 1 .. 15 { offsets[len+1] = offsets[len] + count[len]; }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &l) in lengths.iter().enumerate() {
+            if l == 0 { continue }
+            symbols[usize::from(offsets[usize::from(l)])] = symbol as u16;
+            offsets[usize::from(l)] += 1;
+        }
+        count[0] = 0;
+        Ok(Self { count, symbols })
+    }
+
+    fn fixed() -> (Self, Self) {
+        let Huffman { lit, dist } = Huffman::fixed();
+        (Self::new(&lit.lengths).expect("bug: invalid fixed code"), Self::new(&dist.lengths).expect("bug: invalid fixed code"))
+    }
+
+    fn dynamic<R: Read>(input: &mut BitReader<R>) -> io::Result<(Self, Self)> {
+        let hlit  = input.bits(5)? as usize + 257;
+        let hdist = input.bits(5)? as usize + 1;
+        let hclen = input.bits(4)? as usize + 4;
+        if hlit > 286 || hdist > 30 { return Err(invalid("too many length or distance codes")) }
+
+        let mut clen = [0u8; 19];
+        for &s in CLEN_ORDER[..hclen].iter() { clen[s] = input.bits(3)? as u8; }
+        let clen = Self::new(&clen)?;
+
+        let mut lengths = Vec::with_capacity(hlit + hdist);
+        while lengths.len() < hlit + hdist {
+            let (value, repeat) = match clen.decode(input)? {
+                symbol @ 0 ..= 15   => (symbol as u8, 1),
+                16                  => (*lengths.last().ok_or_else(|| invalid("repeat with no previous length"))?, 3 + input.bits(2)?),
+                17                  => (0, 3 + input.bits(3)?),
+                _                   => (0, 11 + input.bits(7)?),
+            };
+            if lengths.len() + repeat as usize > hlit + hdist { return Err(invalid("too many code lengths")) }
+            lengths.extend(std::iter::repeat(value).take(repeat as usize));
+        }
+        if lengths[256] == 0 { return Err(invalid("no end of block code")) }
+        Ok((Self::new(&lengths[..hlit])?, Self::new(&lengths[hlit..])?))
+    }
+
+    fn decode<R: Read>(&self, input: &mut BitReader<R>) -> io::Result<usize> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in self.count[1..].iter() {
+            code |= input.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count { return Ok(self.symbols[(index + code - first) as usize].into()) }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("invalid huffman code"))
+    }
+}
+
+
+
+#[derive(Clone, Copy)] enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+fn lz77(window: &[u8], start: usize, tokens: &mut Vec<Token>) {
+    const NIL : u32 = u32::MAX;
+    fn hash(window: &[u8], i: usize) -> usize {
+        let v = u32::from(window[i]) << 16 | u32::from(window[i+1]) << 8 | u32::from(window[i+2]);
+        (v.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize
+    }
+    fn insert(window: &[u8], head: &mut [u32], prev: &mut [u32], i: usize) {
+        if i + MIN_MATCH <= window.len() {
+            let h = hash(window, i);
+            prev[i] = head[h];
+            head[h] = i as u32;
+        }
+    }
+    let mut head = vec![NIL; 1 << HASH_BITS];
+    let mut prev = vec![NIL; window.len()];
+
+    for i in 0 .. start { insert(window, &mut head, &mut prev, i); }
+
+    let mut i = start;
+    while i < window.len() {
+        let max_len = (window.len() - i).min(MAX_MATCH);
+        let mut best_len = 0;
+        let mut best_dist = 0;
+        if max_len >= MIN_MATCH {
+            let mut candidate = head[hash(window, i)];
+            let mut chain = MAX_CHAIN;
+            while candidate != NIL && chain > 0 {
+                let c = candidate as usize;
+                let dist = i - c;
+                if dist > WINDOW_SIZE { break }
+                if window[c + best_len.min(max_len-1)] == window[i + best_len.min(max_len-1)] {
+                    let len = window[c..c+max_len].iter().zip(&window[i..i+max_len]).take_while(|(a, b)| a == b).count();
+                    if len > best_len {
+                        best_len = len;
+                        best_dist = dist;
+                        if len == max_len { break }
+                    }
+                }
+                candidate = prev[c];
+                chain -= 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            tokens.push(Token::Match { len: best_len as u16, dist: best_dist as u16 });
+            for j in i .. i + best_len { insert(window, &mut head, &mut prev, j); }
+            i += best_len;
+        } else {
+            tokens.push(Token::Literal(window[i]));
+            insert(window, &mut head, &mut prev, i);
+            i += 1;
+        }
+    }
+}
+
+fn length_code(len: u16) -> usize { // → 0 ..= 28 (add 257 for the literal/length symbol)
+    if len == 258 { return 28 }
+    LENGTH_BASE[..28].iter().rposition(|&base| base <= len).expect("bug: match length < 3")
+}
+
+fn dist_code(dist: u16) -> usize {
+    DIST_BASE.iter().rposition(|&base| base <= dist).expect("bug: match distance < 1")
+}
+
+fn write_block<W: Write>(out: &mut BitWriter<W>, raw: &[u8], tokens: &[Token], last: bool) -> io::Result<()> {
+    let mut lit_freq = [0u32; 286];
+    let mut dist_freq = [0u32; 30];
+    let mut extra_bits = 0u64;
+    for &t in tokens {
+        match t {
+            Token::Literal(b) => lit_freq[usize::from(b)] += 1,
+            Token::Match { len, dist } => {
+                let (lc, dc) = (length_code(len), dist_code(dist));
+                lit_freq[257 + lc] += 1;
+                dist_freq[dc] += 1;
+                extra_bits += u64::from(LENGTH_EXTRA[lc] + DIST_EXTRA[dc]);
+            },
+        }
+    }
+    lit_freq[256] = 1; // end of block
+
+    let fixed = Huffman::fixed();
+    let dynamic = DynamicHeader::new(&lit_freq, &dist_freq);
+    let fixed_cost   = 3 + fixed.cost(&lit_freq, &dist_freq) + extra_bits;
+    let dynamic_cost = 3 + dynamic.cost + dynamic.codes.cost(&lit_freq, &dist_freq) + extra_bits;
+    let stored_cost  = 3 + 7 + 8 * (raw.len() as u64 + 4 * (1 + raw.len() as u64 / 0xFFFF));
+
+    if stored_cost <= fixed_cost.min(dynamic_cost) {
+        let mut chunks = raw.chunks(0xFFFF).peekable();
+        if chunks.peek().is_none() { return write_stored(out, &[], last) }
+        while let Some(chunk) = chunks.next() { write_stored(out, chunk, last && chunks.peek().is_none())?; }
+        Ok(())
+    } else if fixed_cost <= dynamic_cost {
+        out.put(u32::from(last) | 0b01 << 1, 3)?;
+        write_tokens(out, &fixed, tokens)
+    } else {
+        out.put(u32::from(last) | 0b10 << 1, 3)?;
+        dynamic.write(out)?;
+        write_tokens(out, &dynamic.codes, tokens)
+    }
+}
+
+fn write_stored<W: Write>(out: &mut BitWriter<W>, chunk: &[u8], last: bool) -> io::Result<()> {
+    debug_assert!(chunk.len() <= 0xFFFF);
+    out.put(u32::from(last), 3)?;
+    out.align()?;
+    let len = chunk.len() as u16;
+    out.put(u32::from(len), 16)?;
+    out.put(u32::from(!len), 16)?;
+    out.bytes(chunk)
+}
+
+fn write_tokens<W: Write>(out: &mut BitWriter<W>, codes: &Huffman, tokens: &[Token]) -> io::Result<()> {
+    for &t in tokens {
+        match t {
+            Token::Literal(b) => codes.lit.put(out, usize::from(b))?,
+            Token::Match { len, dist } => {
+                let (lc, dc) = (length_code(len), dist_code(dist));
+                codes.lit.put(out, 257 + lc)?;
+                out.put(u32::from(len - LENGTH_BASE[lc]), LENGTH_EXTRA[lc].into())?;
+                codes.dist.put(out, dc)?;
+                out.put(u32::from(dist - DIST_BASE[dc]), DIST_EXTRA[dc].into())?;
+            },
+        }
+    }
+    codes.lit.put(out, 256)
+}
+
+
+
+struct DynamicHeader {
+    codes:      Huffman,
+    hlit:       usize,
+    hdist:      usize,
+    hclen:      usize,
+    clen:       Code,
+    clen_syms:  Vec<(u8, u8)>, // (symbol, extra bits value)
+    cost:       u64,
+}
+
+impl DynamicHeader {
+    fn new(lit_freq: &[u32; 286], dist_freq: &[u32; 30]) -> Self {
+        let lit = Code::from_lengths(&lengths_limited(lit_freq, 15));
+        let dist = Code::from_lengths(&lengths_limited(dist_freq, 15));
+        let hlit  = 257.max(lit .lengths.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1));
+        let hdist = 1  .max(dist.lengths.iter().rposition(|&l| l != 0).map_or(0, |i| i + 1));
+
+        let all = lit.lengths[..hlit].iter().chain(dist.lengths[..hdist].iter()).copied().collect::<Vec<u8>>();
+        let mut clen_syms = Vec::new();
+        let mut i = 0;
+        while i < all.len() {
+            let l = all[i];
+            let run = all[i..].iter().take_while(|&&x| x == l).count();
+            if l == 0 && run >= 11 {
+                let n = run.min(138);
+                clen_syms.push((18, (n - 11) as u8));
+                i += n;
+            } else if l == 0 && run >= 3 {
+                clen_syms.push((17, (run - 3) as u8));
+                i += run;
+            } else if l != 0 && run >= 4 {
+                clen_syms.push((l, 0));
+                let n = (run - 1).min(6);
+                clen_syms.push((16, (n - 3) as u8));
+                i += 1 + n;
+            } else {
+                clen_syms.push((l, 0));
+                i += 1;
+            }
+        }
+
+        let mut clen_freq = [0u32; 19];
+        for &(sym, _) in clen_syms.iter() { clen_freq[usize::from(sym)] += 1; }
+        let clen = Code::from_lengths(&lengths_limited(&clen_freq, 7));
+        let hclen = 4.max(CLEN_ORDER.iter().rposition(|&s| clen.lengths[s] != 0).map_or(0, |i| i + 1));
+
+        let mut cost = 5 + 5 + 4 + 3 * hclen as u64;
+        for &(sym, _) in clen_syms.iter() {
+            cost += u64::from(clen.lengths[usize::from(sym)]) + match sym { 16 => 2, 17 => 3, 18 => 7, _ => 0 };
+        }
+
+        Self { codes: Huffman { lit, dist }, hlit, hdist, hclen, clen, clen_syms, cost }
+    }
+
+    fn write<W: Write>(&self, out: &mut BitWriter<W>) -> io::Result<()> {
+        out.put((self.hlit - 257) as u32, 5)?;
+        out.put((self.hdist - 1) as u32, 5)?;
+        out.put((self.hclen - 4) as u32, 4)?;
+        for &s in CLEN_ORDER[..self.hclen].iter() { out.put(self.clen.lengths[s].into(), 3)?; }
+        for &(sym, extra) in self.clen_syms.iter() {
+            self.clen.put(out, usize::from(sym))?;
+            match sym {
+                16 => out.put(extra.into(), 2)?,
+                17 => out.put(extra.into(), 3)?,
+                18 => out.put(extra.into(), 7)?,
+                _  => {},
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Huffman { lit: Code, dist: Code }
+
+impl Huffman {
+    fn fixed() -> Self {
+        let mut lit = [0u8; 288];
+        lit[  0..144].fill(8);
+        lit[144..256].fill(9);
+        lit[256..280].fill(7);
+        lit[280..288].fill(8);
+        Self { lit: Code::from_lengths(&lit), dist: Code::from_lengths(&[5; 30]) }
+    }
+
+    fn cost(&self, lit_freq: &[u32], dist_freq: &[u32]) -> u64 {
+        let cost = |code: &Code, freq: &[u32]| freq.iter().zip(code.lengths.iter()).map(|(&f, &l)| u64::from(f) * u64::from(l)).sum::<u64>();
+        cost(&self.lit, lit_freq) + cost(&self.dist, dist_freq)
+    }
+}
+
+/// Canonical huffman code, stored bit-reversed (deflate emits huffman codes MSB-first into an LSB-first bitstream.)
+struct Code {
+    lengths:    Vec<u8>,
+    codes:      Vec<u16>,
+}
+
+impl Code {
+    fn from_lengths(lengths: &[u8]) -> Self {
+        let mut bl_count = [0u16; 16];
+        for &l in lengths { bl_count[usize::from(l)] += 1; }
+        bl_count[0] = 0;
+        let mut next_code = [0u16; 16];
+        let mut code = 0u16;
+        for bits in 1 .. 16 {
+            code = (code + bl_count[bits-1]) << 1;
+            next_code[bits] = code;
+        }
+        let codes = lengths.iter().map(|&l| {
+            if l == 0 { return 0 }
+            let code = next_code[usize::from(l)];
+            next_code[usize::from(l)] += 1;
+            code.reverse_bits() >> (16 - l)
+        }).collect();
+        Self { lengths: lengths.into(), codes }
+    }
+
+    fn put<W: Write>(&self, out: &mut BitWriter<W>, symbol: usize) -> io::Result<()> {
+        debug_assert!(self.lengths[symbol] != 0, "bug: symbol {symbol} has no code");
+        out.put(self.codes[symbol].into(), self.lengths[symbol].into())
+    }
+}
+
+/// Huffman code lengths for `freq`, none longer than `max_bits`.  Always assigns at least two codes so the result is a
+/// complete prefix code (some decoders are picky about single-code trees.)
+fn lengths_limited(freq: &[u32], max_bits: u8) -> Vec<u8> {
+    use std::cmp::Reverse;
+    use std::collections::BinaryHeap;
+
+    let mut freq = freq.to_vec();
+    let used = freq.iter().filter(|&&f| f != 0).count();
+    if used < 2 {
+        for f in freq.iter_mut().take(2) { *f = (*f).max(1); }
+    }
+
+    loop {
+        let mut parent = vec![usize::MAX; freq.len()]; // leaves 0 .. freq.len(), then internal nodes
+        let mut heap = freq.iter().enumerate().filter(|(_, &f)| f != 0).map(|(i, &f)| Reverse((u64::from(f), i))).collect::<BinaryHeap<_>>();
+        while heap.len() > 1 {
+            let Reverse((fa, a)) = heap.pop().unwrap();
+            let Reverse((fb, b)) = heap.pop().unwrap();
+            let node = parent.len();
+            parent.push(usize::MAX);
+            parent[a] = node;
+            parent[b] = node;
+            heap.push(Reverse((fa + fb, node)));
+        }
+
+        let mut lengths = vec![0u8; freq.len()];
+        let mut overflow = false;
+        for (sym, len) in lengths.iter_mut().enumerate() {
+            if freq[sym] == 0 { continue }
+            let mut depth = 0u32;
+            let mut n = sym;
+            while parent[n] != usize::MAX { n = parent[n]; depth += 1; }
+            if depth > u32::from(max_bits) { overflow = true; break }
+            *len = depth as u8;
+        }
+        if !overflow { return lengths }
+
+        // flatten the distribution and try again - converges on a balanced tree, which always fits for our alphabets
+        for f in freq.iter_mut() { if *f != 0 { *f = (*f >> 1).max(1); } }
+    }
+}
+
+
+
+struct BitReader<R: Read> {
+    inner:  BufReader<R>,
+    bits:   u64,
+    nbits:  u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(inner: R) -> Self { Self { inner: BufReader::new(inner), bits: 0, nbits: 0 } }
+
+    fn bits(&mut self, nbits: u32) -> io::Result<u32> {
+        debug_assert!(nbits <= 32);
+        while self.nbits < nbits {
+            let Some(&b) = self.inner.fill_buf()?.first() else { return Err(io::ErrorKind::UnexpectedEof.into()) };
+            self.inner.consume(1);
+            self.bits |= u64::from(b) << self.nbits;
+            self.nbits += 8;
+        }
+        let value = (self.bits & ((1u64 << nbits) - 1)) as u32;
+        self.bits >>= nbits;
+        self.nbits -= nbits;
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        let partial = self.nbits % 8;
+        self.bits >>= partial;
+        self.nbits -= partial;
+    }
+
+    fn bytes(&mut self, out: &mut Vec<u8>, mut n: usize) -> io::Result<()> {
+        debug_assert!(self.nbits % 8 == 0);
+        while n > 0 && self.nbits > 0 {
+            out.push(self.bits(8)? as u8);
+            n -= 1;
+        }
+        let start = out.len();
+        out.resize(start + n, 0);
+        self.inner.read_exact(&mut out[start..])
+    }
+}
+
+
+
+struct BitWriter<W: Write> {
+    inner:  W,
+    buf:    Vec<u8>,
+    bits:   u64,
+    nbits:  u32,
+}
+
+impl<W: Write> BitWriter<W> {
+    const FLUSH_AT : usize = 16 * 1024;
+
+    fn new(inner: W) -> Self { Self { inner, buf: Vec::with_capacity(Self::FLUSH_AT + 64), bits: 0, nbits: 0 } }
+
+    fn put(&mut self, value: u32, nbits: u32) -> io::Result<()> {
+        debug_assert!(nbits <= 32);
+        debug_assert!(nbits == 32 || value >> nbits == 0);
+        self.bits |= u64::from(value) << self.nbits;
+        self.nbits += nbits;
+        while self.nbits >= 8 {
+            self.buf.push(self.bits as u8);
+            self.bits >>= 8;
+            self.nbits -= 8;
+        }
+        if self.buf.len() >= Self::FLUSH_AT { self.flush_buf()?; }
+        Ok(())
+    }
+
+    fn align(&mut self) -> io::Result<()> {
+        if self.nbits > 0 { self.put(0, 8 - self.nbits)?; }
+        Ok(())
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        debug_assert!(self.nbits == 0);
+        self.buf.extend_from_slice(bytes);
+        if self.buf.len() >= Self::FLUSH_AT { self.flush_buf()?; }
+        Ok(())
+    }
+
+    fn flush_buf(&mut self) -> io::Result<()> {
+        if !self.buf.is_empty() { self.inner.write_all(&self.buf)?; }
+        self.buf.clear();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_buf()?;
+        self.inner.flush()
+    }
+
+    fn finish(mut self) -> io::Result<W> {
+        self.align()?;
+        self.flush_buf()?;
+        Ok(self.inner)
+    }
+}
+
+
+
+#[test] fn check_decoder() {
+    let inflate = |data: &[u8]| { let mut out = Vec::new(); Decoder::new(data).read_to_end(&mut out).map(|_| out) };
+
+    // zlib -9 and -0 output
+    let fixed = [0xCB, 0x48, 0xCD, 0xC9, 0xC9, 0x57, 0xC8, 0x40, 0x90, 0x3A, 0x0A, 0x29, 0xA9, 0x69, 0x39, 0x89, 0x25, 0xA9, 0x8A, 0x5C, 0x19, 0x34, 0x90, 0x03, 0x00];
+    assert_eq!(inflate(&fixed).unwrap(), b"hello hello hello, deflate!\n".repeat(4));
+    assert_eq!(inflate(&[0x01, 0x06, 0x00, 0xF9, 0xFF, 0x73, 0x74, 0x6F, 0x72, 0x65, 0x64]).unwrap(), b"stored");
+    assert!(inflate(&fixed[..10]).is_err(), "truncated");
+    assert!(inflate(&[0x07]).is_err(), "invalid block type");
+    assert!(inflate(&[0x01, 0x06, 0x00, 0xF8, 0xFF]).is_err(), "stored length mismatch");
+
+    let mut text = Vec::new();
+    for i in 0 .. 20_000u32 { text.extend_from_slice(format!("line {} of {}\n", i % 997, i.wrapping_mul(2654435761) % 13).as_bytes()); }
+    let mut noise = vec![0u8; 100_000];
+    let mut x = 1u32;
+    for b in noise.iter_mut() { x ^= x << 13; x ^= x >> 17; x ^= x << 5; *b = x as u8; }
+    for data in [&b""[..], b"a", &text, &noise] {
+        let mut e = Encoder::new(Vec::new());
+        e.write_all(data).unwrap();
+        assert_eq!(inflate(&e.finish().unwrap()).unwrap(), data);
+    }
+}
+
+#[test] fn check_encoder() {
+    let deflate = |data: &[u8]| { let mut e = Encoder::new(Vec::new()); e.write_all(data).unwrap(); e.finish().unwrap() };
+    let inflate = |data: &[u8]| { let mut out = Vec::new(); Decoder::new(data).read_to_end(&mut out).unwrap(); out };
+    let btype = |data: &[u8]| (data[0] >> 1) & 0b11; // of the first block
+
+    let mut noise = vec![0u8; 70_000]; // > 0xFFFF: a stored block has to be split
+    let mut x = 1u32;
+    for b in noise.iter_mut() { x ^= x << 13; x ^= x >> 17; x ^= x << 5; *b = x as u8; }
+    let short = b"hello hello hello, deflate!\n".repeat(4);
+    let mut text = Vec::new();
+    for i in 0 .. 20_000u32 { text.extend_from_slice(format!("line {} of {}\n", i % 997, i.wrapping_mul(2654435761) % 13).as_bytes()); }
+
+    for (data, expected, what) in [(&b""[..], 0b01, "empty"), (&noise[..1000], 0b00, "stored"), (&noise, 0b00, "stored"), (&short, 0b01, "fixed"), (&text, 0b10, "dynamic")] {
+        let compressed = deflate(data);
+        assert_eq!(btype(&compressed), expected, "{what} block for {} bytes", data.len());
+        assert_eq!(inflate(&compressed), data, "{what} block for {} bytes", data.len());
+    }
+    assert!(deflate(&text).len() < text.len() / 4);
+    assert_eq!(deflate(&noise).len(), noise.len() + 3 * 5, "65535 + 1 + 4464 bytes, with 5 header bytes each");
+}
@@ -0,0 +1,414 @@
+//! `?download=zip` / `?download=tar`: stream a directory tree as a single archive, without temporary files.
+//!
+//! Zips use data descriptors (sizes and CRCs follow each member) so nothing needs to be known up front, and switch to
+//! ZIP64 records wherever a size or offset could overflow 32 bits.  Tars are POSIX ustar, with pax headers for long
+//! paths and huge files.
+
+use crate::*;
+use crate::fs::dir::Entry;
+use crate::fs::file::Stat;
+use crate::webdav::DateTimeUTC;
+
+use std::io::{self, Read, Write};
+use std::time::SystemTime;
+
+
+
+/// Directories nested deeper than this aren't included (e.g. `--symlinks follow` loops.)
+const MAX_DEPTH : usize = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)] pub enum Format {
+    Zip,
+    Tar,
+}
+
+impl Format {
+    /// The format requested by a `?download=...` query string.  `Some(Err(()))` if it's not one we know.
+    pub fn from_search(search: &str) -> Option<Result<Self, ()>> {
+        let value = search.strip_prefix('?')?.split('&').find_map(|kv| kv.strip_prefix("download="))?;
+        Some(match value {
+            "zip"   => Ok(Format::Zip),
+            "tar"   => Ok(Format::Tar),
+            _       => Err(()),
+        })
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self { Format::Zip => "zip", Format::Tar => "tar" }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self { Format::Zip => "application/zip", Format::Tar => "application/x-tar" }
+    }
+}
+
+/// Stream `dir` and everything visible beneath it into `out`, as `name/...`.  `dir_path` is `dir`'s decoded
+/// root-relative path (with a trailing `/`, or empty for the root) for `--allow`/`--deny`.  Files are only included if
+/// a GET for them would be served, i.e. they have a MIME type.
+pub fn write(out: &mut impl Write, settings: &Settings, name: &str, dir_path: &str, dir: mount::Dir, format: Format) -> io::Result<()> {
+    match format {
+        Format::Zip => walk(&mut Zip { out: Counter { inner: out, count: 0 }, central: Vec::new(), count: 0 }, settings, name, dir_path, dir),
+        Format::Tar => walk(&mut Tar { out }, settings, name, dir_path, dir),
+    }
+}
+
+trait Archiver {
+    /// `path` ends with `/`.
+    fn dir(&mut self, path: &str, modified: SystemTime) -> io::Result<()>;
+    /// `data` yields at most `stat.len` bytes.
+    fn file(&mut self, path: &str, stat: &Stat, data: &mut dyn Read, deflate: bool) -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+fn walk(archiver: &mut impl Archiver, settings: &Settings, name: &str, dir_path: &str, dir: mount::Dir) -> io::Result<()> {
+    struct Frame<'m> { archived: String, path: String, dir: mount::Dir<'m>, entries: Vec<Entry>, depth: usize, next: usize }
+    impl<'m> Frame<'m> {
+        fn new(archived: String, path: String, dir: mount::Dir<'m>, depth: usize) -> Self {
+            let mut entries = dir.snapshot().entries().cloned().collect::<Vec<_>>();
+            entries.sort_by(|a, b| a.name_os().cmp(b.name_os())); // deterministic archives for identical trees
+            Self { archived, path, dir, entries, depth, next: 0 }
+        }
+    }
+    archiver.dir(&format!("{name}/"), dir.snapshot().modified())?;
+    let mut stack = vec![Frame::new(format!("{name}/"), dir_path.into(), dir, 0)];
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(e) = frame.entries.get(frame.next) else { stack.pop(); continue };
+        frame.next += 1;
+        let path = format!("{}{}", frame.path, e.name_lossy());
+        if !settings.rules.allows(&path, e.is_dir()) { continue }
+        let archived = format!("{}{}", frame.archived, e.name_lossy());
+
+        if e.is_dir() {
+            if frame.depth >= MAX_DEPTH { continue }
+            let Some(subdir) = frame.dir.child(e.name_os()) else { continue }; // vanished or unreadable: skip it
+            archiver.dir(&format!("{archived}/"), subdir.snapshot().modified())?;
+            let depth = frame.depth + 1;
+            stack.push(Frame::new(archived + "/", path + "/", subdir, depth));
+        } else if e.is_file() {
+            let Some(vfs) = frame.dir.vfs_of(e) else { continue };
+            let Some(stat) = vfs.stat(e.path()) else { continue };
+            let Ok(mut data) = vfs.read_range(e.path(), 0 .. stat.len) else { continue };
+            let mut head = Vec::new();
+            let ext_mime = settings.mime.by_extension(e.name_lossy());
+            if ext_mime.is_none() && settings.sniff { (&mut data).take(mime::SNIFF_LEN as u64).read_to_end(&mut head)?; }
+            let sniffed = if ext_mime.is_none() && settings.sniff { mime::sniff(&head) } else { None };
+            let Some(mime) = ext_mime.or(sniffed).or_else(|| settings.mime.fallback()) else { continue }; // no type, no access - as for GET
+            let deflate = stat.len >= compress::MIN_SIZE && compress::is_compressible(mime);
+            archiver.file(&archived, &stat, &mut (&head[..]).chain(data), deflate)?;
+        }
+    }
+    archiver.finish()
+}
+
+
+
+struct Counter<W: Write> {
+    inner:  W,
+    count:  u64,
+}
+
+impl<W: Write> Write for Counter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+struct Zip<W: Write> {
+    out:        Counter<W>,
+    central:    Vec<u8>, // central directory, written by finish
+    count:      u64,
+}
+
+impl<W: Write> Zip<W> {
+    /// Members at least this large get ZIP64 local headers and data descriptors, with room for deflate's overhead.
+    const ZIP64_LEN : u64 = 0xF000_0000;
+
+    fn entry(&mut self, path: &str, modified: Option<SystemTime>, method: u16, zip64: bool, data: Option<&mut dyn Read>) -> io::Result<()> {
+        let offset = self.out.count;
+        let (time, date) = dos_time(modified);
+        let mtime = modified.and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs().min(u32::MAX.into()) as u32);
+        let version : u16 = if zip64 { 45 } else { 20 };
+        let flags : u16 = if data.is_some() { 0x0808 } else { 0x0800 }; // UTF-8 names, data descriptor for files
+
+        let mut local = Vec::new();
+        local.extend(0x04034b50u32.to_le_bytes());
+        for v in [version, flags, method, time, date] { local.extend(v.to_le_bytes()); }
+        local.extend(0u32.to_le_bytes()); // CRC: in the data descriptor
+        local.extend(if zip64 { [0xFF; 8] } else { [0; 8] });
+        local.extend((path.len() as u16).to_le_bytes());
+        local.extend((if zip64 { 20 + 9 } else { 9 } as u16).to_le_bytes());
+        local.extend(path.as_bytes());
+        if zip64 { local.extend([0x01, 0x00, 16, 0]); local.extend([0; 16]); }
+        local.extend([0x55, 0x54, 5, 0, 1]); // extended timestamp: UTC mtime
+        local.extend(mtime.to_le_bytes());
+        self.out.write_all(&local)?;
+
+        if let Some(data) = data {
+            let start = self.out.count;
+            let (crc, len) = match method {
+                8 => {
+                    let mut e = deflate::Encoder::new(&mut self.out);
+                    let copied = copy_crc(data, &mut e)?;
+                    e.finish()?;
+                    copied
+                },
+                _ => copy_crc(data, &mut self.out)?,
+            };
+            let compressed = self.out.count - start;
+
+            let mut descriptor = Vec::new();
+            descriptor.extend(0x08074b50u32.to_le_bytes());
+            descriptor.extend(crc.to_le_bytes());
+            if zip64 {
+                descriptor.extend(compressed.to_le_bytes());
+                descriptor.extend(len.to_le_bytes());
+            } else {
+                descriptor.extend((compressed as u32).to_le_bytes());
+                descriptor.extend((len as u32).to_le_bytes());
+            }
+            self.out.write_all(&descriptor)?;
+            self.central_entry(path, version, flags, method, (time, date), mtime, crc, compressed, len, offset, false)
+        } else {
+            self.central_entry(path, version, flags, method, (time, date), mtime, 0, 0, 0, offset, true)
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn central_entry(&mut self, path: &str, version: u16, flags: u16, method: u16, (time, date): (u16, u16), mtime: u32, crc: u32, compressed: u64, len: u64, offset: u64, is_dir: bool) -> io::Result<()> {
+        let mut zip64 = Vec::new();
+        let clamp = |v: u64, zip64: &mut Vec<u8>| if v >= 0xFFFF_FFFF { zip64.extend(v.to_le_bytes()); 0xFFFF_FFFFu32 } else { v as u32 };
+        let (len32, compressed32, offset32) = (clamp(len, &mut zip64), clamp(compressed, &mut zip64), clamp(offset, &mut zip64));
+        let version = if zip64.is_empty() { version } else { 45 };
+
+        let c = &mut self.central;
+        c.extend(0x02014b50u32.to_le_bytes());
+        c.extend([version as u8, 3]); // made by: unix, for the permissions below
+        for v in [version, flags, method, time, date] { c.extend(v.to_le_bytes()); }
+        c.extend(crc.to_le_bytes());
+        c.extend(compressed32.to_le_bytes());
+        c.extend(len32.to_le_bytes());
+        c.extend((path.len() as u16).to_le_bytes());
+        c.extend((if zip64.is_empty() { 9 } else { 4 + zip64.len() + 9 } as u16).to_le_bytes());
+        c.extend([0; 6]); // comment length, disk number, internal attributes
+        c.extend(if is_dir { (0o40755u32 << 16) | 0x10 } else { 0o100644 << 16 }.to_le_bytes());
+        c.extend(offset32.to_le_bytes());
+        c.extend(path.as_bytes());
+        if !zip64.is_empty() {
+            c.extend([0x01, 0x00]);
+            c.extend((zip64.len() as u16).to_le_bytes());
+            c.extend(&zip64);
+        }
+        c.extend([0x55, 0x54, 5, 0, 1]);
+        c.extend(mtime.to_le_bytes());
+        self.count += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> Archiver for Zip<W> {
+    fn dir(&mut self, path: &str, modified: SystemTime) -> io::Result<()> { self.entry(path, Some(modified), 0, false, None) }
+
+    fn file(&mut self, path: &str, stat: &Stat, data: &mut dyn Read, deflate: bool) -> io::Result<()> {
+        self.entry(path, stat.modified, if deflate { 8 } else { 0 }, stat.len >= Self::ZIP64_LEN, Some(data))
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let cd_at = self.out.count;
+        let cd_len = self.central.len() as u64;
+        let central = std::mem::take(&mut self.central);
+        self.out.write_all(&central)?;
+
+        let mut end = Vec::new();
+        if self.count >= 0xFFFF || cd_at >= 0xFFFF_FFFF || cd_len >= 0xFFFF_FFFF {
+            let zip64_at = self.out.count;
+            end.extend(0x06064b50u32.to_le_bytes());
+            end.extend(44u64.to_le_bytes()); // size of the rest of this record
+            end.extend([45, 3, 45, 0]);
+            end.extend([0; 8]); // disk numbers
+            for v in [self.count, self.count, cd_len, cd_at] { end.extend(v.to_le_bytes()); }
+            end.extend(0x07064b50u32.to_le_bytes());
+            end.extend(0u32.to_le_bytes());
+            end.extend(zip64_at.to_le_bytes());
+            end.extend(1u32.to_le_bytes());
+        }
+        end.extend(0x06054b50u32.to_le_bytes());
+        end.extend([0; 4]); // disk numbers
+        let count = self.count.min(0xFFFF) as u16;
+        end.extend(count.to_le_bytes());
+        end.extend(count.to_le_bytes());
+        end.extend((cd_len.min(0xFFFF_FFFF) as u32).to_le_bytes());
+        end.extend((cd_at.min(0xFFFF_FFFF) as u32).to_le_bytes());
+        end.extend([0; 2]); // comment length
+        self.out.write_all(&end)?;
+        self.out.flush()
+    }
+}
+
+/// Copy `data` to `out`, returning its CRC-32 and length.
+fn copy_crc(data: &mut dyn Read, out: &mut impl Write) -> io::Result<(u32, u64)> {
+    let (mut crc, mut len) = (checksum::Crc32::new(), 0u64);
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = match data.read(&mut buf) {
+            Ok(0)   => return Ok((crc.get(), len)),
+            Ok(n)   => n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        crc.update(&buf[..n]);
+        len += n as u64;
+        out.write_all(&buf[..n])?;
+    }
+}
+
+/// MS-DOS `(time, date)`, in UTC (see also the extended timestamp.)  Clamped to 1980, the earliest representable year.
+fn dos_time(modified: Option<SystemTime>) -> (u16, u16) {
+    let t = modified.and_then(|m| DateTimeUTC::try_from(m).ok()).unwrap_or_default();
+    if t.year < 1980 { return (0, (1 << 5) | 1) }
+    let time = (u16::from(t.hour) << 11) | (u16::from(t.minute) << 5) | u16::from(t.second / 2);
+    let date = (((t.year - 1980).min(127) as u16) << 9) | (u16::from(t.month_no) << 5) | u16::from(t.day_no);
+    (time, date)
+}
+
+
+
+struct Tar<W: Write> {
+    out: W,
+}
+
+impl<W: Write> Tar<W> {
+    /// Largest size a ustar header can hold (11 octal digits.)
+    const MAX_OCTAL : u64 = 0o777_7777_7777;
+
+    fn header(&mut self, path: &str, typeflag: u8, mode: u32, size: u64, modified: Option<SystemTime>) -> io::Result<()> {
+        let mtime = modified.and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok()).map_or(0, |d| d.as_secs()).min(Self::MAX_OCTAL);
+        let (prefix, name) = split_ustar(path).unwrap_or(("", ""));
+
+        let mut pax = String::new();
+        if name.is_empty() { pax_record(&mut pax, "path", path); }
+        if size > Self::MAX_OCTAL { pax_record(&mut pax, "size", &size.to_string()); }
+        if !pax.is_empty() {
+            let pax_name = format!("PaxHeaders/{}", path.rsplit('/').find(|s| !s.is_empty()).unwrap_or(""));
+            let pax_name = &pax_name[.. pax_name.char_indices().map(|(i, c)| i + c.len_utf8()).take_while(|&end| end <= 100).last().unwrap_or(0)];
+            self.out.write_all(&ustar_header("", pax_name, b'x', 0o644, pax.len() as u64, mtime))?;
+            self.out.write_all(pax.as_bytes())?;
+            self.pad(pax.len() as u64)?;
+        }
+        self.out.write_all(&ustar_header(prefix, name, typeflag, mode, size.min(Self::MAX_OCTAL), mtime))
+    }
+
+    fn pad(&mut self, len: u64) -> io::Result<()> {
+        self.out.write_all(&[0; 512][.. ((512 - len % 512) % 512) as usize])
+    }
+}
+
+impl<W: Write> Archiver for Tar<W> {
+    fn dir(&mut self, path: &str, modified: SystemTime) -> io::Result<()> { self.header(path, b'5', 0o755, 0, Some(modified)) }
+
+    fn file(&mut self, path: &str, stat: &Stat, data: &mut dyn Read, _deflate: bool) -> io::Result<()> {
+        self.header(path, b'0', 0o644, stat.len, stat.modified)?;
+        let copied = io::copy(data, &mut self.out)?;
+        io::copy(&mut io::repeat(0).take(stat.len - copied), &mut self.out)?; // XXX: shrank while we were reading it
+        self.pad(stat.len)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.write_all(&[0; 1024])?;
+        self.out.flush()
+    }
+}
+
+/// Split `path` into ustar `(prefix, name)` fields of at most 155 and 100 bytes, if possible.
+fn split_ustar(path: &str) -> Option<(&str, &str)> {
+    if path.len() <= 100 { return Some(("", path)) }
+    let trimmed = path.trim_end_matches('/');
+    let split = trimmed.match_indices('/').map(|(i, _)| i).find(|&i| i <= 155 && path.len() - i - 1 <= 100)?;
+    Some((&path[..split], &path[split + 1 ..]))
+}
+
+fn ustar_header(prefix: &str, name: &str, typeflag: u8, mode: u32, size: u64, mtime: u64) -> [u8; 512] {
+    let mut h = [0u8; 512];
+    h[.. name.len()].copy_from_slice(name.as_bytes());
+    h[100 .. 108].copy_from_slice(format!("{mode:07o}\0").as_bytes());
+    h[108 .. 116].copy_from_slice(b"0000000\0"); // uid
+    h[116 .. 124].copy_from_slice(b"0000000\0"); // gid
+    h[124 .. 136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+    h[136 .. 148].copy_from_slice(format!("{mtime:011o}\0").as_bytes());
+    h[148 .. 156].copy_from_slice(b"        ");
+    h[156] = typeflag;
+    h[257 .. 265].copy_from_slice(b"ustar\x0000");
+    h[345 .. 345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    let checksum = h.iter().map(|&b| u32::from(b)).sum::<u32>();
+    h[148 .. 156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    h
+}
+
+/// Append a `"<len> <key>=<value>\n"` record, where `<len>` counts itself.
+fn pax_record(out: &mut String, key: &str, value: &str) {
+    let rest = key.len() + value.len() + 3; // ' ', '=', '\n'
+    let mut len = rest + 1;
+    while len != rest + len.to_string().len() { len = rest + len.to_string().len(); }
+    out.push_str(&format!("{len} {key}={value}\n"));
+}
+
+
+
+#[test] fn check_download() {
+    use crate::vfs::Vfs;
+    let tmp = std::env::temp_dir().join(format!("mmuhttpd-check-download-{}", std::process::id()));
+    std::fs::create_dir_all(&tmp).unwrap();
+    let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1672531200);
+    let html = "<p>hello</p>\n".repeat(100);
+    let long = format!("site/{}/{}.txt", "d".repeat(90), "f".repeat(120));
+    let stat = |len: usize| Stat { len: len as u64, created: None, modified: Some(modified) };
+
+    for format in [Format::Zip, Format::Tar] {
+        let mut out = Vec::new();
+        let mut archiver : Box<dyn Archiver + '_> = match format {
+            Format::Zip => Box::new(Zip { out: Counter { inner: &mut out, count: 0 }, central: Vec::new(), count: 0 }),
+            Format::Tar => Box::new(Tar { out: &mut out }),
+        };
+        archiver.dir("site/", modified).unwrap();
+        archiver.file("site/index.html", &stat(html.len()), &mut html.as_bytes(), true).unwrap();
+        archiver.dir("site/empty/", modified).unwrap();
+        archiver.file(&long, &stat(4), &mut &b"long"[..], false).unwrap();
+        archiver.file("site/short.txt", &stat(5), &mut &b"abc"[..], false).unwrap(); // shrank while reading
+        archiver.finish().unwrap();
+        drop(archiver);
+        let path = tmp.join(format!("download.{}", format.extension()));
+        std::fs::write(&path, &out).unwrap();
+
+        let archive = archive::open(&path).unwrap();
+        let root = archive.list(&path).unwrap();
+        let subdir = |dir: &crate::fs::dir::Snapshot, name: &str| archive.list(dir.by_name(name).unwrap().path()).unwrap();
+        let read = |dir: &crate::fs::dir::Snapshot, name: &str| {
+            let e = dir.by_name(name).unwrap();
+            let mut s = Vec::new();
+            archive.read_range(e.path(), 0 .. archive.stat(e.path()).unwrap().len).unwrap().read_to_end(&mut s).unwrap();
+            s
+        };
+        let site = subdir(&root, "site");
+        assert_eq!(read(&site, "index.html"), html.as_bytes(), "{format:?}");
+        assert_eq!(archive.stat(site.by_name("index.html").unwrap().path()).unwrap().modified, Some(modified), "{format:?}");
+        assert_eq!(subdir(&site, "empty").entries().count(), 0, "{format:?}");
+        assert_eq!(read(&subdir(&site, &"d".repeat(90)), &long[96..]), b"long", "{format:?}");
+        let short = read(&site, "short.txt");
+        assert!(short.starts_with(b"abc"), "{format:?}");
+    }
+
+    assert_eq!(Format::from_search("?download=zip"),        Some(Ok(Format::Zip)));
+    assert_eq!(Format::from_search("?a=b&download=tar"),    Some(Ok(Format::Tar)));
+    assert_eq!(Format::from_search("?download=rar"),        Some(Err(())));
+    assert_eq!(Format::from_search(""),                     None);
+    assert_eq!(split_ustar(&long[..150]),                   Some((&long[..95], &long[96..150])));
+    let mut pax = String::new();
+    pax_record(&mut pax, "path", &"x".repeat(92)); // 99 bytes + 2 digits would be 101: 3 digits
+    assert_eq!(pax, format!("102 path={}\n", "x".repeat(92)));
+
+    let _ = std::fs::remove_dir_all(&tmp);
+}
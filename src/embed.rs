@@ -0,0 +1,36 @@
+//! A directory baked into the executable at build time via `MMUHTTPD_EMBED=DIR cargo build` (see `build.rs`.)
+
+use crate::vfs::{Memory, Precomputed};
+
+use std::borrow::Cow;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+
+
+struct File {
+    path:       &'static str,           // `/`-separated, relative to the embedded directory
+    modified:   u64,                    // seconds since the unix epoch
+    etag:       &'static str,
+    content:    &'static [u8],
+    gzip:       Option<&'static [u8]>,
+    deflate:    Option<&'static [u8]>,
+}
+
+static FILES : &[File] = include!(concat!(env!("OUT_DIR"), "/embed.rs"));
+
+/// Was this build made without `MMUHTTPD_EMBED` (or with an empty directory?)
+pub fn is_empty() -> bool { FILES.is_empty() }
+
+/// The contents of the embedded file at the `/`-separated `path`, if any.
+pub fn get(path: &str) -> Option<&'static [u8]> { FILES.iter().find(|f| f.path == path).map(|f| f.content) }
+
+/// Everything embedded, read-only, with paths starting at `root`.
+pub fn vfs(root: PathBuf) -> Memory {
+    let fs = Memory::new(root).read_only();
+    for f in FILES {
+        let precomputed = Precomputed { etag: Some(f.etag.into()), gzip: f.gzip.map(Cow::Borrowed), deflate: f.deflate.map(Cow::Borrowed) };
+        fs.insert_precomputed(f.path, f.content, SystemTime::UNIX_EPOCH + Duration::from_secs(f.modified), precomputed);
+    }
+    fs
+}
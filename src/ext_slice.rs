@@ -11,6 +11,7 @@ pub trait ExtSlice {
     }
 
     /// Split once at/excluding `window`
+    #[allow(clippy::type_complexity)] // just a pair of subslices: an alias would only obscure that
     fn split_once<'a>(&'a self, window: &[Self::Item]) -> Option<(&'a [Self::Item], &'a [Self::Item])> where Self::Item : PartialEq {
         let o = self.find_window(window)?;
         let (a, b) = self._ext_slice().split_at(o);
@@ -1,40 +1,113 @@
 pub mod dir {
+    use super::file::{Opened, Stat};
+    use crate::vfs::Vfs;
     use std::collections::HashMap;
     use std::ffi::*;
     use std::path::*;
     use std::sync::*;
-    use std::time::SystemTime;
+    use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+    use std::time::{Duration, Instant, SystemTime};
+
+
+
+    /// Directory [`Snapshot`]s, revalidated against the directory's mtime and reloaded after a TTL regardless.
+    /// The least recently used snapshots are evicted past a fixed number of directories.
+    pub struct Cache {
+        slots:          Mutex<Slots>,
+        max_entries:    usize,
+        ttl:            Duration,
+        symlinks:       Symlinks,
+    }
 
+    #[derive(Default)] struct Slots {
+        by_path:    HashMap<PathBuf, Arc<Slot>>,
+        tick:       u64, // incremented per lookup, for LRU bookkeeping
+    }
 
+    #[derive(Default)] struct Slot {
+        last_used:  AtomicU64,
+        loaded:     Mutex<Option<(Arc<Snapshot>, Instant)>>, // held while (re)loading, so only one thread reads a given dir
+    }
 
-    #[derive(Default)] pub struct Cache {
-        snapshots: Mutex<HashMap<PathBuf, Arc<Snapshot>>>,
+    impl Default for Cache {
+        fn default() -> Self { Self::new(Self::DEFAULT_MAX_ENTRIES, Self::DEFAULT_TTL, Symlinks::Deny) }
     }
 
     impl Cache {
-        pub fn new() -> Self { Default::default() }
+        pub const DEFAULT_MAX_ENTRIES   : usize     = 1024;
+        pub const DEFAULT_TTL           : Duration  = Duration::from_secs(5);
+
+        pub fn new(max_entries: usize, ttl: Duration, symlinks: Symlinks) -> Self { Self { slots: Default::default(), max_entries: max_entries.max(1), ttl, symlinks } }
 
         pub fn read_dir(&self, path: impl AsRef<Path> + Into<PathBuf>) -> Option<Arc<Snapshot>> { // XXX: this is a little awkward
             let meta = std::fs::metadata(path.as_ref()).ok()?;
             let modified = meta.modified().ok()?; // XXX
             let created = meta.created().unwrap_or(modified);
 
-            let mut snapshots = self.snapshots.lock().expect("bug: Mutex poisoned");
-            if let Some(snapshot) = snapshots.get(path.as_ref()) {
-                if modified == snapshot.modified {
+            let slot = {
+                let mut slots = self.slots.lock().expect("bug: Mutex poisoned");
+                slots.tick += 1;
+                let tick = slots.tick;
+                let slot = match slots.by_path.get(path.as_ref()) {
+                    Some(slot) => Arc::clone(slot),
+                    None => {
+                        let slot = Arc::new(Slot::default());
+                        slots.by_path.insert(path.as_ref().into(), Arc::clone(&slot));
+                        slot
+                    },
+                };
+                slot.last_used.store(tick, Relaxed);
+                while slots.by_path.len() > self.max_entries {
+                    let Some(lru) = slots.by_path.iter().min_by_key(|(_, s)| s.last_used.load(Relaxed)).map(|(p, _)| p.clone()) else { break };
+                    slots.by_path.remove(&lru);
+                }
+                slot
+            };
+
+            // Only this directory's slot is locked while reading it: other requests for other directories proceed.
+            let mut loaded = slot.loaded.lock().expect("bug: Mutex poisoned");
+            if let Some((snapshot, loaded_at)) = loaded.as_ref() {
+                if modified == snapshot.modified && loaded_at.elapsed() < self.ttl {
                     return Some(Arc::clone(snapshot));
                 }
             }
+            let snapshot = Arc::new(Snapshot::new(created, modified, path.as_ref(), &self.symlinks).ok()?); // XXX
+            *loaded = Some((Arc::clone(&snapshot), Instant::now()));
+            Some(snapshot)
+        }
 
-            // This might be long I/O while holding a lock.  This is semi-intentional:
-            // • no sense having multiple threads reading the same dir.
-            // • might be better to modify V={Arc<Snapshot> → Mutex<Option<Arc<Snapshot>>>} and drop the overall lock
-            // • OTOH this isn't meant to be a maximum performance production httpd
-            let snapshot = Arc::new(Snapshot::new(created, modified, path.as_ref()).ok()?); // XXX
+        /// Forget any snapshot of `dir`, e.g. because we just modified it.
+        #[allow(dead_code)] // for write methods: nothing served writes to disk (yet), see `Vfs::write`
+        pub fn invalidate(&self, dir: impl AsRef<Path>) {
+            self.slots.lock().expect("bug: Mutex poisoned").by_path.remove(dir.as_ref());
+        }
 
-            snapshots.insert(path.into(), Arc::clone(&snapshot));
-            Some(snapshot)
+        #[cfg(test)] fn len(&self) -> usize { self.slots.lock().unwrap().by_path.len() }
+    }
+
+    /// The real filesystem, with directory listings cached.
+    impl Vfs for Cache {
+        fn list(&self, path: &Path) -> Option<Arc<Snapshot>> { self.read_dir(path) }
+
+        fn stat(&self, path: &Path) -> Option<Stat> {
+            std::fs::metadata(path).ok().filter(|m| m.is_file()).map(|m| Stat::from(&m))
         }
+
+        fn open(&self, path: &Path) -> std::io::Result<Opened> { Ok(Opened::File(std::fs::File::open(path)?, 0)) }
+    }
+
+
+
+    /// What to make of symlinks inside the webroot.  Whatever the policy, it's applied when a directory is read, so GET,
+    /// listings, and PROPFIND all agree on which entries exist.
+    #[derive(Clone, Debug, PartialEq, Eq)] pub enum Symlinks {
+        /// Hide them entirely.
+        Deny,
+        /// Serve whatever they point at, wherever that is.
+        Follow,
+        /// Serve whatever they point at if it resolves to somewhere inside one of these canonicalized roots (e.g. one
+        /// per `--mount`), hide them otherwise.
+        WithinRoot(Vec<PathBuf>),
     }
 
 
@@ -45,6 +118,7 @@ pub mod dir {
         path:       PathBuf,
         entries:    Vec<Entry>,
         by_name:    HashMap<OsString, usize>, // indexes entries
+        by_folded:  HashMap<String, Vec<usize>>, // indexes entries by `fold(name_lossy)`, sorted by name
     }
 
     impl Default for Snapshot {
@@ -55,22 +129,58 @@ pub mod dir {
                 path:       Default::default(),
                 entries:    Default::default(),
                 by_name:    Default::default(),
+                by_folded:  Default::default(),
             }
         }
     }
 
     impl Snapshot {
-        pub fn new(created: SystemTime, modified: SystemTime, path: impl Into<PathBuf>) -> std::io::Result<Self> {
+        pub fn new(created: SystemTime, modified: SystemTime, path: impl Into<PathBuf>, symlinks: &Symlinks) -> std::io::Result<Self> {
             let path = path.into();
             let mut snapshot = Self { created, modified, path, .. Default::default() };
             for e in std::fs::read_dir(&snapshot.path)? {
-                let e : Entry = e?.into();
-                snapshot.by_name.insert(e.name_os().into(), snapshot.entries.len());
-                snapshot.entries.push(e);
+                let Some(e) = Entry::new(e?, symlinks) else { continue };
+                snapshot.push(e);
             }
+            snapshot.sort_folded();
             Ok(snapshot)
         }
 
+        /// Overlay `layers` (highest priority first) and `mounts` (which shadow everything) into a single directory.
+        /// The result takes its path and timestamps from the first layer, if any.
+        pub fn merge(mounts: Vec<Entry>, layers: &[Arc<Snapshot>]) -> Self {
+            let mut merged = match layers.first() {
+                Some(top)   => Self { created: top.created, modified: top.modified, path: top.path.clone(), .. Default::default() },
+                None        => Self::default(),
+            };
+            for e in mounts.into_iter().chain(layers.iter().flat_map(|layer| layer.entries.iter().cloned())) {
+                if merged.by_name.contains_key(e.name_os()) { continue } // shadowed
+                merged.push(e);
+            }
+            merged.sort_folded();
+            merged
+        }
+
+        /// A directory that doesn't come from reading a real one (e.g. inside an archive.)  `path` is whatever the
+        /// [`Vfs`] it came from understands.
+        pub fn with_entries(created: SystemTime, modified: SystemTime, path: PathBuf, entries: Vec<Entry>) -> Self {
+            let mut snapshot = Self { created, modified, path, .. Default::default() };
+            for e in entries { snapshot.push(e); }
+            snapshot.sort_folded();
+            snapshot
+        }
+
+        fn push(&mut self, e: Entry) {
+            self.by_name.insert(e.name_os().into(), self.entries.len());
+            self.by_folded.entry(fold(e.name_lossy())).or_default().push(self.entries.len());
+            self.entries.push(e);
+        }
+
+        fn sort_folded(&mut self) {
+            let Self { entries, by_folded, .. } = self;
+            for indices in by_folded.values_mut().filter(|i| i.len() > 1) { indices.sort_by(|&a, &b| entries[a].name_os().cmp(entries[b].name_os())); }
+        }
+
         pub fn created(&self) -> SystemTime { self.created }
         pub fn modified(&self) -> SystemTime { self.modified }
         pub fn path(&self) -> &Path { self.path.as_path() }
@@ -81,12 +191,19 @@ pub mod dir {
             self.entries.get(index)
         }
 
-        pub fn entries<'e>(&'e self) -> impl Iterator<Item = &'e Entry> { self.entries.iter() }
+        /// Entries whose names match `name` case-insensitively, in a deterministic order.
+        pub fn by_folded_name<'e>(&'e self, name: &str) -> impl Iterator<Item = &'e Entry> {
+            self.by_folded.get(&fold(name)).into_iter().flatten().filter_map(|&i| self.entries.get(i))
+        }
+
+        pub fn entries(&self) -> impl Iterator<Item = &Entry> { self.entries.iter() }
     }
 
+    fn fold(name: &str) -> String { name.to_lowercase() }
+
 
 
-    pub struct Entry {
+    #[derive(Clone)] pub struct Entry {
         name_os:    Option<OsString>, // None = name_lossy
         name_lossy: String,
         path:       PathBuf,
@@ -94,26 +211,50 @@ pub mod dir {
     }
 
     impl Entry {
-        pub fn name_os      (&self) -> &OsStr   { self.name_os.as_deref().unwrap_or(OsStr::new(&self.name_lossy)) }
+        pub fn name_os      (&self) -> &OsStr   { self.name_os.as_deref().unwrap_or_else(|| OsStr::new(&self.name_lossy)) }
         pub fn name_lossy   (&self) -> &str     { &self.name_lossy }
         pub fn path         (&self) -> &Path    { &self.path }
         pub fn is_dir       (&self) -> bool     { self.flags & EntryFlag::IS_DIR  != EntryFlag::NONE }
         pub fn is_file      (&self) -> bool     { self.flags & EntryFlag::IS_FILE != EntryFlag::NONE }
     }
 
-    impl From<std::fs::DirEntry> for Entry {
-        fn from(de: std::fs::DirEntry) -> Self {
+    impl Entry {
+        /// A directory mounted at `name`, e.g. `pkg` for `--mount /pkg/=path`.
+        pub fn mount(name: &str, path: PathBuf) -> Self {
+            Self::synthetic(name, path, true)
+        }
+
+        /// A file or directory that doesn't come from reading a real directory (e.g. inside an archive.)
+        pub fn synthetic(name: &str, path: PathBuf, is_dir: bool) -> Self {
+            let flags = if is_dir { EntryFlag::IS_DIR } else { EntryFlag::IS_FILE };
+            Self { name_os: None, name_lossy: name.into(), path, flags }
+        }
+
+        /// `None` if `symlinks` says `de` shouldn't exist as far as clients are concerned.
+        fn new(de: std::fs::DirEntry, symlinks: &Symlinks) -> Option<Self> {
             let name_os = de.file_name();
             let (name_lossy, name_os) = match name_os.into_string() {
                 Ok(name_lossy)  => (name_lossy, None),
                 Err(name_os)    => (name_os.to_string_lossy().into_owned(), Some(name_os)),
             };
+            let path = de.path();
+            let file_type = match de.file_type() {
+                Ok(file_type) if file_type.is_symlink() => match symlinks {
+                    Symlinks::Deny              => return None,
+                    Symlinks::Follow            => std::fs::metadata(&path).ok()?.file_type(), // dangling: hide
+                    Symlinks::WithinRoot(root)  => {
+                        let target = std::fs::canonicalize(&path).ok()?;
+                        if !root.iter().any(|root| target.starts_with(root)) { return None }
+                        std::fs::metadata(&target).ok()?.file_type()
+                    },
+                },
+                Ok(file_type)   => file_type,
+                Err(_)          => return Some(Self { name_os, name_lossy, path, flags: EntryFlag::NONE }),
+            };
             let mut flags = EntryFlag::NONE;
-            if let Ok(file_type) = de.file_type() {
-                if file_type.is_dir()  { flags |= EntryFlag::IS_DIR  }
-                if file_type.is_file() { flags |= EntryFlag::IS_FILE }
-            }
-            Self { name_os, name_lossy, path: de.path(), flags }
+            if file_type.is_dir()  { flags |= EntryFlag::IS_DIR  }
+            if file_type.is_file() { flags |= EntryFlag::IS_FILE }
+            Some(Self { name_os, name_lossy, path, flags })
         }
     }
 
@@ -129,4 +270,263 @@ pub mod dir {
     impl core::ops::BitOr  for EntryFlag { type Output = Self; fn bitor (self, rhs: Self) -> Self::Output { Self(self.0 | rhs.0) } }
     impl core::ops::BitAndAssign for EntryFlag { fn bitand_assign(&mut self, rhs: Self) { self.0 &= rhs.0 } }
     impl core::ops::BitOrAssign  for EntryFlag { fn bitor_assign (&mut self, rhs: Self) { self.0 |= rhs.0 } }
+
+
+
+    #[test] fn check_cache() {
+        let root = std::env::temp_dir().join(format!("mmuhttpd-check-cache-{}", std::process::id()));
+        let dirs = ["a", "b", "c"].map(|d| root.join(d));
+        for dir in dirs.iter() { std::fs::create_dir_all(dir).unwrap(); }
+
+        let cache = Cache::new(2, Duration::from_secs(3600), Symlinks::Deny);
+        let a = cache.read_dir(&dirs[0]).unwrap();
+        assert!(Arc::ptr_eq(&a, &cache.read_dir(&dirs[0]).unwrap()), "should be cached");
+        let _ = cache.read_dir(&dirs[1]).unwrap();
+        let _ = cache.read_dir(&dirs[0]).unwrap(); // a is now more recently used than b
+        let _ = cache.read_dir(&dirs[2]).unwrap(); // evicts b
+        assert_eq!(cache.len(), 2);
+        assert!(Arc::ptr_eq(&a, &cache.read_dir(&dirs[0]).unwrap()), "a should've survived eviction");
+
+        std::fs::write(dirs[0].join("new.txt"), "new").unwrap(); // may not change the dir mtime on coarse filesystems
+        cache.invalidate(&dirs[0]);
+        assert!(cache.read_dir(&dirs[0]).unwrap().by_name("new.txt").is_some());
+
+        let cache = Cache::new(2, Duration::ZERO, Symlinks::Deny);
+        let a = cache.read_dir(&dirs[0]).unwrap();
+        assert!(!Arc::ptr_eq(&a, &cache.read_dir(&dirs[0]).unwrap()), "should've expired");
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[test] fn check_folded() {
+        let dir = std::env::temp_dir().join(format!("mmuhttpd-check-folded-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["Logo.PNG", "readme.md", "README.md"] { std::fs::write(dir.join(name), name).unwrap(); }
+        let snapshot = Cache::new(1, Duration::ZERO, Symlinks::Deny).read_dir(&dir).unwrap();
+        let folded = |name| snapshot.by_folded_name(name).map(|e| e.name_lossy().to_string()).collect::<Vec<_>>();
+        assert_eq!(folded("logo.png"),  ["Logo.PNG"]);
+        if snapshot.entries().count() == 3 { // else case insensitive filesystem
+            assert_eq!(folded("Readme.MD"), ["README.md", "readme.md"]);
+        }
+        assert!(folded("missing").is_empty());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)] #[test] fn check_symlinks() {
+        let tmp = std::env::temp_dir().join(format!("mmuhttpd-check-symlinks-{}", std::process::id()));
+        let (root, outside) = (tmp.join("root"), tmp.join("outside"));
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::create_dir_all(&outside).unwrap();
+        std::fs::write(root.join("sub/inside.txt"), "inside").unwrap();
+        std::fs::write(outside.join("outside.txt"), "outside").unwrap();
+        std::os::unix::fs::symlink(root.join("sub/inside.txt"), root.join("in.txt")).unwrap();
+        std::os::unix::fs::symlink("sub", root.join("in")).unwrap();
+        std::os::unix::fs::symlink(outside.join("outside.txt"), root.join("out.txt")).unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("out")).unwrap();
+        std::os::unix::fs::symlink("missing", root.join("dangling")).unwrap();
+
+        let names = |symlinks| {
+            let snapshot = Cache::new(1, Duration::ZERO, symlinks).read_dir(&root).unwrap();
+            let mut names = snapshot.entries().map(|e| format!("{}{}", e.name_lossy(), if e.is_dir() { "/" } else if e.is_file() { "" } else { "?" })).collect::<Vec<_>>();
+            names.sort();
+            names
+        };
+        assert_eq!(names(Symlinks::Deny),                                               ["sub/"]);
+        assert_eq!(names(Symlinks::Follow),                                             ["in.txt", "in/", "out.txt", "out/", "sub/"]);
+        assert_eq!(names(Symlinks::WithinRoot(vec![std::fs::canonicalize(&root).unwrap()])),  ["in.txt", "in/", "sub/"]);
+
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+}
+
+pub mod file {
+    use crate::compress::Encoding;
+    use std::collections::HashMap;
+    use std::io::{self, Read, Seek};
+    use std::path::*;
+    use std::sync::*;
+    use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+    use std::time::SystemTime;
+
+
+
+    /// The bits of a file's metadata we care about, whether it lives on disk or inside an archive.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)] pub struct Stat {
+        pub len:        u64,
+        pub created:    Option<SystemTime>,
+        pub modified:   Option<SystemTime>,
+    }
+
+    impl From<&std::fs::Metadata> for Stat {
+        fn from(meta: &std::fs::Metadata) -> Self { Self { len: meta.len(), created: meta.created().ok(), modified: meta.modified().ok() } }
+    }
+
+    /// A file opened for reading.
+    pub enum Opened {
+        /// A real file, whose contents start at the given offset (non-zero for e.g. members of a `.tar`.)
+        File(std::fs::File, u64),
+        /// Contents that can only be read front to back (e.g. deflated members of a `.zip`.)
+        Stream(Box<dyn Read>),
+    }
+
+    impl Opened {
+        /// Bytes `range` of the contents.
+        pub fn into_range_reader(self, range: std::ops::Range<u64>) -> io::Result<Box<dyn Read>> {
+            let len = range.end.saturating_sub(range.start);
+            match self {
+                Opened::File(mut file, offset) => {
+                    file.seek(io::SeekFrom::Start(offset + range.start))?;
+                    Ok(Box::new(file.take(len)))
+                },
+                Opened::Stream(mut stream) => {
+                    let skipped = io::copy(&mut (&mut stream).take(range.start), &mut io::sink())?; // XXX: no seeking in e.g. deflated zip members
+                    if skipped < range.start { return Err(io::ErrorKind::UnexpectedEof.into()) }
+                    Ok(Box::new(stream.take(len)))
+                },
+            }
+        }
+    }
+
+    /// An ETag derived from a file's size and mtime - cheap, and changes whenever the contents (probably) do.
+    pub fn etag(stat: &Stat) -> String {
+        let modified = stat.modified.and_then(|m| m.duration_since(SystemTime::UNIX_EPOCH).ok()).unwrap_or_default();
+        format!("\"{:x}-{:x}.{:x}\"", stat.len, modified.as_secs(), modified.subsec_nanos())
+    }
+
+    /// Small files' contents and response headers, keyed by path and revalidated against size + mtime.
+    /// The least recently used files are evicted to stay under a total size budget.
+    pub struct Cache {
+        state:      Mutex<State>,
+        max_bytes:  u64,
+    }
+
+    #[derive(Default)] struct State {
+        by_path:    HashMap<PathBuf, Arc<File>>,
+        bytes:      u64, // contents + compressed variants of everything in by_path
+        tick:       u64,
+    }
+
+    pub struct File {
+        len:                u64,
+        modified:           Option<SystemTime>,
+        pub content:        Vec<u8>,
+        pub content_type:   String,
+        pub sniffed:        bool,
+        pub etag:           String,
+        compressed:         Mutex<HashMap<&'static str, Arc<Vec<u8>>>>, // by Encoding::name()
+        last_used:          AtomicU64,
+    }
+
+    impl Cache {
+        pub const DEFAULT_MAX_BYTES : u64 = 32 * 1024 * 1024;
+
+        /// Files larger than this aren't cached: "small hot assets" only.
+        pub const MAX_FILE_SIZE : u64 = 1024 * 1024;
+
+        pub fn new(max_bytes: u64) -> Self { Self { state: Default::default(), max_bytes } }
+
+        /// Is a file of this size worth caching?
+        pub fn wants(&self, len: u64) -> bool { self.max_bytes > 0 && len <= Self::MAX_FILE_SIZE && len <= self.max_bytes / 2 }
+
+        /// The cached file at `path`, if we have one matching `stat`.
+        pub fn get(&self, path: &Path, stat: &Stat) -> Option<Arc<File>> {
+            let mut state = self.state.lock().expect("bug: Mutex poisoned");
+            state.tick += 1;
+            let tick = state.tick;
+            let file = state.by_path.get(path)?;
+            if file.len != stat.len || file.modified != stat.modified {
+                let stale = state.by_path.remove(path)?;
+                state.bytes -= stale.size();
+                return None;
+            }
+            file.last_used.store(tick, Relaxed);
+            Some(Arc::clone(file))
+        }
+
+        pub fn insert(&self, path: &Path, stat: &Stat, etag: String, content: Vec<u8>, content_type: String, sniffed: bool) -> Arc<File> {
+            let file = Arc::new(File {
+                len:            stat.len,
+                modified:       stat.modified,
+                content,
+                content_type,
+                sniffed,
+                etag,
+                compressed:     Default::default(),
+                last_used:      AtomicU64::new(0),
+            });
+            if !self.wants(file.len) { return file }
+
+            let mut state = self.state.lock().expect("bug: Mutex poisoned");
+            state.tick += 1;
+            file.last_used.store(state.tick, Relaxed);
+            if let Some(prev) = state.by_path.insert(path.into(), Arc::clone(&file)) { state.bytes -= prev.size(); }
+            state.bytes += file.size();
+            self.evict(&mut state);
+            file
+        }
+
+        /// `file`'s content compressed with `encoding`, compressing (and caching) it if this is the first request for it.
+        pub fn compressed(&self, file: &Arc<File>, encoding: Encoding) -> std::io::Result<Arc<Vec<u8>>> {
+            if let Some(c) = file.compressed.lock().expect("bug: Mutex poisoned").get(encoding.name()) { return Ok(Arc::clone(c)) }
+
+            let mut e = crate::compress::Encoder::new(Vec::new(), encoding)?;
+            std::io::Write::write_all(&mut e, &file.content)?;
+            let compressed = Arc::new(e.finish()?);
+
+            let mut state = self.state.lock().expect("bug: Mutex poisoned");
+            let mut variants = file.compressed.lock().expect("bug: Mutex poisoned");
+            if variants.insert(encoding.name(), Arc::clone(&compressed)).is_none() {
+                // only count it if `file` is still in the cache (otherwise it'll never be subtracted back out)
+                if state.by_path.values().any(|f| Arc::ptr_eq(f, file)) { state.bytes += compressed.len() as u64; }
+            }
+            drop(variants);
+            self.evict(&mut state);
+            Ok(compressed)
+        }
+
+        fn evict(&self, state: &mut State) {
+            while state.bytes > self.max_bytes {
+                let Some(lru) = state.by_path.iter().min_by_key(|(_, f)| f.last_used.load(Relaxed)).map(|(p, _)| p.clone()) else { break };
+                let Some(file) = state.by_path.remove(&lru) else { break };
+                state.bytes -= file.size();
+            }
+        }
+    }
+
+    impl File {
+        fn size(&self) -> u64 {
+            self.content.len() as u64 + self.compressed.lock().expect("bug: Mutex poisoned").values().map(|c| c.len() as u64).sum::<u64>()
+        }
+    }
+
+
+
+    #[test] fn check_cache() {
+        let root = std::env::temp_dir().join(format!("mmuhttpd-check-file-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let paths = ["a.txt", "b.txt", "c.txt"].map(|f| root.join(f));
+        for path in paths.iter() { std::fs::write(path, [b'x'; 100]).unwrap(); }
+        let meta = |i: usize| Stat::from(&std::fs::metadata(&paths[i]).unwrap());
+        let insert = |cache: &Cache, i: usize| cache.insert(&paths[i], &meta(i), etag(&meta(i)), std::fs::read(&paths[i]).unwrap(), "text/plain".into(), false);
+
+        let cache = Cache::new(1000);
+        assert!(cache.get(&paths[0], &meta(0)).is_none());
+        let a = insert(&cache, 0);
+        assert!(Arc::ptr_eq(&a, &cache.get(&paths[0], &meta(0)).unwrap()));
+        assert_eq!(a.etag, etag(&meta(0)));
+
+        let cache = Cache::new(250); // room for 2 files
+        let _ = insert(&cache, 0);
+        let _ = insert(&cache, 1);
+        let _ = cache.get(&paths[0], &meta(0)).unwrap(); // a is now more recently used than b
+        let _ = insert(&cache, 2); // evicts b
+        assert!(cache.get(&paths[0], &meta(0)).is_some());
+        assert!(cache.get(&paths[1], &meta(1)).is_none());
+        assert!(cache.get(&paths[2], &meta(2)).is_some());
+
+        std::fs::write(&paths[0], [b'y'; 50]).unwrap();
+        assert!(cache.get(&paths[0], &meta(0)).is_none(), "size changed: should be stale");
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
 }
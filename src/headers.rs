@@ -0,0 +1,189 @@
+//! `_headers` / `--header`: extra response headers by URL path, in the style of Netlify's `_headers` files:
+//!
+//! ```text
+//! # comments
+//! /*
+//!   Cross-Origin-Opener-Policy: same-origin
+//!   Cross-Origin-Embedder-Policy: require-corp
+//! /assets/*.js
+//!   Cache-Control: public, max-age=31536000
+//! ```
+//!
+//! Patterns are matched against the whole (decoded) URL path.  Within a segment, `*` matches any run of characters and
+//! `?` any one.  A segment that's just `:name` matches any one segment, and a final `*` segment matches everything
+//! below (including nothing at all: `/blog/*` matches `/blog` too.)  When several rules set the same header, the last
+//! one wins - and `--header`s come after the root's `_headers` file.
+
+use crate::response::Response;
+
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::path::Path;
+
+
+
+/// Read from the webroot at startup, if it exists.  Never served.
+pub const FILE : &str = "_headers";
+
+#[derive(Default)] pub struct Headers {
+    rules: Vec<Rule>, // last match wins
+}
+
+struct Rule {
+    segments:   Vec<String>,
+    name:       String,
+    value:      String,
+}
+
+impl Headers {
+    /// Send `name: value` with every response to a path matching `pattern`.
+    pub fn insert(&mut self, pattern: &str, name: &str, value: &str) -> Result<(), String> {
+        let Some(trimmed) = pattern.trim().strip_prefix('/') else { return Err(format!("pattern {pattern:?} must start with `/`")) };
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() || !name.bytes().all(is_token) { return Err(format!("invalid header name {name:?}")) }
+        if ["content-length", "transfer-encoding", "connection", "keep-alive", "upgrade"].iter().any(|h| name.eq_ignore_ascii_case(h)) {
+            return Err(format!("{name} is up to the server, not `_headers`"))
+        }
+        if value.bytes().any(|b| b.is_ascii_control() && b != b'\t') { return Err(format!("invalid header value {value:?}")) }
+        self.rules.push(Rule { segments: trimmed.split('/').map(String::from).collect(), name: name.into(), value: value.into() });
+        Ok(())
+    }
+
+    /// Send COOP/COEP/CORP with everything, so pages can use `SharedArrayBuffer` (`--cross-origin-isolated`.)  `coep`
+    /// is the `Cross-Origin-Embedder-Policy`: `require-corp` or `credentialless`.
+    pub fn isolate(&mut self, coep: &str) {
+        self.insert("/*", "Cross-Origin-Opener-Policy",     "same-origin").expect("bug: invalid built-in header");
+        self.insert("/*", "Cross-Origin-Embedder-Policy",   coep).expect("bug: invalid built-in header");
+        self.insert("/*", "Cross-Origin-Resource-Policy",   "same-origin").expect("bug: invalid built-in header");
+    }
+
+    /// Load a [`FILE`].
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        self.parse(path, &text)
+    }
+
+    /// Parse the contents of a [`FILE`] found at `path`: unindented patterns, each followed by indented headers.
+    pub fn parse(&mut self, path: &Path, text: &str) -> io::Result<()> {
+        let mut pattern = None;
+        for (line_no, line) in text.lines().enumerate() {
+            let error = |err: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {err}", path.display(), line_no + 1));
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') { continue }
+            if !line.starts_with([' ', '\t']) { pattern = Some(trimmed); continue }
+            let Some(pattern) = pattern else { return Err(error(format!("header {trimmed:?} before any path"))) };
+            let Some((name, value)) = trimmed.split_once(':') else { return Err(error(format!("expected `Name: value`, but got {trimmed:?}"))) };
+            self.insert(pattern, name, value).map_err(error)?;
+        }
+        Ok(())
+    }
+
+    /// The headers for requests of the URL `path`.
+    pub fn matching(&self, path: &str) -> Vec<(&str, &str)> {
+        let segments = path.trim_start_matches('/').split('/').map(|s| match crate::percent::decode_segment(s) {
+            Some(decoded)   => Cow::Owned(decoded.to_string_lossy().into_owned()),
+            None            => Cow::Borrowed(s), // "", ".", "..", ...
+        }).collect::<Vec<_>>();
+        let segments = segments.iter().map(|s| &**s).collect::<Vec<_>>();
+
+        self.collect(self.rules.iter().filter(|r| matches(&r.segments, &segments)))
+    }
+
+    /// The headers for every path (`/*`), for responses to requests too malformed to have a path.
+    pub fn everywhere(&self) -> Vec<(&str, &str)> {
+        self.collect(self.rules.iter().filter(|r| r.segments == ["*"]))
+    }
+
+    fn collect<'h>(&'h self, rules: impl Iterator<Item = &'h Rule>) -> Vec<(&'h str, &'h str)> {
+        let mut headers = Vec::<(&str, &str)>::new();
+        for rule in rules {
+            headers.retain(|(name, _)| !name.eq_ignore_ascii_case(&rule.name));
+            headers.push((&rule.name, &rule.value));
+        }
+        headers
+    }
+}
+
+fn matches(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None                                            => path.is_empty(),
+        Some((p, rest)) if p == "*" && rest.is_empty()  => true,
+        Some((p, rest)) if p.starts_with(':')           => path.split_first().map_or(false, |(s, path)| !s.is_empty() && matches(rest, path)),
+        Some((p, rest))                                 => path.split_first().map_or(false, |(s, path)| crate::rules::matches_segment(p.as_bytes(), s.as_bytes()) && matches(rest, path)),
+    }
+}
+
+fn is_token(b: u8) -> bool { b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b) }
+
+/// Adds `extra` headers to everything sent through `inner`, replacing any of the same name (except `Vary`, which is
+/// combined: e.g. `Accept-Encoding` from compression + `Origin` from `--cors`.)
+pub struct WithHeaders<'r> {
+    pub inner: &'r mut dyn Response,
+    pub extra: Vec<(&'r str, &'r str)>,
+}
+
+impl Write for WithHeaders<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.inner.write(buf) }
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> { self.inner.write_all(buf) }
+    fn flush(&mut self) -> io::Result<()> { self.inner.flush() }
+}
+
+impl Response for WithHeaders<'_> {
+    fn head(&mut self, status: u16, headers: &[(&str, &str)], len: Option<u64>) -> io::Result<()> {
+        let is_vary = |name: &str| name.eq_ignore_ascii_case("Vary");
+        let vary = headers.iter().chain(self.extra.iter()).filter(|(name, _)| is_vary(name)).map(|(_, value)| *value).collect::<Vec<_>>().join(", ");
+        let mut merged = headers.iter().copied().filter(|(name, _)| !is_vary(name) && !self.extra.iter().any(|(e, _)| e.eq_ignore_ascii_case(name))).collect::<Vec<_>>();
+        merged.extend(self.extra.iter().copied().filter(|(name, _)| !is_vary(name)));
+        if !vary.is_empty() { merged.push(("Vary", &vary)); }
+        self.inner.head(status, &merged, len)
+    }
+
+    fn send_file(&mut self, file: &std::fs::File, offset: u64, len: u64) -> io::Result<()> { self.inner.send_file(file, offset, len) }
+    fn finish(&mut self) -> io::Result<()> { self.inner.finish() }
+}
+
+
+
+#[test] fn check_headers() {
+    let mut headers = Headers::default();
+    headers.parse(Path::new("_headers"), "
+# comment
+/*
+  Cross-Origin-Opener-Policy: same-origin
+  Cache-Control: no-cache
+
+/assets/*.js
+  Cache-Control: public, max-age=31536000
+/blog/:post/
+  X-Post: yes
+/blog/*
+  X-Blog: yes
+").unwrap();
+    assert_eq!(headers.matching("/"), [("Cross-Origin-Opener-Policy", "same-origin"), ("Cache-Control", "no-cache")]);
+    assert_eq!(headers.matching("/assets/app.js"), [("Cross-Origin-Opener-Policy", "same-origin"), ("Cache-Control", "public, max-age=31536000")]);
+    assert_eq!(headers.matching("/assets/sub/app.js").len(), 2, "`*` doesn't cross `/`");
+    assert_eq!(headers.matching("/blog/hello%20world/").len(), 4);
+    assert_eq!(headers.matching("/blog//").len(), 3, "`:post` needs a name");
+    assert_eq!(headers.matching("/blog").len(), 3);
+    assert_eq!(headers.everywhere(), [("Cross-Origin-Opener-Policy", "same-origin"), ("Cache-Control", "no-cache")]);
+
+    assert!(headers.insert("*", "X-A", "b").is_err());
+    assert!(headers.insert("/*", "X A", "b").is_err());
+    assert!(headers.insert("/*", "X-A", "b\r\nX-Injected: c").is_err());
+    assert!(headers.insert("/*", "Content-Length", "0").is_err());
+    assert!(headers.parse(Path::new("_headers"), "/*\n  no colon\n").is_err());
+}
+
+#[test] fn check_served_headers() {
+    use crate::serve::{memory_settings, request};
+    let mut settings = memory_settings(&[("index.html", b"<p>hi</p>"), ("sub/a.txt", b"abcdef"), (FILE, b"/*\n  X-Hidden: yes\n")]);
+    settings.headers.insert("/sub/*", "Cache-Control", "no-store").unwrap();
+
+    let res = request(&settings, "GET", "/sub/a.txt", &[], b"");
+    assert_eq!((res.status, res.header("Cache-Control")), (200, Some("no-store")));
+    let res = request(&settings, "GET", "/sub/missing.txt", &[], b"");
+    assert_eq!((res.status, res.header("Cache-Control")), (404, Some("no-store")), "errors get headers too");
+    let res = request(&settings, "GET", "/", &[], b"");
+    assert_eq!((res.status, res.header("Cache-Control")), (200, None));
+    assert_eq!(request(&settings, "GET", "/_headers", &[], b"").status, 404, "never served");
+}
@@ -0,0 +1,445 @@
+//! [RFC 7541](https://www.rfc-editor.org/rfc/rfc7541) HPACK header compression for HTTP/2.
+
+use std::collections::VecDeque;
+
+
+
+/// The most we'll let peers grow our decoder's dynamic table (we never advertise anything else.)
+pub const DEFAULT_TABLE_SIZE : usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)] pub enum Error {
+    /// Malformed or truncated header block, bad table index, bad huffman coding, etc.
+    Compression,
+
+    /// Header list exceeded the caller's size limit.  The block was still fully decoded (so the dynamic table is intact.)
+    TooLarge,
+}
+
+const STATIC_TABLE : [(&str, &str); 61] = [
+    (":authority",                  ""),
+    (":method",                     "GET"),
+    (":method",                     "POST"),
+    (":path",                       "/"),
+    (":path",                       "/index.html"),
+    (":scheme",                     "http"),
+    (":scheme",                     "https"),
+    (":status",                     "200"),
+    (":status",                     "204"),
+    (":status",                     "206"),
+    (":status",                     "304"),
+    (":status",                     "400"),
+    (":status",                     "404"),
+    (":status",                     "500"),
+    ("accept-charset",              ""),
+    ("accept-encoding",             "gzip, deflate"),
+    ("accept-language",             ""),
+    ("accept-ranges",               ""),
+    ("accept",                      ""),
+    ("access-control-allow-origin", ""),
+    ("age",                         ""),
+    ("allow",                       ""),
+    ("authorization",               ""),
+    ("cache-control",               ""),
+    ("content-disposition",         ""),
+    ("content-encoding",            ""),
+    ("content-language",            ""),
+    ("content-length",              ""),
+    ("content-location",            ""),
+    ("content-range",               ""),
+    ("content-type",                ""),
+    ("cookie",                      ""),
+    ("date",                        ""),
+    ("etag",                        ""),
+    ("expect",                      ""),
+    ("expires",                     ""),
+    ("from",                        ""),
+    ("host",                        ""),
+    ("if-match",                    ""),
+    ("if-modified-since",           ""),
+    ("if-none-match",               ""),
+    ("if-range",                    ""),
+    ("if-unmodified-since",         ""),
+    ("last-modified",               ""),
+    ("link",                        ""),
+    ("location",                    ""),
+    ("max-forwards",                ""),
+    ("proxy-authenticate",          ""),
+    ("proxy-authorization",         ""),
+    ("range",                       ""),
+    ("referer",                     ""),
+    ("refresh",                     ""),
+    ("retry-after",                 ""),
+    ("server",                      ""),
+    ("set-cookie",                  ""),
+    ("strict-transport-security",   ""),
+    ("transfer-encoding",           ""),
+    ("user-agent",                  ""),
+    ("vary",                        ""),
+    ("via",                         ""),
+    ("www-authenticate",            ""),
+];
+
+
+
+struct Table {
+    entries:    VecDeque<(String, String)>, // newest first
+    size:       usize,
+    max:        usize,
+}
+
+impl Table {
+    fn new(max: usize) -> Self { Self { entries: VecDeque::new(), size: 0, max } }
+
+    fn entry_size(name: &str, value: &str) -> usize { name.len() + value.len() + 32 }
+
+    fn get(&self, index: usize) -> Option<(&str, &str)> {
+        match index {
+            0           => None,
+            1 ..= 61    => Some(STATIC_TABLE[index - 1]),
+            _           => self.entries.get(index - 62).map(|(n, v)| (n.as_str(), v.as_str())),
+        }
+    }
+
+    fn insert(&mut self, name: String, value: String) {
+        let size = Self::entry_size(&name, &value);
+        self.evict_to(self.max.saturating_sub(size));
+        if size <= self.max {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    fn resize(&mut self, max: usize) {
+        self.max = max;
+        self.evict_to(max);
+    }
+
+    fn evict_to(&mut self, max: usize) {
+        while self.size > max {
+            let Some((n, v)) = self.entries.pop_back() else { break };
+            self.size -= Self::entry_size(&n, &v);
+        }
+    }
+
+    /// (index of exact name+value match, index of name match)
+    fn find(&self, name: &str, value: &str) -> (Option<usize>, Option<usize>) {
+        let mut name_match = None;
+        let all = STATIC_TABLE.iter().map(|&(n, v)| (n, v)).chain(self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str())));
+        for (i, (n, v)) in all.enumerate() {
+            if n != name { continue }
+            if v == value { return (Some(i + 1), Some(i + 1)) }
+            if name_match.is_none() { name_match = Some(i + 1) }
+        }
+        (None, name_match)
+    }
+}
+
+
+
+pub struct Decoder {
+    table:  Table,
+    limit:  usize, // our advertised SETTINGS_HEADER_TABLE_SIZE
+}
+
+impl Default for Decoder {
+    fn default() -> Self { Self::new() }
+}
+
+impl Decoder {
+    pub fn new() -> Self { Self { table: Table::new(DEFAULT_TABLE_SIZE), limit: DEFAULT_TABLE_SIZE } }
+
+    /// Decode a complete header block.  `max_list_size` is measured per
+    /// [SETTINGS_MAX_HEADER_LIST_SIZE](https://www.rfc-editor.org/rfc/rfc9113#section-6.5.2) (name + value + 32 per field.)
+    pub fn decode(&mut self, mut block: &[u8], max_list_size: usize) -> Result<Vec<(String, String)>, Error> {
+        let mut headers = Vec::new();
+        let mut list_size = 0;
+        let mut too_large = false;
+        while let Some(&first) = block.first() {
+            let (name, value) = if first & 0x80 != 0 { // indexed field
+                let index = integer(&mut block, 7)?;
+                let (n, v) = self.table.get(index).ok_or(Error::Compression)?;
+                (n.to_string(), v.to_string())
+            } else if first & 0xC0 == 0x40 { // literal with incremental indexing
+                let (n, v) = self.literal(&mut block, 6)?;
+                self.table.insert(n.clone(), v.clone());
+                (n, v)
+            } else if first & 0xE0 == 0x20 { // dynamic table size update
+                if !headers.is_empty() { return Err(Error::Compression) }
+                let max = integer(&mut block, 5)?;
+                if max > self.limit { return Err(Error::Compression) }
+                self.table.resize(max);
+                continue;
+            } else { // literal without indexing (0000) / never indexed (0001)
+                self.literal(&mut block, 4)?
+            };
+
+            list_size += Table::entry_size(&name, &value);
+            if list_size > max_list_size { too_large = true; headers.clear(); }
+            if !too_large { headers.push((name, value)); }
+        }
+        if too_large { Err(Error::TooLarge) } else { Ok(headers) }
+    }
+
+    fn literal(&self, block: &mut &[u8], prefix: u8) -> Result<(String, String), Error> {
+        let index = integer(block, prefix)?;
+        let name = if index == 0 { string(block)? } else { self.table.get(index).ok_or(Error::Compression)?.0.to_string() };
+        let value = string(block)?;
+        Ok((name, value))
+    }
+}
+
+fn integer(block: &mut &[u8], prefix: u8) -> Result<usize, Error> {
+    let mask = (1u16 << prefix) as u8 - 1;
+    let (&first, rest) = block.split_first().ok_or(Error::Compression)?;
+    *block = rest;
+    let mut value = usize::from(first & mask);
+    if value < usize::from(mask) { return Ok(value) }
+    let mut shift = 0;
+    loop {
+        let (&b, rest) = block.split_first().ok_or(Error::Compression)?;
+        *block = rest;
+        if shift > 28 { return Err(Error::Compression) }
+        value += usize::from(b & 0x7F) << shift;
+        shift += 7;
+        if b & 0x80 == 0 { return Ok(value) }
+    }
+}
+
+fn string(block: &mut &[u8]) -> Result<String, Error> {
+    let huffman = block.first().ok_or(Error::Compression)? & 0x80 != 0;
+    let len = integer(block, 7)?;
+    if len > block.len() { return Err(Error::Compression) }
+    let (raw, rest) = block.split_at(len);
+    *block = rest;
+    let bytes = if huffman { huffman::decode(raw)? } else { raw.to_vec() };
+    Ok(String::from_utf8(bytes).unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
+}
+
+
+
+pub struct Encoder {
+    table:          Table,
+    size_update:    Option<usize>,
+}
+
+impl Default for Encoder {
+    fn default() -> Self { Self::new() }
+}
+
+impl Encoder {
+    pub fn new() -> Self { Self { table: Table::new(DEFAULT_TABLE_SIZE), size_update: None } }
+
+    /// The peer changed SETTINGS_HEADER_TABLE_SIZE: we'll use up to that much (but no more than our default.)
+    pub fn set_max_table_size(&mut self, max: usize) {
+        let max = max.min(DEFAULT_TABLE_SIZE);
+        if max != self.table.max {
+            self.table.resize(max);
+            self.size_update = Some(max);
+        }
+    }
+
+    pub fn encode<'h>(&mut self, headers: impl IntoIterator<Item = (&'h str, &'h str)>, out: &mut Vec<u8>) {
+        if let Some(max) = self.size_update.take() { put_integer(out, 0x20, 5, max); }
+        for (name, value) in headers {
+            match self.table.find(name, value) {
+                (Some(index), _) => put_integer(out, 0x80, 7, index),
+                (None, name_index) => {
+                    // values that are unique per response would only churn the dynamic table
+                    let index = !matches!(name, "content-length" | "content-range" | "etag" | "last-modified" | "date" | "location");
+                    if index { put_integer(out, 0x40, 6, name_index.unwrap_or(0)) } else { put_integer(out, 0x00, 4, name_index.unwrap_or(0)) }
+                    if name_index.is_none() { put_string(out, name); }
+                    put_string(out, value);
+                    if index { self.table.insert(name.into(), value.into()); }
+                },
+            }
+        }
+    }
+}
+
+fn put_integer(out: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let mask = (1u16 << prefix) as u8 - 1;
+    if value < usize::from(mask) { return out.push(flags | value as u8) }
+    out.push(flags | mask);
+    value -= usize::from(mask);
+    while value >= 0x80 {
+        out.push(0x80 | value as u8);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn put_string(out: &mut Vec<u8>, s: &str) {
+    let huffman_len = huffman::encoded_len(s.as_bytes());
+    if huffman_len < s.len() {
+        put_integer(out, 0x80, 7, huffman_len);
+        huffman::encode(s.as_bytes(), out);
+    } else {
+        put_integer(out, 0x00, 7, s.len());
+        out.extend_from_slice(s.as_bytes());
+    }
+}
+
+
+
+/// The [canonical huffman code](https://www.rfc-editor.org/rfc/rfc7541#appendix-B) of HPACK.
+mod huffman {
+    use super::Error;
+
+    /// Code length (in bits) of each symbol (256 = EOS.)  Codes are assigned canonically (shorter codes first, ties
+    /// broken by symbol value), which reproduces the table in RFC 7541 Appendix B.
+    const LENGTHS : [u8; 257] = [
+        13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+        28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+         6, 10, 10, 12, 13,  6,  8, 11, 10, 10,  8, 11,  8,  6,  6,  6,
+         5,  5,  5,  6,  6,  6,  6,  6,  6,  6,  7,  8, 15,  6, 12, 10,
+        13,  6,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,
+         7,  7,  7,  7,  7,  7,  7,  7,  8,  7,  8, 13, 19, 13, 14,  6,
+        15,  5,  6,  5,  6,  5,  6,  6,  6,  5,  7,  7,  6,  6,  6,  5,
+         6,  7,  6,  5,  5,  6,  7,  7,  7,  7,  7, 15, 11, 14, 13, 28,
+        20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+        24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+        22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+        21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+        26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+        19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+        20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+        26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+        30,
+    ];
+
+    const MAX_LEN : usize = 30;
+    const EOS : u16 = 256;
+
+    struct Canonical {
+        codes:      [u32; 257],             // per symbol
+        sorted:     [u16; 257],             // symbols, by (length, symbol)
+        first:      [u32; MAX_LEN + 1],     // first code of each length
+        offset:     [u16; MAX_LEN + 1],     // index into `sorted` of the first symbol of each length
+        count:      [u16; MAX_LEN + 1],     // number of codes of each length
+    }
+
+    const CANONICAL : Canonical = {
+        let mut c = Canonical { codes: [0; 257], sorted: [0; 257], first: [0; MAX_LEN + 1], offset: [0; MAX_LEN + 1], count: [0; MAX_LEN + 1] };
+        let mut code = 0u32;
+        let mut n = 0;
+        let mut len = 1;
+        while len <= MAX_LEN {
+            c.first[len] = code;
+            c.offset[len] = n as u16;
+            let mut sym = 0;
+            while sym < 257 {
+                if LENGTHS[sym] as usize == len {
+                    c.codes[sym] = code;
+                    c.sorted[n] = sym as u16;
+                    c.count[len] += 1;
+                    code += 1;
+                    n += 1;
+                }
+                sym += 1;
+            }
+            code <<= 1;
+            len += 1;
+        }
+        c
+    };
+
+    pub fn encoded_len(raw: &[u8]) -> usize {
+        let bits = raw.iter().map(|&b| usize::from(LENGTHS[usize::from(b)])).sum::<usize>();
+        (bits + 7) / 8
+    }
+
+    pub fn encode(raw: &[u8], out: &mut Vec<u8>) {
+        let mut bits = 0u64;
+        let mut nbits = 0u32;
+        for &b in raw {
+            let len = u32::from(LENGTHS[usize::from(b)]);
+            bits = (bits << len) | u64::from(CANONICAL.codes[usize::from(b)]);
+            nbits += len;
+            while nbits >= 8 {
+                nbits -= 8;
+                out.push((bits >> nbits) as u8);
+            }
+        }
+        if nbits > 0 { out.push(((bits << (8 - nbits)) as u8) | (0xFF >> nbits)); } // pad with the MSBs of EOS (all 1s)
+    }
+
+    pub fn decode(encoded: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::with_capacity(encoded.len() * 8 / 5);
+        let mut code = 0u32;
+        let mut len = 0usize;
+        for &byte in encoded {
+            for bit in (0 .. 8).rev() {
+                code = (code << 1) | u32::from(byte >> bit & 1);
+                len += 1;
+                let index = code.wrapping_sub(CANONICAL.first[len]);
+                if index < u32::from(CANONICAL.count[len]) {
+                    let sym = CANONICAL.sorted[usize::from(CANONICAL.offset[len]) + index as usize];
+                    if sym == EOS { return Err(Error::Compression) }
+                    out.push(sym as u8);
+                    code = 0;
+                    len = 0;
+                } else if len >= MAX_LEN {
+                    return Err(Error::Compression);
+                }
+            }
+        }
+        // padding must be fewer than 8 bits, and all 1s (a prefix of EOS)
+        if len > 7 || code != (1 << len) - 1 { return Err(Error::Compression) }
+        Ok(out)
+    }
+}
+
+
+
+#[cfg(test)] fn hex(s: &str) -> Vec<u8> {
+    let s = s.replace(' ', "");
+    (0 .. s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i+2], 16).unwrap()).collect()
+}
+
+#[test] fn check_huffman() {
+    for (raw, encoded) in [
+        ("www.example.com",                 "f1e3 c2e5 f23a 6ba0 ab90 f4ff"),
+        ("no-cache",                        "a8eb 1064 9cbf"),
+        ("custom-value",                    "25a8 49e9 5bb8 e8b4 bf"),
+        ("Mon, 21 Oct 2013 20:13:21 GMT",   "d07a be94 1054 d444 a820 0595 040b 8166 e082 a62d 1bff"),
+    ] {
+        let mut out = Vec::new();
+        huffman::encode(raw.as_bytes(), &mut out);
+        assert_eq!(out, hex(encoded), "{raw}");
+        assert_eq!(huffman::decode(&out).unwrap(), raw.as_bytes());
+    }
+    let all = (0 ..= 255).collect::<Vec<u8>>();
+    let mut out = Vec::new();
+    huffman::encode(&all, &mut out);
+    assert_eq!(huffman::decode(&out).unwrap(), all);
+}
+
+#[test] fn check_decoder() {
+    // https://www.rfc-editor.org/rfc/rfc7541#appendix-C.4 (requests with huffman coding, sharing a dynamic table)
+    let mut d = Decoder::new();
+    let h = |v: &[(&str, &str)]| v.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect::<Vec<_>>();
+    assert_eq!(d.decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"), 4096).unwrap(), h(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")]));
+    assert_eq!(d.decode(&hex("8286 84be 5886 a8eb 1064 9cbf"), 4096).unwrap(), h(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"), ("cache-control", "no-cache")]));
+    assert_eq!(d.decode(&hex("8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"), 4096).unwrap(), h(&[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"), ("custom-key", "custom-value")]));
+    assert_eq!(d.table.size, 164);
+
+    assert_eq!(Decoder::new().decode(&hex("8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff"), 64), Err(Error::TooLarge));
+    assert_eq!(Decoder::new().decode(&hex("be"), 4096), Err(Error::Compression)); // empty dynamic table
+}
+
+#[test] fn check_encoder_roundtrip() {
+    let mut e = Encoder::new();
+    let mut d = Decoder::new();
+    for _ in 0 .. 3 {
+        let headers = [(":status", "200"), ("content-type", "text/html; charset=utf-8"), ("content-length", "1234"), ("x-custom", "\u{1F412}")];
+        let mut block = Vec::new();
+        e.encode(headers.iter().copied(), &mut block);
+        let decoded = d.decode(&block, 4096).unwrap();
+        assert_eq!(decoded, headers.iter().map(|&(n, v)| (n.to_string(), v.to_string())).collect::<Vec<_>>());
+    }
+    e.set_max_table_size(0);
+    let mut block = Vec::new();
+    e.encode([("x-custom", "value")], &mut block);
+    assert_eq!(block[0], 0x20); // size update
+    assert_eq!(d.decode(&block, 4096).unwrap(), [("x-custom".to_string(), "value".to_string())]);
+}
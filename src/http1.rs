@@ -0,0 +1,298 @@
+//! HTTP/1.0 and HTTP/1.1 (one request per connection - no keep-alive.)
+
+use crate::*;
+use crate::request::*;
+use crate::response::reason;
+
+use std::io::{self, BufReader, Read, Write};
+use std::net::{TcpStream, Shutdown};
+
+
+
+pub fn on_connection(settings: &'static Settings, mut stream: TcpStream) {
+    let mut header = [0u8; 8 * 1024]; // common limit per https://stackoverflow.com/a/60623751/953531
+    let (header, body_prefix) = match read_header(settings, &mut stream, &mut header[..]) { Err(()) => return, Ok(h) => h };
+    macro_rules! early { ($error:ident) => { return early(settings, &stream, response::$error) } }
+
+    let (request, headers) = header.split_once(b"\r\n").unwrap_or((header, b""));
+    let Some((method, after_method)) = request.split_once(b" ") else { early!(bad_request) };
+    let Some((path_search, version)) = after_method.split_once(b" ") else { early!(bad_request) };
+    if method == b"PRI" && path_search == b"*" && version == b"HTTP/2.0" {
+        return http2::serve(settings, stream, body_prefix, http2::Start::PriorKnowledge);
+    }
+    let version = match version {
+        b"HTTP/1.0" => Version::Http10,
+        b"HTTP/1.1" => Version::Http11,
+        _           => early!(http_version_not_supported),
+    };
+    let (path, search) = path_search.split_at(path_search.find_window(b"?").unwrap_or(path_search.len()));
+    debug_assert!(search.is_empty() || search.starts_with(b"?"));
+    let Ok(headers) = core::str::from_utf8(headers) else { early!(bad_request) };
+    let path = match core::str::from_utf8(path) {
+        Ok(path)    => std::borrow::Cow::Borrowed(path),
+        Err(_)      => std::borrow::Cow::Owned(percent::encode_non_ascii(path)), // raw legacy (e.g. Latin-1) file names
+    };
+    let Ok(search) = core::str::from_utf8(search) else { early!(bad_request) };
+    let headers = headers.split('\n').map(|h| h.trim_end()).filter_map(|h| h.split_once(':')).map(|(k, v)| (k, v.trim())).collect();
+    let req = Request { method, path: &path, search, version, headers };
+    //dbg!((String::from_utf8_lossy(method), path, &req.headers));
+
+    if let Some(upgrade) = h2c_upgrade(&req) {
+        if stream.write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n").is_err() { return }
+        return http2::serve(settings, stream, body_prefix, upgrade);
+    }
+
+    let mut body = Body {
+        prefix:             body_prefix,
+        stream:             &stream,
+        content_length:     req.header("Content-Length"),
+        transfer_encoding:  req.header("Transfer-Encoding"),
+        expect_continue:    version == Version::Http11 && req.header("Expect").map_or(false, |e| e.eq_ignore_ascii_case("100-continue")),
+    };
+    let mut res = Response::new(&stream, version);
+    res.head_only = method == b"HEAD";
+    serve::serve(settings, &req, &mut body, &mut res);
+}
+
+/// Should we switch to HTTP/2 per [RFC 7540 § 3.2](https://www.rfc-editor.org/rfc/rfc7540#section-3.2)?
+/// We only bother for requests without bodies, which is what clients actually send.
+fn h2c_upgrade(req: &Request) -> Option<http2::Start> {
+    if req.version != Version::Http11 { return None }
+    if !req.header("Upgrade")?.split(',').any(|p| p.trim().eq_ignore_ascii_case("h2c")) { return None }
+    if !req.header("Connection")?.split(',').any(|p| p.trim().eq_ignore_ascii_case("HTTP2-Settings")) { return None }
+    if req.header("Transfer-Encoding").is_some() || req.header("Content-Length").map_or(false, |l| l != "0") { return None }
+    let settings = http2::decode_base64url(req.header("HTTP2-Settings")?)?;
+
+    let hop_by_hop = ["Connection", "Upgrade", "HTTP2-Settings", "Keep-Alive", "Proxy-Connection", "Transfer-Encoding"];
+    let request = http2::OwnedRequest {
+        method:     req.method.into(),
+        path:       req.path.into(),
+        search:     req.search.into(),
+        headers:    req.headers.iter().filter(|(k, _)| !hop_by_hop.iter().any(|h| h.eq_ignore_ascii_case(k))).map(|&(k, v)| (k.to_ascii_lowercase(), v.into())).collect(),
+    };
+    Some(http2::Start::Upgrade { request, settings })
+}
+
+/// Reads the request line + headers (up to but excluding the terminating crlfcrlf.)  Also returns anything that was read
+/// past the crlfcrlf, which is the start of the request body.
+fn read_header<'h>(settings: &Settings, stream: &mut TcpStream, header: &'h mut [u8]) -> Result<(&'h [u8], &'h [u8]), ()> {
+    debug_assert!(header.len() > 4);
+    let mut len = 0;
+    while len < header.len() {
+        match stream.read(&mut header[len..]) {
+            Err(_io)    => { early(settings, stream, response::request_too_large); return Err(()) },
+            Ok(0)       => { early(settings, stream, response::bad_request); return Err(()) },
+            Ok(read) => {
+                let crlfcrlf = b"\r\n\r\n"; // marks end of HTTP request headers
+                let crlfcrlf_search_start = len.saturating_sub(crlfcrlf.len()-1);
+                len += read;
+                for (offset, window) in header[crlfcrlf_search_start .. len].windows(crlfcrlf.len()).enumerate() {
+                    if window == crlfcrlf {
+                        let end = crlfcrlf_search_start + offset;
+                        let (header, body) = header[..len].split_at(end);
+                        return Ok((header, &body[crlfcrlf.len()..]));
+                    }
+                }
+            },
+        }
+    }
+    early(settings, stream, response::request_header_fields_too_large);
+    Err(())
+}
+
+/// Reject a request we couldn't make sense of.  Still sends the headers meant for every path (e.g.
+/// `--cross-origin-isolated`), as [`serve::serve`] would have.
+fn early(settings: &Settings, stream: &TcpStream, respond: fn(&mut dyn response::Response)) {
+    respond(&mut headers::WithHeaders { inner: &mut Response::new(stream, Version::Http10), extra: settings.headers.everywhere() })
+}
+
+
+
+struct Body<'s> {
+    prefix:             &'s [u8],
+    stream:             &'s TcpStream,
+    content_length:     Option<&'s str>,
+    transfer_encoding:  Option<&'s str>,
+    expect_continue:    bool,
+}
+
+impl RequestBody for Body<'_> {
+    fn open(&mut self, limit: u64) -> Result<Box<dyn Read + '_>, body::Error> {
+        let inner = BufReader::new(self.prefix.chain(self.stream));
+        let body = body::Body::new(inner, self.content_length, self.transfer_encoding, limit)?;
+        if std::mem::take(&mut self.expect_continue) {
+            // N.B. if this fails, reading the body will fail too
+            let _ = { let mut stream = self.stream; stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n") };
+        }
+        Ok(Box::new(body))
+    }
+}
+
+
+
+#[derive(Clone, Copy, PartialEq, Eq)] enum Framing {
+    Length,
+    Chunked,
+    Close,
+    None,   // HEAD: headers as if for GET, but no body at all (not even a chunked terminator)
+}
+
+/// Buffers up to [`Response::FLUSH_AT`] bytes at a time, framing the body per the `len` passed to [`response::Response::head`].
+pub struct Response<'s> {
+    stream:     &'s TcpStream,
+    version:    Version,
+    framing:    Option<Framing>,    // None until head()
+    head_only:  bool,               // responding to HEAD: discard any body
+    out:        Vec<u8>,            // framed and ready to send
+    body:       Vec<u8>,            // not yet framed
+}
+
+impl<'s> Response<'s> {
+    const FLUSH_AT : usize = 16 * 1024;
+
+    pub fn new(stream: &'s TcpStream, version: Version) -> Self {
+        debug_assert!(version < Version::Http2);
+        Self { stream, version, framing: None, head_only: false, out: Vec::new(), body: Vec::new() }
+    }
+
+    fn frame_body(&mut self) {
+        if self.body.is_empty() { return }
+        if self.framing == Some(Framing::None) {
+            self.body.clear();
+        } else if self.framing == Some(Framing::Chunked) {
+            let _ = chunked::Encoder::new(&mut self.out).write_all(&self.body); // can't fail: Vec
+            self.body.clear();
+        } else {
+            self.out.append(&mut self.body);
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        self.frame_body();
+        if self.out.is_empty() { return Ok(()) }
+        let mut stream = self.stream;
+        stream.write_all(&self.out)?;
+        self.out.clear();
+        Ok(())
+    }
+}
+
+impl Write for Response<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        debug_assert!(self.framing.is_some(), "bug: body written before head");
+        self.body.extend_from_slice(buf);
+        if self.body.len() >= Self::FLUSH_AT { self.send()?; }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.send() }
+}
+
+impl response::Response for Response<'_> {
+    fn head(&mut self, status: u16, headers: &[(&str, &str)], len: Option<u64>) -> io::Result<()> {
+        debug_assert!(self.framing.is_none(), "bug: head sent twice");
+        let out = &mut self.out;
+        write!(out, "{} {status} {}\r\n", self.version.as_str(), reason(status))?;
+        for (name, value) in headers { write!(out, "{name}: {value}\r\n")?; }
+        self.framing = Some(match len {
+            Some(_) if status < 200 || status == 204        => Framing::Length, // MUST NOT send Content-Length
+            Some(_) if status == 304                        => Framing::Length, // would describe the representation, not this (empty) body
+            Some(len)                                       => { write!(out, "Content-Length: {len}\r\n")?; Framing::Length },
+            None if self.version == Version::Http11         => { write!(out, "Transfer-Encoding: chunked\r\n")?; Framing::Chunked },
+            None                                            => Framing::Close,
+        });
+        if self.head_only { self.framing = Some(Framing::None); }
+        if self.version == Version::Http11 { write!(out, "Connection: close\r\n")?; }
+        write!(out, "\r\n")?;
+        Ok(())
+    }
+
+    fn send_file(&mut self, file: &std::fs::File, offset: u64, len: u64) -> io::Result<()> {
+        if self.framing == Some(Framing::None) { return Ok(()) }
+        if self.framing == Some(Framing::Chunked) { return sendfile::copy(file, offset, len, self) }
+        self.send()?; // N.B. whatever we've buffered must go out first
+        match sendfile::sendfile(self.stream, file, offset, len) {
+            Err(err) if err.kind() == io::ErrorKind::Unsupported => sendfile::copy(file, offset, len, self),
+            result => result,
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.frame_body();
+        if self.framing == Some(Framing::Chunked) { chunked::Encoder::new(&mut self.out).finish()?; }
+        self.send()?;
+        linger(self.stream)
+    }
+}
+
+/// Close the connection without discarding the response we just sent.
+///
+/// If we close while unread request body bytes are still sitting in our receive buffer (e.g. we rejected a request
+/// without reading its body), most OSes send a TCP RST, which can clobber the response before the client reads it.  So: stop
+/// sending, then briefly read and discard whatever the client is still sending.
+fn linger(stream: &TcpStream) -> io::Result<()> {
+    stream.shutdown(Shutdown::Write)?;
+    let _ = stream.set_read_timeout(Some(std::time::Duration::from_secs(1)));
+    let mut discard = [0u8; 4096];
+    let mut remaining = 1024 * 1024;
+    let mut stream = stream;
+    while remaining > 0 {
+        match stream.read(&mut discard) {
+            Ok(0) | Err(_)  => break,
+            Ok(n)           => remaining -= n.min(remaining),
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Read); // the client may have already hung up
+    Ok(())
+}
+
+
+
+/// Send `raw` to [`on_connection`] over loopback, and collect everything it sends back.
+#[cfg(test)] fn exchange(settings: &'static Settings, raw: &str) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    client.write_all(raw.as_bytes()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = std::thread::spawn(move || on_connection(settings, server));
+    let mut response = Vec::new();
+    client.read_to_end(&mut response).unwrap();
+    drop(client);
+    server.join().unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+#[test] fn check_head() {
+    let mut settings = serve::memory_settings(&[("big.txt", &[b'x'; 2000])]);
+    settings.files = fs::file::Cache::new(0); // streamed, and thus compressed on the fly (of unknown length)
+    let settings : &'static Settings = Box::leak(Box::new(settings));
+
+    let get = exchange(settings, "GET /big.txt HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(get.contains("Transfer-Encoding: chunked\r\n") && get.ends_with("\r\n0\r\n\r\n"), "{get:?}");
+    let head = exchange(settings, "HEAD /big.txt HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n") && head.contains("Content-Encoding: gzip\r\n"), "{head:?}");
+    assert!(head.ends_with("\r\n\r\n") && head.matches("\r\n\r\n").count() == 1, "HEAD mustn't send a body, even a chunked terminator: {head:?}");
+    let head = exchange(settings, "HEAD /?download=zip HTTP/1.1\r\n\r\n");
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n") && head.ends_with("\r\n\r\n") && head.matches("\r\n\r\n").count() == 1, "{head:?}");
+    let head = exchange(settings, "HEAD /big.txt HTTP/1.0\r\nAccept-Encoding: gzip\r\n\r\n");
+    assert!(head.ends_with("\r\n\r\n") && head.matches("\r\n\r\n").count() == 1, "{head:?}");
+}
+
+#[test] fn check_cross_origin_isolated() {
+    let mut settings = serve::memory_settings(&[("index.html", b"<p>hi</p>")]);
+    settings.headers.isolate("require-corp");
+    let settings : &'static Settings = Box::leak(Box::new(settings));
+
+    for (raw, status) in [
+        ("GET / HTTP/1.1\r\n\r\n",           "200 OK"),
+        ("GET /missing HTTP/1.1\r\n\r\n",    "404 Not Found"),
+        ("GARBAGE\r\n\r\n",                 "400 Bad Request"),
+        ("GET / HTTP/9.9\r\n\r\n",           "505 HTTP Version Not Supported"),
+    ] {
+        let res = exchange(settings, raw);
+        assert!(res.starts_with("HTTP/1.") && res.contains(status), "{raw:?}: {res:?}");
+        for header in ["Cross-Origin-Opener-Policy: same-origin", "Cross-Origin-Embedder-Policy: require-corp", "Cross-Origin-Resource-Policy: same-origin"] {
+            assert!(res.contains(&format!("{header}\r\n")), "{raw:?} should've sent {header}: {res:?}");
+        }
+    }
+}
@@ -0,0 +1,925 @@
+//! [RFC 9113](https://www.rfc-editor.org/rfc/rfc9113) HTTP/2 over cleartext TCP ("h2c"), either by prior knowledge or
+//! via an HTTP/1.1 `Upgrade: h2c`.
+//!
+//! Threading:
+//! *   The connection's thread reads + dispatches frames (see [`Conn`].)
+//! *   A writer thread owns the write half of the socket and the HPACK encoder, and is fed [`Out`] messages.
+//! *   Each request stream gets its own handler thread running [`serve::serve`], just like HTTP/1 connections do.
+//!
+//! Flow control:  Connection level receive credit is handed back immediately.  Stream level receive credit is handed
+//! back as handlers actually read request bodies.  Handlers block on send windows in [`Shared::reserve`].
+
+use crate::*;
+use crate::request::*;
+
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Condvar, Mutex};
+
+
+
+const PREFACE : &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+const DATA          : u8 = 0x0;
+const HEADERS       : u8 = 0x1;
+const PRIORITY      : u8 = 0x2;
+const RST_STREAM    : u8 = 0x3;
+const SETTINGS      : u8 = 0x4;
+const PUSH_PROMISE  : u8 = 0x5;
+const PING          : u8 = 0x6;
+const GOAWAY        : u8 = 0x7;
+const WINDOW_UPDATE : u8 = 0x8;
+const CONTINUATION  : u8 = 0x9;
+
+const END_STREAM    : u8 = 0x01;
+const ACK           : u8 = 0x01;
+const END_HEADERS   : u8 = 0x04;
+const PADDED        : u8 = 0x08;
+const PRIORITY_FLAG : u8 = 0x20;
+
+const NO_ERROR              : u32 = 0x0;
+const PROTOCOL_ERROR        : u32 = 0x1;
+const INTERNAL_ERROR        : u32 = 0x2;
+const FLOW_CONTROL_ERROR    : u32 = 0x3;
+const STREAM_CLOSED         : u32 = 0x5;
+const FRAME_SIZE_ERROR      : u32 = 0x6;
+const REFUSED_STREAM        : u32 = 0x7;
+const COMPRESSION_ERROR     : u32 = 0x9;
+
+const SETTINGS_HEADER_TABLE_SIZE        : u16 = 0x1;
+const SETTINGS_ENABLE_PUSH              : u16 = 0x2;
+const SETTINGS_MAX_CONCURRENT_STREAMS   : u16 = 0x3;
+const SETTINGS_INITIAL_WINDOW_SIZE      : u16 = 0x4;
+const SETTINGS_MAX_FRAME_SIZE           : u16 = 0x5;
+const SETTINGS_MAX_HEADER_LIST_SIZE     : u16 = 0x6;
+
+const DEFAULT_WINDOW            : i64   = 65_535;
+const MAX_WINDOW                : i64   = (1 << 31) - 1;
+const DEFAULT_MAX_FRAME_SIZE    : usize = 16_384;
+const MAX_FRAME_SIZE            : usize = (1 << 24) - 1;
+
+// what we advertise
+const MAX_CONCURRENT_STREAMS    : usize = 100;
+const MAX_HEADER_LIST_SIZE      : usize = 16 * 1024;
+const MAX_HEADER_BLOCK          : usize = 64 * 1024; // compressed, across HEADERS + CONTINUATION frames
+const RECV_WINDOW               : i64   = 1024 * 1024;
+
+
+
+/// How the connection became an HTTP/2 connection.
+pub enum Start {
+    /// The client opened with the connection preface.  `PRI * HTTP/2.0\r\n\r\n` has already been consumed.
+    PriorKnowledge,
+
+    /// The client sent an HTTP/1.1 request with `Upgrade: h2c`, which we've answered with `101 Switching Protocols`.
+    /// That request becomes stream 1.
+    Upgrade { request: OwnedRequest, settings: Vec<u8> },
+}
+
+/// A request that outlives the buffer it was parsed from.
+pub struct OwnedRequest {
+    pub method:     Vec<u8>,
+    pub path:       String,
+    pub search:     String,
+    pub headers:    Vec<(String, String)>, // lowercase names
+}
+
+/// Decode the `HTTP2-Settings` header (unpadded base64url.)
+pub fn decode_base64url(s: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(s.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+    for b in s.trim().trim_end_matches('=').bytes() {
+        let v = match b {
+            b'A' ..= b'Z'   => b - b'A',
+            b'a' ..= b'z'   => b - b'a' + 26,
+            b'0' ..= b'9'   => b - b'0' + 52,
+            b'-'            => 62,
+            b'_'            => 63,
+            _               => return None,
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+pub fn serve(settings: &'static Settings, stream: TcpStream, prefix: &[u8], start: Start) {
+    let Ok(write_half) = stream.try_clone() else { return };
+    let mut input = BufReader::new(prefix.chain(&stream));
+    let expected = match start { Start::PriorKnowledge => &PREFACE[18..], Start::Upgrade { .. } => PREFACE };
+    let mut preface = vec![0u8; expected.len()];
+    if input.read_exact(&mut preface).is_err() || preface != expected { return }
+
+    let shared = Arc::new(Shared::default());
+    let (out, rx) = mpsc::channel();
+    { let shared = shared.clone(); let _ = std::thread::spawn(move || write_loop(write_half, rx, shared)); }
+
+    let mut server_settings = Vec::new();
+    for (id, value) in [
+        (SETTINGS_MAX_CONCURRENT_STREAMS,   MAX_CONCURRENT_STREAMS as u32),
+        (SETTINGS_INITIAL_WINDOW_SIZE,      RECV_WINDOW as u32),
+        (SETTINGS_MAX_HEADER_LIST_SIZE,     MAX_HEADER_LIST_SIZE as u32),
+    ] {
+        server_settings.extend_from_slice(&id.to_be_bytes());
+        server_settings.extend_from_slice(&value.to_be_bytes());
+    }
+    let _ = out.send(Out::Frame(frame(SETTINGS, 0, 0, &server_settings)));
+    let _ = out.send(Out::Frame(window_update(0, (RECV_WINDOW - DEFAULT_WINDOW) as u32)));
+
+    let mut conn = Conn {
+        settings,
+        input,
+        out,
+        shared,
+        decoder:        hpack::Decoder::new(),
+        streams:        Default::default(),
+        active:         Arc::new(AtomicUsize::new(0)),
+        last_stream:    0,
+        goaway:         false,
+    };
+
+    let result = (|| -> Result<(), Close> {
+        if let Start::Upgrade { request, settings } = start {
+            // "A 101 (Switching Protocols) response serves as implicit acknowledgement" of the HTTP2-Settings values
+            conn.apply_settings(&settings)?;
+            conn.last_stream = 1;
+            conn.spawn(1, request, None);
+        }
+        loop { conn.read_frame()?; }
+    })();
+
+    if let Err(Close::GoAway(code)) = result {
+        let mut payload = conn.last_stream.to_be_bytes().to_vec();
+        payload.extend_from_slice(&code.to_be_bytes());
+        let _ = conn.out.send(Out::Frame(frame(GOAWAY, 0, 0, &payload)));
+    }
+
+    // No more DATA will arrive for handlers still reading a request body: dropping their `Sender`s wakes them with an EOF.
+    conn.streams.lock().unwrap().clear();
+    // No more WINDOW_UPDATEs will arrive to unblock handlers waiting on send windows.
+    conn.shared.state.lock().unwrap().closed = true;
+    conn.shared.changed.notify_all();
+    // The writer exits (and closes the socket) once every handler has also finished with its `Sender`.
+}
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)] enum Close {
+    /// The socket failed, or the client hung up.
+    Io,
+
+    /// Connection error: send GOAWAY with this code and hang up.
+    GoAway(u32),
+}
+
+impl From<io::Error> for Close {
+    fn from(_: io::Error) -> Self { Close::Io }
+}
+
+/// Messages for the writer thread.
+enum Out {
+    /// A complete, encoded frame.
+    Frame(Vec<u8>),
+
+    /// A header block to HPACK encode and split into HEADERS + CONTINUATION frames.
+    Headers { stream: u32, headers: Vec<(String, String)>, end_stream: bool },
+
+    /// Already fits within the peer's window + max frame size.
+    Data { stream: u32, data: Vec<u8>, end_stream: bool },
+
+    /// The peer's SETTINGS_HEADER_TABLE_SIZE changed.
+    HeaderTableSize(usize),
+
+    /// The peer's SETTINGS_MAX_FRAME_SIZE changed.
+    MaxFrameSize(usize),
+}
+
+fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+    let mut f = Vec::with_capacity(9 + payload.len());
+    put_frame_header(&mut f, payload.len(), kind, flags, stream);
+    f.extend_from_slice(payload);
+    f
+}
+
+fn put_frame_header(out: &mut Vec<u8>, len: usize, kind: u8, flags: u8, stream: u32) {
+    debug_assert!(len <= MAX_FRAME_SIZE);
+    out.extend_from_slice(&(len as u32).to_be_bytes()[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&(stream & 0x7FFF_FFFF).to_be_bytes());
+}
+
+fn window_update(stream: u32, increment: u32) -> Vec<u8> { frame(WINDOW_UPDATE, 0, stream, &increment.to_be_bytes()) }
+fn rst_stream(stream: u32, code: u32) -> Vec<u8> { frame(RST_STREAM, 0, stream, &code.to_be_bytes()) }
+
+fn write_loop(stream: TcpStream, rx: Receiver<Out>, shared: Arc<Shared>) {
+    let mut socket = BufWriter::with_capacity(64 * 1024, &stream);
+    let mut hpack = hpack::Encoder::new();
+    let mut max_frame_size = DEFAULT_MAX_FRAME_SIZE;
+    let mut block = Vec::new();
+    let mut buf = Vec::new();
+
+    let result = (|| -> io::Result<()> {
+        loop {
+            let msg = match rx.try_recv() {
+                Ok(msg) => msg,
+                Err(TryRecvError::Disconnected) => return socket.flush(),
+                Err(TryRecvError::Empty) => {
+                    socket.flush()?; // nothing else queued: don't sit on what we have
+                    match rx.recv() { Ok(msg) => msg, Err(_) => return Ok(()) }
+                },
+            };
+            match msg {
+                Out::Frame(f) => socket.write_all(&f)?,
+                Out::HeaderTableSize(size) => hpack.set_max_table_size(size),
+                Out::MaxFrameSize(size) => max_frame_size = size,
+                Out::Data { stream, data, end_stream } => {
+                    buf.clear();
+                    put_frame_header(&mut buf, data.len(), DATA, if end_stream { END_STREAM } else { 0 }, stream);
+                    socket.write_all(&buf)?;
+                    socket.write_all(&data)?;
+                },
+                Out::Headers { stream, headers, end_stream } => {
+                    block.clear();
+                    hpack.encode(headers.iter().map(|(k, v)| (k.as_str(), v.as_str())), &mut block);
+                    let mut chunks = block.chunks(max_frame_size).peekable();
+                    let mut kind = HEADERS;
+                    let mut flags = if end_stream { END_STREAM } else { 0 };
+                    while let Some(chunk) = chunks.next().or(if kind == HEADERS { Some(&[][..]) } else { None }) {
+                        if chunks.peek().is_none() { flags |= END_HEADERS; }
+                        buf.clear();
+                        put_frame_header(&mut buf, chunk.len(), kind, flags, stream);
+                        socket.write_all(&buf)?;
+                        socket.write_all(chunk)?;
+                        kind = CONTINUATION;
+                        flags = 0;
+                    }
+                },
+            }
+        }
+    })();
+
+    if result.is_err() {
+        shared.state.lock().unwrap().closed = true;
+        shared.changed.notify_all();
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+
+
+/// Send-side flow control state shared between the reader and handlers.
+#[derive(Default)] struct Shared {
+    state:      Mutex<SendState>,
+    changed:    Condvar,
+}
+
+struct SendState {
+    conn_window:    i64,
+    initial_window: i64,
+    max_frame_size: usize,
+    streams:        HashMap<u32, i64>, // send windows of streams that can still send DATA
+    closed:         bool,
+}
+
+impl Default for SendState {
+    fn default() -> Self {
+        Self { conn_window: DEFAULT_WINDOW, initial_window: DEFAULT_WINDOW, max_frame_size: DEFAULT_MAX_FRAME_SIZE, streams: HashMap::new(), closed: false }
+    }
+}
+
+impl Shared {
+    /// Block until we may send between 1 and `want` bytes of DATA on `stream`.
+    fn reserve(&self, stream: u32, want: usize) -> io::Result<usize> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed { return Err(io::ErrorKind::ConnectionAborted.into()) }
+            let conn_window = state.conn_window;
+            let max_frame_size = state.max_frame_size;
+            let Some(window) = state.streams.get_mut(&stream) else { return Err(io::ErrorKind::ConnectionReset.into()) };
+            if *window > 0 && conn_window > 0 {
+                let n = (want as i64).min(*window).min(conn_window).min(max_frame_size as i64);
+                *window -= n;
+                state.conn_window -= n;
+                return Ok(n as usize);
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn is_open(&self, stream: u32) -> bool { self.state.lock().unwrap().streams.contains_key(&stream) }
+
+    fn remove(&self, stream: u32) { self.state.lock().unwrap().streams.remove(&stream); }
+}
+
+
+
+/// A request stream whose body may still be arriving.
+struct Incoming {
+    body:   Sender<Option<Vec<u8>>>, // None = END_STREAM
+    unread: Arc<AtomicUsize>,        // received but not yet consumed (i.e. not yet credited back to the client)
+}
+
+/// The handler's end of an [`Incoming`].
+type Outgoing = (Receiver<Option<Vec<u8>>>, Arc<AtomicUsize>);
+
+struct Conn<'s> {
+    settings:       &'static Settings,
+    input:          BufReader<io::Chain<&'s [u8], &'s TcpStream>>,
+    out:            Sender<Out>,
+    shared:         Arc<Shared>,
+    decoder:        hpack::Decoder,
+    streams:        Arc<Mutex<HashMap<u32, Incoming>>>, // shared with handlers, which remove theirs once they've responded
+    active:         Arc<AtomicUsize>, // handler threads still running
+    last_stream:    u32,
+    goaway:         bool,
+}
+
+impl Conn<'_> {
+    fn read_frame(&mut self) -> Result<(), Close> {
+        let (kind, flags, id, payload) = self.read_raw_frame()?;
+        match kind {
+            DATA            => self.on_data(flags, id, payload),
+            HEADERS         => self.on_headers(flags, id, payload),
+            PRIORITY        => {
+                if id == 0 { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+                if payload.len() != 5 { self.reset(id, FRAME_SIZE_ERROR); }
+                Ok(())
+            },
+            RST_STREAM      => {
+                if id == 0 || id > self.last_stream { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+                if payload.len() != 4 { return Err(Close::GoAway(FRAME_SIZE_ERROR)) }
+                self.streams.lock().unwrap().remove(&id);
+                self.shared.remove(id);
+                self.shared.changed.notify_all();
+                Ok(())
+            },
+            SETTINGS        => {
+                if id != 0 { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+                if flags & ACK != 0 { return if payload.is_empty() { Ok(()) } else { Err(Close::GoAway(FRAME_SIZE_ERROR)) } }
+                self.apply_settings(&payload)?;
+                self.send(frame(SETTINGS, ACK, 0, &[]))
+            },
+            PUSH_PROMISE    => Err(Close::GoAway(PROTOCOL_ERROR)), // clients can't push
+            PING            => {
+                if id != 0 { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+                if payload.len() != 8 { return Err(Close::GoAway(FRAME_SIZE_ERROR)) }
+                if flags & ACK != 0 { return Ok(()) }
+                self.send(frame(PING, ACK, 0, &payload))
+            },
+            GOAWAY          => {
+                if id != 0 { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+                self.goaway = true; // finish what's in flight, but don't start anything new
+                Ok(())
+            },
+            WINDOW_UPDATE   => self.on_window_update(id, &payload),
+            CONTINUATION    => Err(Close::GoAway(PROTOCOL_ERROR)), // only valid immediately after HEADERS (see on_headers)
+            _               => Ok(()), // "Implementations MUST ignore and discard frames of unknown types"
+        }
+    }
+
+    fn read_raw_frame(&mut self) -> Result<(u8, u8, u32, Vec<u8>), Close> {
+        let mut header = [0u8; 9];
+        self.input.read_exact(&mut header)?;
+        let len = u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize;
+        let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7FFF_FFFF;
+        if len > DEFAULT_MAX_FRAME_SIZE { return Err(Close::GoAway(FRAME_SIZE_ERROR)) } // we never advertise larger
+        let mut payload = vec![0u8; len];
+        self.input.read_exact(&mut payload)?;
+        Ok((header[3], header[4], id, payload))
+    }
+
+    fn send(&self, frame: Vec<u8>) -> Result<(), Close> { self.out.send(Out::Frame(frame)).map_err(|_| Close::Io) }
+
+    /// Respond on `id` without spawning a handler, like [`http1`]'s early errors.  N.B. header-only responses: this is
+    /// the reader thread, which mustn't block on a send window.
+    fn early(&self, id: u32, respond: fn(&mut dyn response::Response)) {
+        { let mut state = self.shared.state.lock().unwrap(); let w = state.initial_window; state.streams.insert(id, w); }
+        let mut res = StreamResponse { id, out: self.out.clone(), shared: self.shared.clone(), head_only: false, head: None, buf: Vec::new(), finished: false };
+        respond(&mut headers::WithHeaders { inner: &mut res, extra: self.settings.headers.everywhere() });
+    }
+
+    fn reset(&mut self, id: u32, code: u32) {
+        self.streams.lock().unwrap().remove(&id);
+        self.shared.remove(id);
+        self.shared.changed.notify_all();
+        let _ = self.send(rst_stream(id, code));
+    }
+
+    fn apply_settings(&mut self, payload: &[u8]) -> Result<(), Close> {
+        if payload.len() % 6 != 0 { return Err(Close::GoAway(FRAME_SIZE_ERROR)) }
+        for setting in payload.chunks(6) {
+            let id = u16::from_be_bytes([setting[0], setting[1]]);
+            let value = u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]]);
+            match id {
+                SETTINGS_HEADER_TABLE_SIZE => { let _ = self.out.send(Out::HeaderTableSize(value as usize)); },
+                SETTINGS_ENABLE_PUSH => if value > 1 { return Err(Close::GoAway(PROTOCOL_ERROR)) }, // we never push anyways
+                SETTINGS_INITIAL_WINDOW_SIZE => {
+                    if i64::from(value) > MAX_WINDOW { return Err(Close::GoAway(FLOW_CONTROL_ERROR)) }
+                    let mut state = self.shared.state.lock().unwrap();
+                    let delta = i64::from(value) - state.initial_window;
+                    state.initial_window = i64::from(value);
+                    for window in state.streams.values_mut() {
+                        *window += delta;
+                        if *window > MAX_WINDOW { return Err(Close::GoAway(FLOW_CONTROL_ERROR)) }
+                    }
+                    drop(state);
+                    self.shared.changed.notify_all();
+                },
+                SETTINGS_MAX_FRAME_SIZE => {
+                    let value = value as usize;
+                    if !(DEFAULT_MAX_FRAME_SIZE ..= MAX_FRAME_SIZE).contains(&value) { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+                    self.shared.state.lock().unwrap().max_frame_size = value;
+                    let _ = self.out.send(Out::MaxFrameSize(value));
+                },
+                _ => {}, // SETTINGS_MAX_CONCURRENT_STREAMS, SETTINGS_MAX_HEADER_LIST_SIZE: we don't push, and our headers are small
+            }
+        }
+        Ok(())
+    }
+
+    fn on_window_update(&mut self, id: u32, payload: &[u8]) -> Result<(), Close> {
+        if payload.len() != 4 { return Err(Close::GoAway(FRAME_SIZE_ERROR)) }
+        let increment = i64::from(u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]) & 0x7FFF_FFFF);
+        if id == 0 {
+            if increment == 0 { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+            let mut state = self.shared.state.lock().unwrap();
+            state.conn_window += increment;
+            if state.conn_window > MAX_WINDOW { return Err(Close::GoAway(FLOW_CONTROL_ERROR)) }
+        } else {
+            if id > self.last_stream { return Err(Close::GoAway(PROTOCOL_ERROR)) } // idle stream
+            if increment == 0 { self.reset(id, PROTOCOL_ERROR); return Ok(()) }
+            let mut state = self.shared.state.lock().unwrap();
+            let Some(window) = state.streams.get_mut(&id) else { return Ok(()) }; // already finished sending: ignore
+            *window += increment;
+            if *window > MAX_WINDOW { drop(state); self.reset(id, FLOW_CONTROL_ERROR); return Ok(()) }
+        }
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    fn on_data(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), Close> {
+        if id == 0 { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+        if id > self.last_stream { return Err(Close::GoAway(PROTOCOL_ERROR)) } // idle stream
+        let flow_len = payload.len();
+        if flow_len > 0 { self.send(window_update(0, flow_len as u32))?; }
+        let data = unpad(flags, payload)?;
+        let padding = flow_len - data.len();
+
+        let mut streams = self.streams.lock().unwrap();
+        let Some(incoming) = streams.get(&id) else { return Ok(()) }; // XXX: only "closed" streams should be ignored, "half-closed (remote)" ones are a STREAM_CLOSED error
+        if incoming.unread.fetch_add(data.len(), Ordering::Relaxed) + data.len() > RECV_WINDOW as usize { drop(streams); self.reset(id, FLOW_CONTROL_ERROR); return Ok(()) }
+        if padding > 0 { self.send(window_update(id, padding as u32))?; } // nobody will ever read the padding
+        let _ = incoming.body.send(Some(data)); // the handler may have finished without reading the body
+        if flags & END_STREAM != 0 {
+            let _ = incoming.body.send(None);
+            streams.remove(&id);
+        }
+        Ok(())
+    }
+
+    fn on_headers(&mut self, flags: u8, id: u32, payload: Vec<u8>) -> Result<(), Close> {
+        if id == 0 || id % 2 == 0 { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+
+        let mut block = unpad(flags, payload)?;
+        if flags & PRIORITY_FLAG != 0 {
+            if block.len() < 5 { return Err(Close::GoAway(FRAME_SIZE_ERROR)) }
+            block.drain(..5);
+        }
+        let mut end_headers = flags & END_HEADERS != 0;
+        while !end_headers {
+            let (kind, flags, cid, payload) = self.read_raw_frame()?;
+            if kind != CONTINUATION || cid != id { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+            if block.len() + payload.len() > MAX_HEADER_BLOCK { return Err(Close::GoAway(COMPRESSION_ERROR)) } // XXX: ENHANCE_YOUR_CALM?
+            block.extend_from_slice(&payload);
+            end_headers = flags & END_HEADERS != 0;
+        }
+        let headers = self.decoder.decode(&block, MAX_HEADER_LIST_SIZE);
+        let end_stream = flags & END_STREAM != 0;
+
+        if let Some(incoming) = self.streams.lock().unwrap().remove(&id) { // trailers
+            if !end_stream { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+            if headers == Err(hpack::Error::Compression) { return Err(Close::GoAway(COMPRESSION_ERROR)) }
+            let _ = incoming.body.send(None);
+            return Ok(())
+        }
+        if id <= self.last_stream {
+            // Trailers may still be on their way after we've reset the stream (or responded without reading the body), and
+            // "[an] endpoint MUST ignore frames that it receives on closed streams after it has sent a RST_STREAM frame"
+            if headers == Err(hpack::Error::Compression) { return Err(Close::GoAway(COMPRESSION_ERROR)) }
+            return if end_stream { Ok(()) } else { Err(Close::GoAway(STREAM_CLOSED)) }
+        }
+        self.last_stream = id;
+
+        let headers = match headers {
+            Ok(headers) => headers,
+            Err(hpack::Error::Compression) => return Err(Close::GoAway(COMPRESSION_ERROR)),
+            Err(hpack::Error::TooLarge) => { self.early(id, response::request_header_fields_too_large); return Ok(()) },
+        };
+        if self.goaway { return Ok(()) }
+        if self.active.load(Ordering::Relaxed) >= MAX_CONCURRENT_STREAMS { self.reset(id, REFUSED_STREAM); return Ok(()) }
+        let Some(request) = parse_request(headers) else { self.reset(id, PROTOCOL_ERROR); return Ok(()) };
+
+        let (tx, rx) = mpsc::channel();
+        let unread = Arc::new(AtomicUsize::new(0));
+        if end_stream { let _ = tx.send(None); } else { self.streams.lock().unwrap().insert(id, Incoming { body: tx, unread: unread.clone() }); }
+        self.spawn(id, request, Some((rx, unread)));
+        Ok(())
+    }
+
+    fn spawn(&mut self, id: u32, request: OwnedRequest, body: Option<Outgoing>) {
+        { let mut state = self.shared.state.lock().unwrap(); let w = state.initial_window; state.streams.insert(id, w); }
+        let settings = self.settings;
+        let out = self.out.clone();
+        let shared = self.shared.clone();
+        let streams = self.streams.clone();
+        let active = self.active.clone();
+        active.fetch_add(1, Ordering::Relaxed);
+        let _ = std::thread::spawn(move || {
+            let req = Request {
+                method:     &request.method,
+                path:       &request.path,
+                search:     &request.search,
+                version:    Version::Http2,
+                headers:    request.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect(),
+            };
+            let content_length = req.header("content-length").map(String::from);
+            let expect_continue = req.header("expect").map_or(false, |e| e.eq_ignore_ascii_case("100-continue"));
+            let mut body = StreamBody { id, out: out.clone(), rx: body, content_length, expect_continue, ended: false };
+            let head_only = req.method == b"HEAD";
+            let mut res = StreamResponse { id, out, shared, head_only, head: None, buf: Vec::new(), finished: false };
+            serve::serve(settings, &req, &mut body, &mut res);
+            streams.lock().unwrap().remove(&id); // N.B. before any RST_STREAM: from here on, more of the body is ignored (see `Conn::on_data`)
+            let finished = res.finished;
+            drop(res);
+            if finished && !body.is_complete() {
+                // "A server can send a complete response prior to the client sending an entire request [...] by sending a
+                // RST_STREAM with an error code of NO_ERROR after sending a complete response"
+                let _ = body.out.send(Out::Frame(rst_stream(id, NO_ERROR)));
+            }
+            active.fetch_sub(1, Ordering::Relaxed);
+        });
+    }
+}
+
+/// Strip PADDED padding from a DATA or HEADERS payload.
+fn unpad(flags: u8, mut payload: Vec<u8>) -> Result<Vec<u8>, Close> {
+    if flags & PADDED == 0 { return Ok(payload) }
+    let Some(&pad) = payload.first() else { return Err(Close::GoAway(FRAME_SIZE_ERROR)) };
+    let pad = usize::from(pad);
+    if pad + 1 > payload.len() { return Err(Close::GoAway(PROTOCOL_ERROR)) }
+    payload.truncate(payload.len() - pad);
+    payload.remove(0);
+    Ok(payload)
+}
+
+/// Validate pseudo-headers per [RFC 9113 § 8.3.1](https://www.rfc-editor.org/rfc/rfc9113#section-8.3.1).
+fn parse_request(fields: Vec<(String, String)>) -> Option<OwnedRequest> {
+    let (mut method, mut scheme, mut path, mut authority) = (None, None, None, None);
+    let mut headers = Vec::with_capacity(fields.len());
+    for (name, value) in fields {
+        if value.bytes().any(|b| matches!(b, b'\0' | b'\r' | b'\n')) { return None } // RFC 9113 §8.2.1: malformed (and e.g. a CRLF could end up in a Location header)
+        if let Some(pseudo) = name.strip_prefix(':') {
+            if !headers.is_empty() { return None } // pseudo-headers must come first
+            let slot = match pseudo { "method" => &mut method, "scheme" => &mut scheme, "path" => &mut path, "authority" => &mut authority, _ => return None };
+            if slot.replace(value).is_some() { return None }
+        } else {
+            if name.bytes().any(|b| b.is_ascii_uppercase()) { return None }
+            if matches!(name.as_str(), "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade") { return None }
+            if name == "te" && value != "trailers" { return None }
+            headers.push((name, value));
+        }
+    }
+    let (method, _scheme, path_search) = (method?, scheme?, path?);
+    if let Some(authority) = authority { if !headers.iter().any(|(k, _)| k == "host") { headers.insert(0, ("host".into(), authority)); } }
+    let split = path_search.find('?').unwrap_or(path_search.len());
+    let (path, search) = path_search.split_at(split);
+    Some(OwnedRequest { method: method.into_bytes(), path: path.into(), search: search.into(), headers })
+}
+
+
+
+struct StreamBody {
+    id:             u32,
+    out:            Sender<Out>,
+    rx:             Option<Outgoing>, // None for upgraded requests (no body)
+    content_length: Option<String>,
+    expect_continue:bool,
+    ended:          bool,
+}
+
+impl StreamBody {
+    /// Has the client sent END_STREAM?  (Discards any unread body.)
+    fn is_complete(&mut self) -> bool {
+        let Some((rx, _)) = self.rx.as_ref() else { return true };
+        while !self.ended {
+            match rx.try_recv() {
+                Ok(Some(_)) => {},
+                Ok(None)    => self.ended = true,
+                Err(_)      => return false,
+            }
+        }
+        true
+    }
+}
+
+impl RequestBody for StreamBody {
+    fn open(&mut self, limit: u64) -> Result<Box<dyn Read + '_>, body::Error> {
+        let content_length = match self.content_length.as_deref() {
+            None => None,
+            Some(len) => {
+                if len.is_empty() || !len.bytes().all(|b| b.is_ascii_digit()) { return Err(body::Error::BadRequest) }
+                let Ok(len) = len.parse::<u64>() else { return Err(body::Error::BadRequest) };
+                if len > limit { return Err(body::Error::TooLarge) }
+                Some(len)
+            },
+        };
+        if std::mem::take(&mut self.expect_continue) && !self.ended {
+            let _ = self.out.send(Out::Headers { stream: self.id, headers: vec![(":status".into(), "100".into())], end_stream: false });
+        }
+        Ok(Box::new(BodyReader { body: self, limit, content_length, read: 0, chunk: Vec::new(), pos: 0 }))
+    }
+}
+
+struct BodyReader<'b> {
+    body:           &'b mut StreamBody,
+    limit:          u64,
+    content_length: Option<u64>,
+    read:           u64,
+    chunk:          Vec<u8>,
+    pos:            usize,
+}
+
+impl Read for BodyReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.fill_buf()?.len().min(buf.len());
+        buf[..n].copy_from_slice(&self.chunk[self.pos..][..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for BodyReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.pos == self.chunk.len() && !self.body.ended {
+            let Some((rx, unread)) = self.body.rx.as_ref() else { self.body.ended = true; break };
+            match rx.recv() {
+                Ok(Some(data)) => {
+                    self.read += data.len() as u64;
+                    if self.read > self.limit { return Err(body::too_large()) }
+                    if self.content_length.map_or(false, |len| self.read > len) { return Err(io::ErrorKind::InvalidData.into()) }
+                    unread.fetch_sub(data.len(), Ordering::Relaxed);
+                    if !data.is_empty() { let _ = self.body.out.send(Out::Frame(window_update(self.body.id, data.len() as u32))); }
+                    self.chunk = data;
+                    self.pos = 0;
+                },
+                Ok(None) => {
+                    self.body.ended = true;
+                    if self.content_length.map_or(false, |len| self.read != len) { return Err(io::ErrorKind::InvalidData.into()) }
+                },
+                Err(_) => return Err(io::ErrorKind::UnexpectedEof.into()), // reset, or the connection went away
+            }
+        }
+        Ok(&self.chunk[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) { self.pos = (self.pos + amt).min(self.chunk.len()); }
+}
+
+
+
+struct StreamResponse {
+    id:         u32,
+    out:        Sender<Out>,
+    shared:     Arc<Shared>,
+    head_only:  bool,                          // responding to HEAD: END_STREAM on the HEADERS, and discard any body
+    head:       Option<Vec<(String, String)>>, // sent lazily so header-only responses can carry END_STREAM
+    buf:        Vec<u8>,
+    finished:   bool,
+}
+
+impl StreamResponse {
+    const FLUSH_AT : usize = 16 * 1024;
+
+    fn send(&self, msg: Out) -> io::Result<()> { self.out.send(msg).map_err(|_| io::ErrorKind::ConnectionAborted.into()) }
+
+    fn send_head(&mut self, end_stream: bool) -> io::Result<()> {
+        let Some(headers) = self.head.take() else { return Ok(()) };
+        if !self.shared.is_open(self.id) { return Err(io::ErrorKind::ConnectionReset.into()) }
+        self.send(Out::Headers { stream: self.id, headers, end_stream })
+    }
+
+    fn send_data(&mut self, end_stream: bool) -> io::Result<()> {
+        self.send_head(false)?;
+        let mut data = &self.buf[..];
+        while !data.is_empty() {
+            let n = self.shared.reserve(self.id, data.len())?;
+            let (now, rest) = data.split_at(n);
+            self.send(Out::Data { stream: self.id, data: now.to_vec(), end_stream: end_stream && rest.is_empty() })?;
+            data = rest;
+        }
+        self.buf.clear();
+        Ok(())
+    }
+}
+
+impl Write for StreamResponse {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.head_only { return Ok(buf.len()) }
+        self.buf.extend_from_slice(buf);
+        if self.buf.len() >= Self::FLUSH_AT { self.send_data(false)?; }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> { self.send_data(false) }
+}
+
+impl response::Response for StreamResponse {
+    fn head(&mut self, status: u16, headers: &[(&str, &str)], len: Option<u64>) -> io::Result<()> {
+        let mut head = vec![(":status".to_string(), status.to_string())];
+        for (name, value) in headers {
+            let name = name.to_ascii_lowercase();
+            if matches!(name.as_str(), "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding" | "upgrade") { continue }
+            head.push((name, value.to_string()));
+        }
+        if let Some(len) = len { if status >= 200 && status != 204 && status != 304 { head.push(("content-length".into(), len.to_string())); } }
+        self.head = Some(head);
+        if self.head_only { self.send_head(true)?; }
+        Ok(())
+    }
+
+    fn send_file(&mut self, file: &std::fs::File, offset: u64, len: u64) -> io::Result<()> {
+        if self.head_only { return Ok(()) }
+        sendfile::copy(file, offset, len, self)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if self.head_only || (self.head.is_some() && self.buf.is_empty()) {
+            self.send_head(true)?;
+        } else if self.buf.is_empty() {
+            self.send(Out::Data { stream: self.id, data: Vec::new(), end_stream: true })?;
+        } else {
+            self.send_data(true)?;
+        }
+        self.finished = true;
+        self.shared.remove(self.id);
+        Ok(())
+    }
+}
+
+impl Drop for StreamResponse {
+    fn drop(&mut self) {
+        if self.finished { return }
+        self.shared.remove(self.id);
+        let _ = self.send(Out::Frame(rst_stream(self.id, INTERNAL_ERROR)));
+    }
+}
+
+
+
+#[test] fn check_base64url() {
+    assert_eq!(decode_base64url("AAMAAABkAAQAoAAAAAIAAAAA").unwrap(), b"\0\x03\0\0\0\x64\0\x04\0\xa0\0\0\0\x02\0\0\0\0");
+    assert_eq!(decode_base64url("").unwrap(), b"");
+    assert_eq!(decode_base64url("bad!"), None);
+}
+
+#[test] fn check_parse_request() {
+    let fields = |f: &[(&str, &str)]| f.iter().map(|&(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+    let req = parse_request(fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/a?b"), (":authority", "x"), ("accept", "*/*")])).unwrap();
+    assert_eq!((&req.method[..], &*req.path, &*req.search), (&b"GET"[..], "/a", "?b"));
+    assert_eq!(req.headers, fields(&[("host", "x"), ("accept", "*/*")]));
+    assert!(parse_request(fields(&[(":method", "GET"), (":scheme", "http")])).is_none());
+    assert!(parse_request(fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), ("Accept", "*/*")])).is_none());
+    assert!(parse_request(fields(&[(":method", "GET"), ("accept", "*/*"), (":scheme", "http"), (":path", "/")])).is_none());
+    for path in ["/a\r\nb", "/a\nb", "/a\0b"] { assert!(parse_request(fields(&[(":method", "GET"), (":scheme", "http"), (":path", path)])).is_none(), "{path:?}"); }
+    assert!(parse_request(fields(&[(":method", "GET"), (":scheme", "http"), (":path", "/"), ("x-a", "b\r\nx-b: c")])).is_none());
+}
+
+#[test] fn check_content_length() {
+    let open = |len: &str| {
+        let (out, _rx) = mpsc::channel();
+        let mut body = StreamBody { id: 1, out, rx: None, content_length: Some(len.into()), expect_continue: false, ended: false };
+        body.open(100).map(drop)
+    };
+    assert_eq!(open("100"), Ok(()));
+    assert_eq!(open("101"), Err(body::Error::TooLarge));
+    for len in ["", "+5", "-5", "5 ", "0x5", "99999999999999999999999"] { assert_eq!(open(len), Err(body::Error::BadRequest), "{len:?}"); }
+}
+
+#[test] fn check_early_response() {
+    let mut settings = serve::memory_settings(&[("a.txt", b"abcdef")]);
+    settings.headers.insert("/*", "X-Everywhere", "yes").unwrap();
+    let settings : &'static Settings = Box::leak(Box::new(settings));
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = std::thread::spawn(move || serve(settings, server, b"", Start::PriorKnowledge));
+    let mut encoder = hpack::Encoder::new();
+    let mut headers = |stream: u32, flags: u8, fields: &[(&str, &str)]| {
+        let mut block = Vec::new();
+        encoder.encode(fields.iter().copied(), &mut block);
+        frame(HEADERS, flags | END_HEADERS, stream, &block)
+    };
+    let read_frame = |mut client: &TcpStream| {
+        let mut header = [0u8; 9];
+        client.read_exact(&mut header).unwrap();
+        let mut payload = vec![0u8; u32::from_be_bytes([0, header[0], header[1], header[2]]) as usize];
+        client.read_exact(&mut payload).unwrap();
+        (header[3], header[4], u32::from_be_bytes([header[5], header[6], header[7], header[8]]), payload)
+    };
+    let get = [(":method", "GET"), (":scheme", "http"), (":path", "/a.txt")];
+    let mut decoder = hpack::Decoder::new(); // N.B. must see every response header block, to keep its dynamic table in sync
+
+    // the handler responds without reading the body, and resets the rest of it
+    let mut sent = [&PREFACE[18..], &frame(SETTINGS, 0, 0, &[]), &headers(1, 0, &get), &frame(DATA, 0, 1, b"body")].concat();
+    client.write_all(&sent).unwrap();
+    loop {
+        let (kind, _flags, stream, payload) = read_frame(&client);
+        assert_ne!(kind, GOAWAY);
+        if kind == HEADERS { decoder.decode(&payload, 4096).unwrap(); }
+        if (kind, stream) == (RST_STREAM, 1) { assert_eq!(payload, NO_ERROR.to_be_bytes()); break }
+    }
+
+    // ...after which anything else sent on that stream is ignored (rather than e.g. piling up as unread), and the connection carries on
+    sent.clear();
+    for _ in 0 ..= RECV_WINDOW as usize / DEFAULT_MAX_FRAME_SIZE { sent.extend_from_slice(&frame(DATA, 0, 1, &[0; DEFAULT_MAX_FRAME_SIZE])); }
+    sent.extend_from_slice(&headers(1, END_STREAM, &[("x-trailer", "yes")]));
+    sent.extend_from_slice(&headers(3, END_STREAM, &get));
+    client.write_all(&sent).unwrap();
+    loop {
+        let (kind, _flags, stream, payload) = read_frame(&client);
+        assert_ne!(kind, GOAWAY);
+        assert_ne!((kind, stream), (RST_STREAM, 1), "{payload:?}");
+        let fields = if kind == HEADERS { decoder.decode(&payload, 4096).unwrap() } else { Vec::new() };
+        if (kind, stream) == (HEADERS, 3) { assert!(fields.contains(&(":status".into(), "200".into())), "{fields:?}"); break }
+    }
+
+    // HEAD: GET's headers, with END_STREAM on them, and no DATA after
+    client.write_all(&headers(5, END_STREAM, &[(":method", "HEAD"), (":scheme", "http"), (":path", "/a.txt")])).unwrap();
+    loop {
+        let (kind, flags, stream, payload) = read_frame(&client);
+        assert_ne!(kind, GOAWAY);
+        assert_ne!((kind, stream), (DATA, 5));
+        let fields = if kind == HEADERS { decoder.decode(&payload, 4096).unwrap() } else { Vec::new() };
+        if (kind, stream) == (HEADERS, 5) {
+            assert_eq!(flags & END_STREAM, END_STREAM);
+            assert!(fields.contains(&("content-length".into(), "6".into())), "{fields:?}");
+            break
+        }
+    }
+    client.write_all(&frame(PING, 0, 0, &[0; 8])).unwrap();
+    loop {
+        let (kind, _flags, stream, _payload) = read_frame(&client);
+        assert_ne!(stream, 5);
+        if kind == PING { break }
+    }
+
+    // a header block too large to decode still gets a 431, with the headers for every path
+    let mut block = Vec::new();
+    let big = "x".repeat(MAX_HEADER_LIST_SIZE);
+    encoder.encode([(":method", "GET"), (":scheme", "http"), (":path", "/a.txt"), ("x-big", big.as_str())], &mut block);
+    let (first, rest) = block.split_at(block.len() / 2);
+    client.write_all(&[frame(HEADERS, END_STREAM, 7, first), frame(CONTINUATION, END_HEADERS, 7, rest)].concat()).unwrap();
+    loop {
+        let (kind, flags, stream, payload) = read_frame(&client);
+        assert_ne!(kind, GOAWAY);
+        let fields = if kind == HEADERS { decoder.decode(&payload, 4096).unwrap() } else { Vec::new() };
+        if (kind, stream) == (HEADERS, 7) {
+            assert_eq!(flags & END_STREAM, END_STREAM);
+            assert!(fields.contains(&(":status".into(), "431".into())), "{fields:?}");
+            assert!(fields.contains(&("x-everywhere".into(), "yes".into())), "{fields:?}");
+            break
+        }
+    }
+    client.shutdown(Shutdown::Both).unwrap();
+    server.join().unwrap();
+}
+
+#[test] fn check_disconnect_mid_body() {
+    let settings : &'static Settings = Box::leak(Box::new(serve::memory_settings(&[("a.txt", b"abcdef")])));
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let server = std::thread::spawn(move || serve(settings, server, b"", Start::PriorKnowledge));
+
+    let mut block = Vec::new();
+    hpack::Encoder::new().encode([(":method", "PROPFIND"), (":scheme", "http"), (":path", "/"), ("content-length", "100000")], &mut block);
+    client.write_all(&[&PREFACE[18..], &frame(SETTINGS, 0, 0, &[]), &frame(HEADERS, END_HEADERS, 1, &block), &frame(DATA, 0, 1, &[0; 10])].concat()).unwrap();
+    client.shutdown(Shutdown::Write).unwrap(); // hang up mid-body
+    server.join().unwrap();
+
+    // The writer only closes the socket once every handler is done with it: a handler still waiting on the rest of the
+    // body would keep it open (and itself running) forever.
+    client.set_read_timeout(Some(std::time::Duration::from_secs(10))).unwrap();
+    let mut rest = Vec::new();
+    assert!(client.read_to_end(&mut rest).is_ok(), "handler stuck waiting for a body that'll never arrive");
+}
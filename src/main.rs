@@ -1,10 +1,32 @@
+mod archive;
+mod body;
 mod browser;
+mod checksum;
+mod chunked;
+mod compress;
+mod cors;
+mod deflate;
+mod download;
+mod embed;
 mod ext_slice;  use ext_slice::*;
 mod fs;
+mod headers;
+mod hpack;
+mod http1;
+mod http2;
 mod mime;
+mod mount;
+mod negotiate;
+mod percent;
+mod redirects;
+mod request;
 mod response;
+mod rules;
 mod run;
+mod sendfile;
+mod serve;
 mod settings;   use settings::*;
+mod vfs;
 mod webdav;
 
 fn main() { run::run() }
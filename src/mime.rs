@@ -1,6 +1,134 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::ffi::OsStr;
 use std::path::*;
 
+
+
+/// User configured MIME types (`--mime-types`, `--mime`, `--default-mime`), layered on top of [`by_extension`].
+#[derive(Default)] pub struct Types {
+    custom:     HashMap<String, String>, // lowercase extension without the leading "." => MIME type
+    default:    Option<String>,
+}
+
+impl Types {
+    /// The MIME type for `path`'s extension, ignoring `--default-mime`.
+    pub fn by_extension<'s>(&'s self, path: &(impl AsRef<Path> + ?Sized)) -> Option<&'s str> {
+        let path = path.as_ref();
+        let ext = path.extension().and_then(|ext| ext.to_str());
+        let custom = ext.and_then(|ext| self.custom.get(&ext.to_ascii_lowercase()));
+        custom.map(|m| m.as_str()).or_else(|| by_path(path))
+    }
+
+    pub fn fallback(&self) -> Option<&str> { self.default.as_deref() }
+
+    /// Map `ext` (with or without a leading `.`) to `mime`, overriding any previous or built-in mapping.
+    pub fn insert(&mut self, ext: &str, mime: &str) -> Result<(), String> {
+        let ext = ext.trim();
+        let ext = ext.strip_prefix('.').unwrap_or(ext);
+        if ext.is_empty() || ext.contains(['.', '/', '\\']) { return Err(format!("invalid extension {ext:?}")) }
+        if !is_valid(mime) { return Err(format!("invalid MIME type {mime:?}")) }
+        self.custom.insert(ext.to_ascii_lowercase(), mime.trim().into());
+        Ok(())
+    }
+
+    /// Serve files with unknown types as `mime` instead of refusing to serve them.
+    pub fn set_default(&mut self, mime: &str) -> Result<(), String> {
+        if !is_valid(mime) { return Err(format!("invalid MIME type {mime:?}")) }
+        self.default = Some(mime.trim().into());
+        Ok(())
+    }
+
+    /// Add mappings from an Apache style `mime.types` file:  one `type/subtype ext1 ext2 ...` per line, `#` comments.
+    pub fn load(&mut self, path: &Path) -> Result<(), String> {
+        let text = std::fs::read_to_string(path).map_err(|err| format!("unable to read `{}`: {err}", path.display()))?;
+        self.parse(&text).map_err(|err| format!("{}: {err}", path.display()))
+    }
+
+    fn parse(&mut self, text: &str) -> Result<(), String> {
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            let Some(mime) = words.next() else { continue };
+            for ext in words { self.insert(ext, mime).map_err(|err| format!("line {}: {err}", line_no + 1))?; }
+        }
+        Ok(())
+    }
+}
+
+/// Is `mime` safe and sane to send as a `Content-Type`?  (`type/subtype`, no control characters, etc.)
+pub fn is_valid(mime: &str) -> bool {
+    let mime = mime.trim();
+    let essence = mime.split(';').next().unwrap_or("");
+    let Some((ty, subtype)) = essence.trim().split_once('/') else { return false };
+    let token = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_alphanumeric() || b"!#$&-^_.+".contains(&b));
+    token(ty) && token(subtype) && !mime.bytes().any(|b| b.is_ascii_control())
+}
+
+/// `"text/html"` for `"text/html; charset=utf-8"`.
+pub fn essence(mime: &str) -> &str { mime.split(';').next().unwrap_or("").trim() }
+
+/// The value of parameter `name` in e.g. `"text/html; charset=utf-8"`, unquoted.
+pub fn param<'m>(mime: &'m str, name: &str) -> Option<&'m str> {
+    mime.split(';').skip(1).filter_map(|p| p.split_once('=')).find(|(k, _)| k.trim().eq_ignore_ascii_case(name)).map(|(_, v)| v.trim().trim_matches('"'))
+}
+
+/// Add a `charset` parameter to text types that don't already specify one, based on the first [`SNIFF_LEN`] bytes of
+/// the file.  Files that aren't valid UTF-8 or UTF-16 are left without a charset, leaving it to the browser to guess.
+pub fn with_charset<'m>(mime: &'m str, head: &[u8]) -> Cow<'m, str> {
+    if !essence(mime).starts_with("text/") || param(mime, "charset").is_some() { return Cow::Borrowed(mime) }
+    match charset(head) {
+        Some(charset)   => Cow::Owned(format!("{}; charset={charset}", mime.trim_end())),
+        None            => Cow::Borrowed(mime),
+    }
+}
+
+/// Detect the encoding of text from its byte order mark, or failing that, by checking if it's valid UTF-8.
+pub fn charset(head: &[u8]) -> Option<&'static str> {
+    if head.starts_with(b"\xEF\xBB\xBF") { return Some("utf-8") }
+    if head.starts_with(b"\xFF\xFE") { return Some("utf-16le") }
+    if head.starts_with(b"\xFE\xFF") { return Some("utf-16be") }
+    match std::str::from_utf8(head) {
+        Ok(_)                                                           => Some("utf-8"),
+        Err(err) if err.error_len().is_none() && head.len() >= SNIFF_LEN   => Some("utf-8"), // just a multi-byte sequence cut off by SNIFF_LEN
+        Err(_)                                                          => None,
+    }
+}
+
+/// How many leading bytes of a file [`sniff`] wants to see.
+pub const SNIFF_LEN : usize = 512;
+
+/// Guess a MIME type from the first [`SNIFF_LEN`] bytes of a file (or all of it, if it's shorter.)
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    // https://mimesniff.spec.whatwg.org/ + https://en.wikipedia.org/wiki/List_of_file_signatures
+    let magic : &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1A\n",     "image/png"),
+        (b"\xFF\xD8\xFF",            "image/jpeg"),
+        (b"GIF87a",                 "image/gif"),
+        (b"GIF89a",                 "image/gif"),
+        (b"\0asm",                  "application/wasm"),
+        (b"%PDF-",                  "application/pdf"),
+        (b"PK\x03\x04",             "application/zip"),
+        (b"PK\x05\x06",             "application/zip"), // empty archive
+        (b"\x1F\x8B",               "application/gzip"),
+        (b"\x7FELF",                 "application/x-elf"),
+    ];
+    if let Some((_, mime)) = magic.iter().find(|(magic, _)| head.starts_with(magic)) { return Some(mime) }
+    if head.len() >= 12 && head.starts_with(b"RIFF") && &head[8..12] == b"WEBP" { return Some("image/webp") }
+
+    if head.starts_with(b"\xFF\xFE") || head.starts_with(b"\xFE\xFF") { return Some("text/plain") } // UTF-16 BOM
+    let text = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    if text.iter().any(|&b| b < 0x20 && !b"\t\n\x0C\r\x1B".contains(&b)) { return None } // binary
+    if charset(head) != Some("utf-8") { return None }
+
+    let trimmed = text.iter().position(|b| !b.is_ascii_whitespace()).map_or(&[][..], |start| &text[start..]);
+    let html_tags : &[&[u8]] = &[b"<!DOCTYPE HTML", b"<HTML", b"<HEAD", b"<SCRIPT", b"<IFRAME", b"<H1", b"<DIV", b"<FONT", b"<TABLE", b"<A", b"<STYLE", b"<TITLE", b"<B", b"<BODY", b"<BR", b"<P", b"<!--"];
+    let is_html = html_tags.iter().any(|tag| {
+        trimmed.len() > tag.len() && trimmed[..tag.len()].eq_ignore_ascii_case(tag) && matches!(trimmed[tag.len()], b' ' | b'>')
+    });
+    Some(if is_html { "text/html" } else { "text/plain" })
+}
+
 pub fn by_path(path: &(impl AsRef<Path> + ?Sized)) -> Option<&'static str> {
     let path = path.as_ref();
     let ext = path.extension()?;
@@ -9,8 +137,8 @@ pub fn by_path(path: &(impl AsRef<Path> + ?Sized)) -> Option<&'static str> {
 
 pub fn by_extension(ext: &(impl AsRef<OsStr> + ?Sized)) -> Option<&'static str> {
     let ext = ext.as_ref();
-    let ext = ext.to_str()?;
-    let ext = ext.strip_prefix(".").unwrap_or(ext);
+    let ext = ext.to_str()?.to_ascii_lowercase(); // e.g. `Logo.PNG` from Windows authored content
+    let ext = ext.strip_prefix('.').unwrap_or(&ext);
     Some(match ext {
         // https://developer.mozilla.org/en-US/docs/Web/HTTP/Basics_of_HTTP/MIME_types/Common_types
         // https://www.iana.org/assignments/media-types/media-types.xhtml#audio
@@ -77,3 +205,67 @@ pub fn by_extension(ext: &(impl AsRef<OsStr> + ?Sized)) -> Option<&'static str>
         _               => return None
     })
 }
+
+
+#[test] fn check_types() {
+    let mut types = Types::default();
+    let by_path = |types: &Types, path: &str| types.by_extension(path).or_else(|| types.fallback()).map(String::from);
+    assert_eq!(by_path(&types, "a.glb"), None);
+    assert_eq!(by_path(&types, "a.txt"), Some("text/plain".into()));
+    types.parse("# comment\nmodel/gltf-binary  glb\n\nmodel/gltf+json gltf GLTF2 # trailing comment\ntext/markdown md\n").unwrap();
+    types.insert(".TXT", "text/x-custom").unwrap();
+    assert_eq!(by_path(&types, "a.glb"),      Some("model/gltf-binary".into()));
+    assert_eq!(by_path(&types, "a.gltf2"),    Some("model/gltf+json".into()));
+    assert_eq!(by_path(&types, "a.txt"),      Some("text/x-custom".into()));
+    assert_eq!(by_path(&types, "LICENSE"),    None);
+    types.set_default("application/octet-stream").unwrap();
+    assert_eq!(by_path(&types, "LICENSE"),    Some("application/octet-stream".into()));
+    assert_eq!(by_path(&types, "a.png"),      Some("image/png".into()));
+    assert_eq!(by_path(&types, "Logo.PNG"),   Some("image/png".into()));
+
+    assert!(types.insert("x", "text/plain\r\nSet-Cookie: a=b").is_err());
+    assert!(types.insert("x", "nonsense").is_err());
+    assert!(types.insert("", "text/plain").is_err());
+    assert!(types.parse("text/plain ok\nbad ext").is_err());
+}
+
+#[test] fn check_sniff() {
+    assert_eq!(sniff(b"\x89PNG\r\n\x1A\n\0\0\0\rIHDR"),     Some("image/png"));
+    assert_eq!(sniff(b"\xFF\xD8\xFF\xE0\0\x10JFIF"),        Some("image/jpeg"));
+    assert_eq!(sniff(b"GIF89a\x01\0\x01\0"),                Some("image/gif"));
+    assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "),            Some("image/webp"));
+    assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "),            None);
+    assert_eq!(sniff(b"\0asm\x01\0\0\0"),                   Some("application/wasm"));
+    assert_eq!(sniff(b"%PDF-1.7\n"),                       Some("application/pdf"));
+    assert_eq!(sniff(b"PK\x03\x04\x14\0"),                  Some("application/zip"));
+    assert_eq!(sniff(b"\x1F\x8B\x08\0"),                     Some("application/gzip"));
+    assert_eq!(sniff(b"\x7FELF\x02\x01\x01"),                Some("application/x-elf"));
+    assert_eq!(sniff(b""),                                  Some("text/plain"));
+    assert_eq!(sniff(b"MIT License\n\nCopyright (c)"),     Some("text/plain"));
+    assert_eq!(sniff("\u{FEFF}caf\u{E9}\n".as_bytes()),      Some("text/plain"));
+    assert_eq!(sniff(b"  \n<!doctype html>\n<html>"),      Some("text/html"));
+    assert_eq!(sniff(b"<p>hi</p>"),                         Some("text/html"));
+    assert_eq!(sniff(b"<pre>hi</pre>"),                     Some("text/plain"));
+    assert_eq!(sniff(b"caf\xE9"),                           None); // latin1
+    assert_eq!(sniff(b"\0\x01\x02\x03"),                     None);
+
+    let mut cut = vec![b'a'; SNIFF_LEN - 1];
+    cut.push(0xE2); // first byte of a 3 byte sequence
+    assert_eq!(sniff(&cut), Some("text/plain"));
+}
+
+#[test] fn check_charset() {
+    assert_eq!(essence(" text/html ; charset=utf-8"), "text/html");
+    assert_eq!(param("text/html; Charset=\"UTF-8\"", "charset"), Some("UTF-8"));
+    assert_eq!(param("text/html", "charset"), None);
+
+    assert_eq!(with_charset("text/plain", b"hello"),                            "text/plain; charset=utf-8");
+    assert_eq!(with_charset("text/html", "caf\u{E9}".as_bytes()),               "text/html; charset=utf-8");
+    assert_eq!(with_charset("text/plain", b"\xFF\xFEh\0i\0"),                   "text/plain; charset=utf-16le");
+    assert_eq!(with_charset("text/plain", b"\xFE\xFF\0h\0i"),                   "text/plain; charset=utf-16be");
+    assert_eq!(with_charset("text/plain", b"caf\xE9"),                          "text/plain"); // latin1?
+    assert_eq!(with_charset("text/plain; charset=iso-8859-1", b"caf\xE9"),      "text/plain; charset=iso-8859-1");
+    assert_eq!(with_charset("text/plain; charset=iso-8859-1", b"hello"),        "text/plain; charset=iso-8859-1");
+    assert_eq!(with_charset("image/png", b"\x89PNG"),                           "image/png");
+    assert_eq!(sniff(b"\xFF\xFEh\0i\0"), Some("text/plain"));
+}
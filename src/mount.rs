@@ -0,0 +1,169 @@
+//! `--mount URLPREFIX=DIR`: serve several directories (or archives) at once, possibly overlaid at the same prefix.
+
+use crate::fs::dir::{Cache, Entry, Snapshot};
+use crate::vfs::Vfs;
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+
+
+#[derive(Default)] pub struct Mounts {
+    mounts: Vec<Mount>, // highest priority first: longest prefix, then command line order
+}
+
+struct Mount {
+    prefix:     Vec<String>, // `/pkg/` => `["pkg"]`
+    root:       PathBuf,
+    vfs:        Option<Box<dyn Vfs>>, // `None` for the real filesystem (via the shared [`Cache`])
+}
+
+impl Mounts {
+    /// Serve `root` at the URL path `prefix` (e.g. `/pkg/`.)  Mounting several roots at the same prefix overlays them:
+    /// the first one mounted containing a given name wins.  Mounts at longer prefixes shadow shorter ones.
+    /// `.zip` and `.tar` roots are indexed immediately, and served read-only.
+    pub fn add(&mut self, prefix: &str, root: PathBuf) -> Result<(), String> {
+        let vfs = match crate::archive::is_archive(&root) {
+            true    => Some(Box::new(crate::archive::open(&root).map_err(|err| format!("unable to read `{}`: {err}", root.display()))?) as Box<dyn Vfs>),
+            false   => None,
+        };
+        self.insert(prefix, root, vfs)
+    }
+
+    /// Serve `vfs` at the URL path `prefix`, much like [`Self::add`].  `root` is the path of `vfs`'s root directory.
+    pub fn add_vfs(&mut self, prefix: &str, root: PathBuf, vfs: Box<dyn Vfs>) -> Result<(), String> {
+        self.insert(prefix, root, Some(vfs))
+    }
+
+    fn insert(&mut self, prefix: &str, root: PathBuf, vfs: Option<Box<dyn Vfs>>) -> Result<(), String> {
+        if !prefix.starts_with('/') { return Err(format!("mount prefix {prefix:?} must start with `/`")) }
+        let trimmed = prefix.trim_matches('/');
+        let prefix_segments = trimmed.split('/').filter(|_| !trimmed.is_empty()).map(String::from).collect::<Vec<_>>();
+        if prefix_segments.iter().any(|s| s.is_empty() || s == "." || s == ".." || s.contains('\\')) { return Err(format!("invalid mount prefix {prefix:?}")) }
+
+        let index = self.mounts.iter().position(|m| m.prefix.len() < prefix_segments.len()).unwrap_or(self.mounts.len());
+        self.mounts.insert(index, Mount { prefix: prefix_segments, root, vfs });
+        Ok(())
+    }
+
+    /// Every mounted directory on disk (e.g. for `--symlinks within-root`.)
+    pub fn roots(&self) -> impl Iterator<Item = &Path> { self.mounts.iter().filter(|m| m.vfs.is_none()).map(|m| m.root.as_path()) }
+
+    /// Every URL prefix something is mounted at (`/`, `/pkg/`, ...), once each.
+    pub fn prefixes(&self) -> Vec<String> {
+        let mut prefixes = self.mounts.iter().map(|m| m.prefix.iter().fold(String::from("/"), |p, s| format!("{p}{s}/"))).collect::<Vec<_>>();
+        prefixes.sort();
+        prefixes.dedup();
+        prefixes
+    }
+
+    /// The merged view of `/`.  `None` only if nothing is mounted there or below (or the mounted directories vanished.)
+    pub fn root<'m>(&'m self, cache: &'m Cache) -> Option<Dir<'m>> {
+        Dir { mounts: self, cache, url: Vec::new(), layers: Vec::new(), merged: Default::default() }.with_layers(Vec::new())
+    }
+}
+
+/// A directory in the merged view of every [`Mount`].
+#[derive(Clone)] pub struct Dir<'m> {
+    mounts: &'m Mounts,
+    cache:  &'m Cache,
+    url:    Vec<String>,                        // decoded URL segments from `/`
+    layers: Vec<(&'m dyn Vfs, Arc<Snapshot>)>,  // the real directories that make up this one, highest priority first
+    merged: Arc<Snapshot>,
+}
+
+impl<'m> Dir<'m> {
+    pub fn snapshot(&self) -> &Arc<Snapshot> { &self.merged }
+
+    /// The subdirectory `name` (which should be the exact name of an entry in [`Self::snapshot`]), if it is one.
+    pub fn child(&self, name: &OsStr) -> Option<Self> {
+        let mut url = self.url.clone();
+        url.push(name.to_string_lossy().into_owned());
+        let layers = self.layers.iter()
+            .filter_map(|(vfs, layer)| Some((*vfs, layer.by_name(name)?)))
+            .filter(|(_, e)| e.is_dir())
+            .filter_map(|(vfs, e)| Some((vfs, vfs.list(e.path())?)))
+            .collect();
+        Self { mounts: self.mounts, cache: self.cache, url, layers: Vec::new(), merged: Default::default() }.with_layers(layers)
+    }
+
+    /// The filesystem `entry` (which should be from [`Self::snapshot`]) lives on.  `None` for directories that are
+    /// only mount points.
+    pub fn vfs_of(&self, entry: &Entry) -> Option<&'m dyn Vfs> {
+        self.layers.iter().find(|(_, layer)| layer.by_name(entry.name_os()).map_or(false, |e| e.path() == entry.path())).map(|(vfs, _)| *vfs)
+    }
+
+    /// Finish constructing `self` from the `inherited` layers of its parent, plus whatever is mounted here.
+    fn with_layers(mut self, inherited: Vec<(&'m dyn Vfs, Arc<Snapshot>)>) -> Option<Self> {
+        let mut mounts = Vec::new();
+        for m in self.mounts.mounts.iter() {
+            if m.prefix == self.url {
+                let vfs = m.vfs.as_deref().unwrap_or(self.cache);
+                self.layers.extend(vfs.list(&m.root).map(|snapshot| (vfs, snapshot)));
+            } else if m.prefix.len() > self.url.len() && m.prefix.starts_with(&self.url) {
+                let name = &m.prefix[self.url.len()];
+                if !mounts.iter().any(|e: &Entry| e.name_lossy() == name) { mounts.push(Entry::mount(name, m.root.clone())); }
+            }
+        }
+        self.layers.extend(inherited);
+        if self.layers.is_empty() && mounts.is_empty() { return None }
+
+        self.merged = match (&self.layers[..], mounts.is_empty()) {
+            ([(_, only)], true) => Arc::clone(only), // the common case: nothing to merge
+            (layers, _)         => Arc::new(Snapshot::merge(mounts, &layers.iter().map(|(_, s)| Arc::clone(s)).collect::<Vec<_>>())),
+        };
+        Some(self)
+    }
+}
+
+
+
+#[test] fn check_mounts() {
+    use std::io::Read;
+    let tmp = std::env::temp_dir().join(format!("mmuhttpd-check-mounts-{}", std::process::id()));
+    let (site, fallback, pkg) = (tmp.join("site"), tmp.join("fallback"), tmp.join("pkg"));
+    for dir in [site.join("sub"), fallback.join("sub"), pkg.clone()] { std::fs::create_dir_all(dir).unwrap(); }
+    std::fs::write(site.join("index.html"), "site").unwrap();
+    std::fs::write(site.join("sub/a.txt"), "site").unwrap();
+    std::fs::write(fallback.join("index.html"), "fallback").unwrap();
+    std::fs::write(fallback.join("sub/b.txt"), "fallback").unwrap();
+    std::fs::write(pkg.join("app.wasm"), "pkg").unwrap();
+
+    let mut mounts = Mounts::default();
+    mounts.add("/", site).unwrap();
+    mounts.add("/deep/er/pkg/", pkg).unwrap();
+    mounts.add("/", fallback).unwrap();
+    let memory = crate::vfs::Memory::new("memory");
+    memory.insert("sub/c.txt", &b"memory"[..], std::time::SystemTime::UNIX_EPOCH);
+    mounts.add_vfs("/", "memory".into(), Box::new(memory)).unwrap();
+    assert!(mounts.add("pkg", tmp.clone()).is_err());
+    assert!(mounts.add("/../", tmp.clone()).is_err());
+    assert_eq!(mounts.prefixes(), ["/", "/deep/er/pkg/"]);
+
+    let cache = Cache::default();
+    let names = |dir: &Dir| { let mut n = dir.snapshot().entries().map(|e| e.name_lossy().to_string()).collect::<Vec<_>>(); n.sort(); n };
+    let read = |dir: &Dir, name: &str| {
+        let e = dir.snapshot().by_name(name).unwrap();
+        let mut out = String::new();
+        dir.vfs_of(e).unwrap().read_range(e.path(), 0 .. 64).unwrap().read_to_string(&mut out).unwrap();
+        out
+    };
+
+    let root = mounts.root(&cache).unwrap();
+    assert_eq!(names(&root), ["deep", "index.html", "sub"]);
+    assert_eq!(read(&root, "index.html"), "site", "first mount wins");
+
+    let sub = root.child(OsStr::new("sub")).unwrap();
+    assert_eq!(names(&sub), ["a.txt", "b.txt", "c.txt"]);
+    assert_eq!(read(&sub, "b.txt"), "fallback");
+    assert_eq!(read(&sub, "c.txt"), "memory");
+
+    let deep = root.child(OsStr::new("deep")).unwrap();
+    assert!(deep.vfs_of(deep.snapshot().by_name("er").unwrap()).is_none(), "mount points aren't on any filesystem");
+    let pkg = deep.child(OsStr::new("er")).unwrap().child(OsStr::new("pkg")).unwrap();
+    assert_eq!(names(&pkg), ["app.wasm"]);
+    assert!(root.child(OsStr::new("index.html")).is_none());
+
+    let _ = std::fs::remove_dir_all(&tmp);
+}
@@ -0,0 +1,149 @@
+//! Apache [MultiViews](https://httpd.apache.org/docs/current/content-negotiation.html#multiviews) style content
+//! negotiation:  a request for a missing `index.html` can be served by `index.en.html`, `index.html.ja`, etc.
+
+use crate::fs::dir::{Entry, Snapshot};
+use crate::mime;
+
+
+
+pub struct Variant<'e> {
+    pub entry:      &'e Entry,
+    pub mime:       &'e str,
+    pub language:   Option<&'e str>,
+}
+
+/// Find files in `dir` that are language and/or type variants of `name`:
+///
+/// | `name`        | Variants                                          |
+/// | --------------| --------------------------------------------------|
+/// | `index.html`  | `index.en.html`, `index.html.en`                  |
+/// | `index`       | `index.html`, `index.pdf`, `index.en.html`, ...   |
+pub fn variants<'e>(dir: &'e Snapshot, name: &str, types: &'e mime::Types) -> Vec<Variant<'e>> {
+    let mut variants = Vec::new();
+    for entry in dir.entries().filter(|e| e.is_file()) {
+        let mut parts = entry.name_lossy().split('.');
+        let Some(stem) = parts.next() else { continue };
+        if stem.is_empty() || !name.split('.').next().map_or(false, |s| s == stem) { continue }
+
+        let mut language = None;
+        let mut base = String::from(stem);
+        for part in parts {
+            if language.is_none() && is_language_tag(part) && types.by_extension(&format!("_.{part}")).is_none() {
+                language = Some(part);
+            } else {
+                base.push('.');
+                base.push_str(part);
+            }
+        }
+        let is_variant = if name.contains('.') { base == name } else { base.strip_prefix(name).map_or(false, |ext| ext.starts_with('.')) };
+        if !is_variant || (language.is_none() && base == name) { continue } // N.B. `name` itself would've been served directly
+        let Some(mime) = types.by_extension(&base) else { continue };
+        variants.push(Variant { entry, mime, language });
+    }
+    variants.sort_by(|a, b| a.entry.name_lossy().cmp(b.entry.name_lossy())); // deterministic tie breaking
+    variants
+}
+
+/// Pick the best variant per `Accept` and `Accept-Language`, or `None` if the client won't accept any of them.
+pub fn choose<'v, 'e>(variants: &'v [Variant<'e>], accept: Option<&str>, accept_language: Option<&str>) -> Option<&'v Variant<'e>> {
+    let mut best = None;
+    let mut best_q = 0.0;
+    for variant in variants {
+        let q_type = accept.map_or(1.0, |a| accept_q(a, variant.mime));
+        let q_lang = match (accept_language, variant.language) {
+            (None, _)                   => 1.0,
+            (Some(_), None)             => 0.001, // still acceptable, but anything the client actually asked for is better
+            (Some(al), Some(language))  => language_q(al, language),
+        };
+        let q = q_type * q_lang;
+        if q > best_q {
+            best = Some(variant);
+            best_q = q;
+        }
+    }
+    best
+}
+
+/// The header to `Vary` on when choosing between `variants`.
+pub fn vary(variants: &[Variant]) -> &'static str {
+    let types = variants.iter().any(|v| mime::essence(v.mime) != mime::essence(variants[0].mime));
+    let languages = variants.iter().any(|v| v.language.is_some());
+    match (types, languages) {
+        (true,  true )  => "Accept, Accept-Language",
+        (true,  false)  => "Accept",
+        (false, _    )  => "Accept-Language",
+    }
+}
+
+/// Iterate the `(value, q)` pairs of e.g. an `Accept` header.  Items with malformed q-values are skipped.
+pub fn qvalues(header: &str) -> impl Iterator<Item = (&str, f32)> {
+    header.split(',').filter_map(|item| {
+        let mut params = item.split(';');
+        let value = params.next().unwrap_or("").trim();
+        let q = params.filter_map(|p| p.trim().strip_prefix("q=")).next().map_or(Some(1.0), |q| q.trim().parse::<f32>().ok())?;
+        if value.is_empty() || !(0.0 ..= 1.0).contains(&q) { return None }
+        Some((value, q))
+    })
+}
+
+/// How acceptable is `mime` per an `Accept` header?  The most specific matching range wins.
+pub fn accept_q(accept: &str, mime: &str) -> f32 {
+    let essence = mime::essence(mime);
+    let (ty, _subtype) = essence.split_once('/').unwrap_or((essence, ""));
+    let mut best = (0, 0.0); // (specificity, q)
+    for (range, q) in qvalues(accept) {
+        let range = mime::essence(range);
+        let specificity = if range.eq_ignore_ascii_case(essence) { 3 }
+            else if range.strip_suffix("/*").map_or(false, |t| t.eq_ignore_ascii_case(ty)) { 2 }
+            else if range == "*/*" { 1 }
+            else { continue };
+        if specificity > best.0 { best = (specificity, q) }
+    }
+    best.1
+}
+
+/// How acceptable is `language` per an `Accept-Language` header?  Ranges match tags they're a prefix of
+/// ([RFC 4647 § 3.3.1](https://www.rfc-editor.org/rfc/rfc4647#section-3.3.1)), and the longest matching range wins.
+pub fn language_q(accept_language: &str, language: &str) -> f32 {
+    let mut best = (0, 0.0); // (specificity, q)
+    for (range, q) in qvalues(accept_language) {
+        let matches = range == "*" || language.get(..range.len()).map_or(false, |prefix| prefix.eq_ignore_ascii_case(range))
+            && matches!(language.as_bytes().get(range.len()), None | Some(b'-'));
+        let specificity = if range == "*" { 1 } else { 1 + range.len() };
+        if matches && specificity > best.0 { best = (specificity, q) }
+    }
+    best.1
+}
+
+/// `en`, `ja`, `en-US`, `zh-Hant-TW`, ...
+fn is_language_tag(s: &str) -> bool {
+    let mut subtags = s.split('-');
+    let primary = subtags.next().unwrap_or("");
+    (2 ..= 3).contains(&primary.len()) && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|t| (1 ..= 8).contains(&t.len()) && t.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+
+
+#[test] fn check_accept() {
+    assert_eq!(accept_q("text/html, application/xhtml+xml;q=0.9, */*;q=0.8", "text/html; charset=utf-8"),  1.0);
+    assert_eq!(accept_q("text/html, application/xhtml+xml;q=0.9, */*;q=0.8", "application/pdf"),           0.8);
+    assert_eq!(accept_q("text/*;q=0.5, text/plain;q=0", "text/plain"),                                      0.0);
+    assert_eq!(accept_q("text/*;q=0.5, text/plain;q=0", "text/html"),                                       0.5);
+    assert_eq!(accept_q("image/png", "text/html"),                                                          0.0);
+
+    assert_eq!(language_q("ja, en;q=0.5", "ja"),            1.0);
+    assert_eq!(language_q("ja, en;q=0.5", "en"),            0.5);
+    assert_eq!(language_q("en", "en-US"),                   1.0);
+    assert_eq!(language_q("en-US", "en"),                   0.0);
+    assert_eq!(language_q("en-us, en;q=0.2", "EN-US"),      1.0);
+    assert_eq!(language_q("english", "en"),                 0.0);
+    assert_eq!(language_q("de, *;q=0.1", "fr"),             0.1);
+    assert_eq!(language_q("de, *;q=0.1, fr;q=0", "fr"),     0.0);
+
+    assert!( is_language_tag("en"));
+    assert!( is_language_tag("zh-Hant-TW"));
+    assert!(!is_language_tag("html"));
+    assert!(!is_language_tag("e"));
+    assert!(!is_language_tag("7z"));
+}
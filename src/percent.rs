@@ -0,0 +1,111 @@
+//! [Percent-encoding](https://www.rfc-editor.org/rfc/rfc3986#section-2.1) of URL path segments, round-tripping file
+//! names that aren't valid UTF-8 (e.g. legacy Latin-1 names on Linux) byte for byte.
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+
+
+
+/// Append `name` to `out` as a percent-encoded path segment.  Safe to embed in XML as-is (`&`, `<`, etc. are encoded.)
+pub fn encode_segment(name: &OsStr, out: &mut String) {
+    for &b in os_bytes(name).iter() {
+        if b.is_ascii_alphanumeric() || b"-._~!$'()*+,;=:@".contains(&b) {
+            out.push(char::from(b));
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+}
+
+/// Percent-encode only the non-ASCII bytes of a raw request path, so it can be passed around as a `&str`.
+pub fn encode_non_ascii(path: &[u8]) -> String {
+    let mut out = String::with_capacity(path.len());
+    for &b in path {
+        if b.is_ascii() { out.push(char::from(b)) } else { out.push_str(&format!("%{b:02X}")) }
+    }
+    out
+}
+
+/// Decode a single path segment back into the exact file name it names.  `None` for malformed escapes, and for
+/// segments that aren't a plain name once decoded (empty, `.`, `..`, or containing `/`, `\` or NUL.)
+pub fn decode_segment(segment: &str) -> Option<OsString> {
+    let mut bytes = Vec::with_capacity(segment.len());
+    let mut rest = segment.as_bytes();
+    while let Some((&b, after)) = rest.split_first() {
+        if b == b'%' {
+            let [hi, lo, ..] = *after else { return None };
+            let hex = |d: u8| char::from(d).to_digit(16);
+            bytes.push((hex(hi)? * 16 + hex(lo)?) as u8);
+            rest = &after[2..];
+        } else {
+            bytes.push(b);
+            rest = after;
+        }
+    }
+    if matches!(&bytes[..], b"" | b"." | b"..") || bytes.iter().any(|b| matches!(b, b'/' | b'\\' | 0)) { return None }
+    os_string(bytes)
+}
+
+#[cfg(unix)] fn os_bytes(s: &OsStr) -> Cow<[u8]> {
+    use std::os::unix::ffi::OsStrExt;
+    Cow::Borrowed(s.as_bytes())
+}
+
+#[cfg(not(unix))] fn os_bytes(s: &OsStr) -> Cow<[u8]> {
+    match s.to_string_lossy() { // XXX: unpaired surrogates can't round trip
+        Cow::Borrowed(s)    => Cow::Borrowed(s.as_bytes()),
+        Cow::Owned(s)       => Cow::Owned(s.into_bytes()),
+    }
+}
+
+#[cfg(unix)] fn os_string(bytes: Vec<u8>) -> Option<OsString> {
+    use std::os::unix::ffi::OsStringExt;
+    Some(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))] fn os_string(bytes: Vec<u8>) -> Option<OsString> {
+    String::from_utf8(bytes).ok().map(OsString::from)
+}
+
+
+
+#[test] fn check_percent() {
+    let encode = |name: &str| { let mut out = String::new(); encode_segment(OsStr::new(name), &mut out); out };
+    assert_eq!(encode("a b&c<d>.txt"),  "a%20b%26c%3Cd%3E.txt");
+    assert_eq!(encode("100%"),          "100%25");
+    assert_eq!(encode("日本"),          "%E6%97%A5%E6%9C%AC");
+    assert_eq!(encode_non_ascii(b"/caf\xE9 au lait"), "/caf%E9 au lait");
+
+    assert_eq!(decode_segment("a%20b%26c.txt").unwrap(),    "a b&c.txt");
+    assert_eq!(decode_segment("%e6%97%a5%E6%9C%AC").unwrap(), "日本");
+    assert_eq!(decode_segment("a+b").unwrap(),              "a+b");
+    assert!(decode_segment("%2e%2E").is_none());
+    assert!(decode_segment("a%2Fb").is_none());
+    assert!(decode_segment("a%5Cb").is_none());
+    assert!(decode_segment("a%00").is_none());
+    assert!(decode_segment("%4").is_none());
+    assert!(decode_segment("%+1").is_none());
+    assert!(decode_segment("").is_none());
+}
+
+#[cfg(target_os = "linux")] #[test] fn check_latin1_round_trip() {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = std::env::temp_dir().join(format!("mmuhttpd-check-latin1-{}", std::process::id()));
+    let name = OsStr::from_bytes(b"caf\xE9.txt");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join(name), "latin1").unwrap();
+
+    let cache = crate::fs::dir::Cache::new(1, std::time::Duration::ZERO, crate::fs::dir::Symlinks::Deny);
+    let snapshot = cache.read_dir(&dir).unwrap();
+    let entry = snapshot.entries().next().unwrap();
+    assert_eq!(entry.name_os(), name);
+    assert!(snapshot.by_name(entry.name_lossy()).is_none(), "lossy name shouldn't resolve");
+
+    let mut href = String::new();
+    encode_segment(entry.name_os(), &mut href);
+    assert_eq!(href, "caf%E9.txt");
+    let decoded = decode_segment(&href).unwrap();
+    assert_eq!(std::fs::read_to_string(snapshot.by_name(&decoded).unwrap().path()).unwrap(), "latin1");
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,248 @@
+//! `_redirects`: redirect or rewrite requests before looking for files, in the style of Netlify's `_redirects` files:
+//!
+//! ```text
+//! # FROM                    [QUERY...]  TO                          [STATUS]
+//! /old.html                             /new.html                   # 301 by default
+//! /docs/*                               /manual/:splat              302
+//! /blog/:year/:slug                     /posts/:year-:slug          308
+//! /store                    id=:id      /products/:id               301
+//! /app/*                                /app/index.html             200   # rewrite: served as if requested
+//! /legacy/*                             https://example.com/:splat  301!  # `!`: even if /legacy/... exists
+//! ```
+//!
+//! `FROM` patterns match the (decoded) URL path like `_headers` patterns do: a final `*` captures the rest of the path
+//! as `:splat`, and `:name` segments capture one segment each.  `QUERY` conditions require a parameter to be present
+//! (`id=:id`, capturing its value) or to have a specific value (`id=5`.)  The first matching rule wins - unless the
+//! requested path exists, in which case rules without a `!` are ignored.  The query string is passed along unless
+//! `TO` has its own or the rule matched on it.
+
+use crate::fs::file::Stat;
+use crate::mount;
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+
+
+/// Read from the webroot (again, whenever it changes.)  Never served.
+pub const FILE : &str = "_redirects";
+
+/// Don't bother reading (or keeping in memory) anything larger.
+const MAX_FILE_SIZE : u64 = 1024 * 1024;
+
+#[derive(Default)] pub struct Rules {
+    rules: Vec<Rule>, // first match wins
+}
+
+struct Rule {
+    from:   Vec<String>,
+    query:  Vec<(String, String)>,
+    to:     String,
+    status: u16,
+    force:  bool,
+}
+
+#[derive(Debug, PartialEq, Eq)] pub enum Action {
+    /// Send the client elsewhere with this status (301, 302, 307, or 308.)
+    Redirect(u16, String),
+    /// Serve this (percent-encoded) path and search (`""` or `"?..."`) instead.
+    Rewrite(String, String),
+}
+
+impl Rules {
+    /// Parse the contents of a [`FILE`] found at `path`.  Bad lines are skipped, and described in the returned errors.
+    pub fn parse(path: &Path, text: &str) -> (Self, Vec<String>) {
+        let mut rules = Self::default();
+        let mut errors = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split(" #").next().unwrap_or("").trim(); // N.B. `#` alone could be part of a URL
+            if line.is_empty() || line.starts_with('#') { continue }
+            match Rule::parse(line) {
+                Ok(rule)    => rules.rules.push(rule),
+                Err(err)    => errors.push(format!("{}:{}: {err}", path.display(), line_no + 1)),
+            }
+        }
+        (rules, errors)
+    }
+
+    /// What to do with a request for `path` + `search`, if anything.  `exists` is only asked about `path` when a rule
+    /// without a `!` matches.
+    pub fn apply(&self, path: &str, search: &str, exists: impl FnOnce() -> bool) -> Option<Action> {
+        let segments = path.trim_start_matches('/').split('/').map(|raw| (raw, crate::percent::decode_segment(raw).map(|d| d.to_string_lossy().into_owned()))).collect::<Vec<_>>();
+        let params = search.trim_start_matches('?').split('&').filter(|p| !p.is_empty()).map(|p| p.split_once('=').unwrap_or((p, ""))).collect::<Vec<_>>();
+        let mut exists = Some(exists);
+        let mut shadowed = None;
+        for rule in self.rules.iter() {
+            let mut captures = Vec::new();
+            if !matches(&rule.from, &segments, &mut captures) { continue }
+            if !rule.query.iter().all(|(key, value)| params.iter().any(|&(k, v)| k == key && match value.strip_prefix(':') {
+                Some(name)  => { captures.push((name.to_string(), v.to_string())); true },
+                None        => v == value,
+            })) { continue }
+            if !rule.force && *shadowed.get_or_insert_with(|| exists.take().map_or(false, |exists| exists())) { continue }
+
+            let mut to = substitute(&rule.to, &captures);
+            if !to.contains('?') && rule.query.is_empty() { to.push_str(search); }
+            return Some(match rule.status {
+                200     => match to.split_once('?') {
+                    Some((path, query)) => Action::Rewrite(path.into(), format!("?{query}")),
+                    None                => Action::Rewrite(to, String::new()),
+                },
+                status  => Action::Redirect(status, to),
+            });
+        }
+        None
+    }
+}
+
+impl Rule {
+    fn parse(line: &str) -> Result<Self, String> {
+        let mut words = line.split_whitespace().peekable();
+        let from = words.next().unwrap_or("");
+        let Some(from) = from.strip_prefix('/') else { return Err(format!("expected a path starting with `/`, but got {from:?}")) };
+        let mut query = Vec::new();
+        while let Some(param) = words.next_if(|w| !w.starts_with('/') && !w.contains("://") && w.contains('=')) {
+            let (key, value) = param.split_once('=').unwrap_or((param, ""));
+            query.push((key.into(), value.into()));
+        }
+        let Some(to) = words.next() else { return Err(format!("expected a destination after {line:?}")) };
+        let (status, force) = match words.next() {
+            None            => ("301", false),
+            Some(status)    => match status.strip_suffix('!') { Some(status) => (status, true), None => (status, false) },
+        };
+        let status = match status { "200" => 200, "301" => 301, "302" => 302, "307" => 307, "308" => 308, _ => return Err(format!("unsupported status {status:?}: expected 200, 301, 302, 307, or 308")) };
+        if let Some(extra) = words.next() { return Err(format!("unexpected {extra:?} after the status")) }
+        if !to.starts_with('/') && (status == 200 || !(to.starts_with("http://") || to.starts_with("https://"))) {
+            return Err(format!("{to:?} should be a path starting with `/`{}", if status == 200 { " (rewrites can't go to other sites)" } else { ", or an http(s):// URL" }))
+        }
+        if to.bytes().any(|b| b.is_ascii_control()) { return Err(format!("invalid destination {to:?}")) }
+        Ok(Self { from: from.split('/').map(String::from).collect(), query, to: to.into(), status, force })
+    }
+}
+
+/// Match `pattern` against the (raw, decoded) `path` segments, capturing `:name`s and `*` (as `splat`) from the raw ones.
+fn matches(pattern: &[String], path: &[(&str, Option<String>)], captures: &mut Vec<(String, String)>) -> bool {
+    match pattern.split_first() {
+        None                                            => path.is_empty(),
+        Some((p, rest)) if p == "*" && rest.is_empty()  => {
+            captures.push(("splat".into(), path.iter().map(|(raw, _)| *raw).collect::<Vec<_>>().join("/")));
+            true
+        },
+        Some((p, rest)) if p.starts_with(':')           => match path.split_first() {
+            Some(((raw, _), path)) if !raw.is_empty()   => { captures.push((p[1..].into(), raw.to_string())); matches(rest, path, captures) },
+            _                                           => false,
+        },
+        Some((p, rest))                                 => match path.split_first() {
+            Some(((raw, decoded), path))                => crate::rules::matches_segment(p.as_bytes(), decoded.as_deref().unwrap_or(raw).as_bytes()) && matches(rest, path, captures),
+            None                                        => false,
+        },
+    }
+}
+
+/// Replace every `:name` in `to` that was captured.
+fn substitute(to: &str, captures: &[(String, String)]) -> String {
+    let mut out = String::new();
+    let mut rest = to;
+    while let Some(colon) = rest.find(':') {
+        out.push_str(&rest[..colon]);
+        let after = &rest[colon+1..];
+        let len = after.find(|ch: char| !(ch.is_ascii_alphanumeric() || ch == '_')).unwrap_or(after.len());
+        match captures.iter().rev().find(|(name, _)| *name == after[..len]) {
+            Some((_, value))    => out.push_str(value),
+            None                => { out.push(':'); out.push_str(&after[..len]); },
+        }
+        rest = &after[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+
+
+/// The root's [`FILE`], re-read whenever its listing (see [`crate::fs::dir::Cache`]) or stats change.
+#[derive(Default)] pub struct Cache {
+    loaded: Mutex<Option<(PathBuf, Stat, Arc<Rules>)>>,
+}
+
+impl Cache {
+    pub fn get(&self, root: &mount::Dir) -> Option<Arc<Rules>> {
+        let entry = root.snapshot().by_name(FILE).filter(|e| e.is_file())?;
+        let vfs = root.vfs_of(entry)?;
+        let stat = vfs.stat(entry.path())?;
+        let mut loaded = self.loaded.lock().expect("bug: Mutex poisoned");
+        if let Some((path, s, rules)) = &*loaded {
+            if path == entry.path() && *s == stat { return Some(Arc::clone(rules)) }
+        }
+
+        let mut text = String::new();
+        let (rules, errors) = match vfs.read_range(entry.path(), 0 .. stat.len.min(MAX_FILE_SIZE)).and_then(|mut r| r.read_to_string(&mut text)) {
+            Ok(_)       => Rules::parse(entry.path(), &text),
+            Err(err)    => (Rules::default(), vec![format!("unable to read `{}`: {err}", entry.path().display())]),
+        };
+        let rules = Arc::new(rules);
+        *loaded = Some((entry.path().into(), stat, Arc::clone(&rules)));
+        for err in errors { eprintln!("warning: {err}"); } // once per (path, stat): requests reuse `loaded` until either changes
+        Some(rules)
+    }
+}
+
+
+
+#[test] fn check_redirects() {
+    let (rules, errors) = Rules::parse(Path::new("_redirects"), "
+# comment
+/old.html                   /new.html
+/docs/*                     /manual/:splat          302
+/blog/:year/:slug           /posts/:year-:slug      308
+/store      id=:id          /products/:id           301
+/store      kind=hat        /hats                   307
+/app/*                      /app/index.html         200
+/legacy/*                   https://example.com/:splat  301!  # forced
+/bad                        /elsewhere              404
+/worse
+/rewrite                    https://example.com/    200
+");
+    assert_eq!(errors.len(), 3, "{errors:?}");
+    let never = || -> bool { panic!("shouldn't need to check") };
+    let redirect = |status, to: &str| Some(Action::Redirect(status, to.into()));
+
+    assert_eq!(rules.apply("/old.html",         "",         || false),  redirect(301, "/new.html"));
+    assert_eq!(rules.apply("/old.html",         "",         || true),   None, "shadowed by an existing file");
+    assert_eq!(rules.apply("/old.html",         "?a=b",     || false),  redirect(301, "/new.html?a=b"));
+    assert_eq!(rules.apply("/docs/a/b%20c.md",  "",         || false),  redirect(302, "/manual/a/b%20c.md"));
+    assert_eq!(rules.apply("/docs",             "",         || false),  redirect(302, "/manual/"));
+    assert_eq!(rules.apply("/blog/2023/hi",     "",         || false),  redirect(308, "/posts/2023-hi"));
+    assert_eq!(rules.apply("/blog/2023/",       "",         never),     None);
+    assert_eq!(rules.apply("/store",            "?id=42&x", || false),  redirect(301, "/products/42"));
+    assert_eq!(rules.apply("/store",            "?kind=hat",|| false),  redirect(307, "/hats"));
+    assert_eq!(rules.apply("/store",            "?kind=cap",never),     None);
+    assert_eq!(rules.apply("/app/some/route",   "?q",       || false),  Some(Action::Rewrite("/app/index.html".into(), "?q".into())));
+    assert_eq!(rules.apply("/legacy/x",         "",         never),     redirect(301, "https://example.com/x"));
+    assert_eq!(rules.apply("/nope",             "",         never),     None);
+
+    assert_eq!(substitute("https://host:8080/:a/:b", &[("a".into(), "1".into())]), "https://host:8080/1/:b");
+}
+
+#[test] fn check_cache() {
+    let settings = crate::serve::memory_settings(&[(FILE, b"bogus\n/a /b")]);
+    let cache = Cache::default();
+    let get = || cache.get(&settings.mounts.root(&settings.cache).unwrap()).unwrap();
+
+    let first = get();
+    assert_eq!(first.apply("/a", "", || false), Some(Action::Redirect(301, "/b".into())));
+    assert!(Arc::ptr_eq(&first, &get()), "unchanged: not re-read (or warned about) again");
+}
+
+#[test] fn check_served_redirects() {
+    use crate::serve::{memory_settings, request};
+    let settings = memory_settings(&[("index.html", b"<p>hi</p>"), ("sub/a.txt", b"abcdef"), (FILE, b"/old /sub/a.txt 302\n/spa/* /index.html 200\n/sub/* /elsewhere 301\n")]);
+
+    let res = request(&settings, "GET", "/old?q", &[], b"");
+    assert_eq!((res.status, res.header("Location")), (302, Some("/sub/a.txt?q")));
+    assert_eq!(request(&settings, "GET", "/spa/some/route", &[], b"").body, b"<p>hi</p>", "rewritten");
+    assert_eq!(request(&settings, "GET", "/sub/a.txt", &[], b"").status, 200, "shadowed by an existing file");
+    assert_eq!(request(&settings, "GET", "/sub/missing.txt", &[], b"").status, 301);
+    assert_eq!(request(&settings, "GET", "/old", &[("Expect", "bogus")], b"").status, 417, "validated before redirecting");
+    assert_eq!(request(&settings, "GET", "/_redirects", &[], b"").status, 404, "never served");
+}
@@ -0,0 +1,46 @@
+use crate::body;
+use std::io::Read;
+
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)] pub enum Version {
+    Http10,
+    Http11,
+    Http2,
+}
+
+impl Version {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+            Version::Http2  => "HTTP/2",
+        }
+    }
+}
+
+/// A parsed request, independent of which HTTP version it arrived over.
+pub struct Request<'r> {
+    pub method:     &'r [u8],
+    pub path:       &'r str,    // excluding any query string
+    pub search:     &'r str,    // "" or "?..."
+    pub version:    Version,
+    pub headers:    Vec<(&'r str, &'r str)>,
+}
+
+impl<'r> Request<'r> {
+    /// The first header named `name` (case insensitive.)
+    pub fn header(&self, name: &str) -> Option<&'r str> {
+        self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|&(_, v)| v)
+    }
+}
+
+/// Where the request body comes from.  Handlers only open the body once they've decided to accept the request.
+pub trait RequestBody {
+    /// Start reading the request body, rejecting it if it's known to exceed `limit` bytes.  Reads that would exceed
+    /// `limit` fail with an error satisfying [`body::is_too_large`].
+    ///
+    /// If the client sent `Expect: 100-continue`, this is what sends `100 Continue` - so don't call it until you're
+    /// sure you want the body.
+    fn open(&mut self, limit: u64) -> Result<Box<dyn Read + '_>, body::Error>;
+}
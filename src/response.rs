@@ -1,24 +1,115 @@
-use std::io::Write;
-use std::net::{TcpStream, Shutdown};
+use crate::*;
 
-pub fn bad_request(stream: &mut TcpStream)                  { respond_4xx(stream, b"HTTP/1.0 400 Bad Request\r\n\r\n") }
-pub fn not_found(stream: &mut TcpStream)                    { respond_4xx(stream, b"HTTP/1.0 404 Not Found\r\n\r\n") }
-pub fn bad_method(stream: &mut TcpStream)                   { respond_4xx(stream, b"HTTP/1.0 405 Method Not Allowed\r\n\r\n") }
-pub fn request_too_large(stream: &mut TcpStream)            { respond_4xx(stream, b"HTTP/1.0 413 Request Too Large\r\n\r\n") }
+use std::io::{self, Write};
 
-pub fn http_version_not_supported(stream: &mut TcpStream)   { respond_5xx(stream, b"HTTP/1.0 505 HTTP Version Not Supported\r\n\r\n") }
-pub fn internal_server_error(stream: &mut TcpStream)        { respond_5xx(stream, b"HTTP/1.0 500 Internal Server Error\r\n\r\n") }
 
-fn respond_4xx(stream: &mut TcpStream, error: &[u8]) {
-    debug_assert!(error.starts_with(b"HTTP/1.0 4"));
-    debug_assert!(error.ends_with(b"\r\n\r\n"));
-    if stream.write_all(error).is_err() { return }
-    if stream.shutdown(Shutdown::Both).is_err() { return }
+
+/// Where a response goes, independent of which HTTP version it's sent over.
+///
+/// Call [`Response::head`] exactly once, write the body (if any), then [`Response::finish`].
+pub trait Response: Write {
+    /// Send the status + headers.  `len` is the exact length of the body that follows, or `None` to stream a body of
+    /// unknown length (chunked for HTTP/1.1, close-delimited for HTTP/1.0, DATA frames for HTTP/2.)
+    ///
+    /// Framing and connection-management headers (`Content-Length`, `Transfer-Encoding`, `Connection`) are the
+    /// transport's responsibility, and shouldn't be included in `headers`.
+    fn head(&mut self, status: u16, headers: &[(&str, &str)], len: Option<u64>) -> io::Result<()>;
+
+    /// Write `len` bytes of `file` starting at `offset` as (part of) the body.  Transports that can hand the copy off to
+    /// the kernel override this (see [`sendfile`].)
+    fn send_file(&mut self, file: &std::fs::File, offset: u64, len: u64) -> io::Result<()> {
+        sendfile::copy(file, offset, len, self)
+    }
+
+    /// Finish the body and flush everything to the client.
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+pub fn reason(status: u16) -> &'static str {
+    match status {
+        100 => "Continue",
+        101 => "Switching Protocols",
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        206 => "Partial Content",
+        207 => "Multi-Status",
+        300 => "Multiple Choices",
+        301 => "Moved Permanently",
+        302 => "Found",
+        304 => "Not Modified",
+        307 => "Temporary Redirect",
+        308 => "Permanent Redirect",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        406 => "Not Acceptable",
+        409 => "Conflict",
+        413 => "Request Too Large",
+        416 => "Range Not Satisfiable",
+        417 => "Expectation Failed",
+        431 => "Request Header Fields Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        505 => "HTTP Version Not Supported",
+        _   => "",
+    }
+}
+
+/// 300 with links to each of `hrefs`, which must already be percent-encoded (and thus safe to embed in HTML as-is.)
+pub fn multiple_choices(res: &mut dyn Response, hrefs: &[String]) {
+    let mut body = String::from("<!DOCTYPE html>\n<title>300 Multiple Choices</title>\n<ul>\n");
+    for href in hrefs { body.push_str(&format!("<li><a href=\"{href}\">{href}</a></li>\n")); }
+    body.push_str("</ul>\n");
+    if res.head(300, &[("Content-Type", "text/html; charset=utf-8")], Some(body.len() as u64)).is_err() { return }
+    if res.write_all(body.as_bytes()).is_err() { return }
+    let _ = res.finish();
+}
+
+pub fn moved_permanently(res: &mut dyn Response, location: &str) { redirect(res, 301, location) }
+
+/// 301, 302, 307, or 308 to `location`, which must already be percent-encoded.
+pub fn redirect(res: &mut dyn Response, status: u16, location: &str) {
+    if res.head(status, &[("Location", location)], Some(0)).is_err() { return }
+    let _ = res.finish();
+}
+
+pub fn bad_request(res: &mut dyn Response)                  { respond_4xx(res, 400) }
+pub fn not_found(res: &mut dyn Response)                    { respond_4xx(res, 404) }
+pub fn bad_method(res: &mut dyn Response)                   { respond_4xx(res, 405) }
+pub fn not_acceptable(res: &mut dyn Response)               { respond_4xx(res, 406) }
+pub fn request_too_large(res: &mut dyn Response)            { respond_4xx(res, 413) }
+pub fn expectation_failed(res: &mut dyn Response)           { respond_4xx(res, 417) }
+pub fn request_header_fields_too_large(res: &mut dyn Response) { respond_4xx(res, 431) }
+
+/// 413 with a short explanation, so clients see *why* their request failed.
+pub fn body_too_large(res: &mut dyn Response, limit: u64) {
+    let body = format!("413 Request Too Large: request body exceeds the {limit} byte limit\n");
+    if res.head(413, &[("Content-Type", "text/plain; charset=utf-8")], Some(body.len() as u64)).is_err() { return }
+    if res.write_all(body.as_bytes()).is_err() { return }
+    let _ = res.finish();
+}
+
+/// 416 with the `Content-Range` the client should've stayed within.
+pub fn range_not_satisfiable(res: &mut dyn Response, len: u64) {
+    let content_range = format!("bytes */{len}");
+    if res.head(416, &[("Content-Range", &content_range)], Some(0)).is_err() { return }
+    let _ = res.finish();
+}
+
+pub fn http_version_not_supported(res: &mut dyn Response)   { respond_5xx(res, 505) }
+pub fn internal_server_error(res: &mut dyn Response)        { respond_5xx(res, 500) }
+pub fn not_implemented(res: &mut dyn Response)              { respond_5xx(res, 501) }
+
+fn respond_4xx(res: &mut dyn Response, status: u16) {
+    debug_assert!((400 .. 500).contains(&status));
+    if res.head(status, &[], Some(0)).is_err() { return }
+    let _ = res.finish();
 }
 
-fn respond_5xx(stream: &mut TcpStream, error: &[u8]) {
-    debug_assert!(error.starts_with(b"HTTP/1.0 5"));
-    debug_assert!(error.ends_with(b"\r\n\r\n"));
-    if stream.write_all(error).is_err() { return }
-    if stream.shutdown(Shutdown::Both).is_err() { return }
+fn respond_5xx(res: &mut dyn Response, status: u16) {
+    debug_assert!((500 .. 600).contains(&status));
+    if res.head(status, &[], Some(0)).is_err() { return }
+    let _ = res.finish();
 }
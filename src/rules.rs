@@ -0,0 +1,164 @@
+//! `--allow` / `--deny` rules deciding which paths under the webroot exist as far as clients are concerned.
+//!
+//! Patterns are matched against root-relative paths (`.well-known/security.txt`) using gitignore-ish globs:
+//!
+//! | Pattern           | Matches                                                                   |
+//! | ------------------| --------------------------------------------------------------------------|
+//! | `*.pdb`           | any `*.pdb` at any depth (no `/` = match the name alone)                  |
+//! | `/notes.txt`      | only the top level `notes.txt` (a leading `/` anchors the pattern)        |
+//! | `docs/*.md`       | `docs/a.md` but not `docs/sub/b.md` (any inner `/` anchors the pattern)   |
+//! | `.well-known/**`  | `.well-known` itself and everything under it                              |
+//! | `target/`         | directories named `target` (a trailing `/` matches directories only)      |
+//!
+//! The last matching rule wins, and a path is hidden if it or any directory it's in is denied.  Like gitignore, that
+//! means a file can't be re-allowed if its directory is denied.
+
+use std::io;
+use std::path::Path;
+
+
+
+/// Read from the webroot at startup, if it exists.  Lines are patterns to deny, or `!pattern`s to allow.
+pub const IGNORE_FILE : &str = ".mmuhttpdignore";
+
+pub struct Rules {
+    rules: Vec<Rule>, // last match wins
+}
+
+struct Rule {
+    allow:      bool,
+    dir_only:   bool,
+    segments:   Vec<String>, // `**` matches any number of segments
+}
+
+impl Default for Rules {
+    /// Hide dotfiles and dot directories (`.git`, `.env`, ...)
+    fn default() -> Self { Self { rules: vec![Rule::parse(false, ".*").expect("bug: invalid built-in rule")] } }
+}
+
+impl Rules {
+    pub fn allow(&mut self, pattern: &str) -> Result<(), String> { self.rules.push(Rule::parse(true,  pattern)?); Ok(()) }
+    pub fn deny (&mut self, pattern: &str) -> Result<(), String> { self.rules.push(Rule::parse(false, pattern)?); Ok(()) }
+
+    /// Hide the webroot's own configuration files (`_headers`, `_redirects`) for good, at the root of every mount (see
+    /// [`crate::mount::Mounts::prefixes`]): call this after any other rules.
+    pub fn deny_config_files(&mut self, prefixes: &[String]) {
+        for prefix in prefixes {
+            for file in [crate::headers::FILE, crate::redirects::FILE] { self.deny(&format!("{prefix}{file}")).expect("bug: invalid built-in rule"); }
+        }
+    }
+
+    /// Load an [`IGNORE_FILE`].
+    pub fn load(&mut self, path: &Path) -> io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        self.parse(path, &text)
+    }
+
+    /// Parse the contents of an [`IGNORE_FILE`] found at `path`.
+    pub fn parse(&mut self, path: &Path, text: &str) -> io::Result<()> {
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') { continue }
+            let result = match line.strip_prefix('!') { Some(pattern) => self.allow(pattern), None => self.deny(line) };
+            if let Err(err) = result { return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}:{}: {err}", path.display(), line_no + 1))) }
+        }
+        Ok(())
+    }
+
+    /// Is the root-relative `path` visible, considering only rules matching `path` itself?
+    /// Use this when `path`'s parent directories are already known to be allowed (e.g. while listing a directory.)
+    pub fn allows(&self, path: &str, is_dir: bool) -> bool {
+        let path = path.trim_matches('/');
+        let segments = path.split('/').collect::<Vec<_>>();
+        self.rules.iter().rev().find(|r| r.matches(&segments, is_dir)).map_or(true, |r| r.allow)
+    }
+
+    /// Is the root-relative `path`, and every directory leading up to it, visible?
+    pub fn allows_all(&self, path: &str, is_dir: bool) -> bool {
+        let path = path.trim_matches('/');
+        let mut dirs = path.match_indices('/').map(|(i, _)| &path[..i]);
+        dirs.all(|dir| self.allows(dir, true)) && self.allows(path, is_dir)
+    }
+}
+
+impl Rule {
+    fn parse(allow: bool, pattern: &str) -> Result<Self, String> {
+        let trimmed = pattern.trim();
+        let dir_only = trimmed.ends_with('/');
+        let trimmed = trimmed.trim_end_matches('/');
+        let anchored = trimmed.contains('/');
+        let trimmed = trimmed.trim_start_matches('/');
+        if trimmed.is_empty() || trimmed.split('/').any(|s| s.is_empty()) { return Err(format!("invalid pattern {pattern:?}")) }
+
+        let mut segments = Vec::new();
+        if !anchored { segments.push(String::from("**")); }
+        segments.extend(trimmed.split('/').map(String::from));
+        Ok(Self { allow, dir_only, segments })
+    }
+
+    fn matches(&self, path: &[&str], is_dir: bool) -> bool {
+        (is_dir || !self.dir_only) && matches(&self.segments, path)
+    }
+}
+
+fn matches(pattern: &[String], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None                                    => path.is_empty(),
+        Some((p, rest)) if p == "**"            => (0 ..= path.len()).any(|skip| matches(rest, &path[skip..])),
+        Some((p, rest))                         => path.split_first().map_or(false, |(s, path)| matches_segment(p.as_bytes(), s.as_bytes()) && matches(rest, path)),
+    }
+}
+
+/// `*` matches any run of bytes, `?` any single byte.
+pub fn matches_segment(pattern: &[u8], name: &[u8]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack = None; // (pattern index after the last `*`, name index it's currently matched up to)
+    while n < name.len() {
+        match pattern.get(p) {
+            Some(b'*')                          => { backtrack = Some((p + 1, n)); p += 1; },
+            Some(&c) if c == b'?' || c == name[n] => { p += 1; n += 1; },
+            _ => match backtrack {
+                Some((bp, bn))                  => { backtrack = Some((bp, bn + 1)); p = bp; n = bn + 1; },
+                None                            => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == b'*')
+}
+
+
+
+#[test] fn check_rules() {
+    let mut rules = Rules::default();
+    assert!(!rules.allows_all(".git/config",               false));
+    assert!(!rules.allows_all("sub/.env",                  false));
+    assert!( rules.allows_all("sub/index.html",            false));
+
+    rules.allow(".well-known/**").unwrap();
+    rules.deny("**/*.pdb").unwrap();
+    rules.deny("target/").unwrap();
+    rules.deny("/notes.txt").unwrap();
+    assert!( rules.allows_all(".well-known/security.txt",  false));
+    assert!( rules.allows_all(".well-known/",              true));
+    assert!(!rules.allows_all(".git/config",               false));
+    assert!(!rules.allows_all("app.pdb",                   false));
+    assert!(!rules.allows_all("bin/x64/app.pdb",           false));
+    assert!(!rules.allows_all("sub/target/debug/app.exe",  false));
+    assert!( rules.allows_all("sub/target",                false), "target/ only matches directories");
+    assert!(!rules.allows_all("notes.txt",                 false));
+    assert!( rules.allows_all("sub/notes.txt",             false));
+
+    rules.deny_config_files(&["/".into(), "/deep/pkg/".into()]);
+    assert!(!rules.allows_all("_headers",                  false));
+    assert!(!rules.allows_all("deep/pkg/_redirects",       false));
+    assert!( rules.allows_all("deep/_redirects",           false));
+    assert!( rules.allows_all("sub/_headers",              false));
+
+    assert!( matches_segment(b"*.tar.gz",   b"a.tar.gz"));
+    assert!( matches_segment(b"a?c*",       b"abc"));
+    assert!(!matches_segment(b"*.pdb",      b"pdb"));
+    assert!( matches_segment(b"*a*b",       b"xaxxab"));
+
+    assert!(Rule::parse(false, "a//b").is_err());
+    assert!(Rule::parse(false, "/").is_err());
+}
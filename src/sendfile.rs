@@ -0,0 +1,56 @@
+//! Zero-copy file → socket transfers via Linux's [`sendfile(2)`](https://man7.org/linux/man-pages/man2/sendfile.2.html),
+//! with a plain userspace [`copy`] for everything else.
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::net::TcpStream;
+
+
+
+/// Send `len` bytes of `file` starting at `offset` straight to `socket` without bouncing them through userspace.
+///
+/// Fails with [`io::ErrorKind::Unsupported`] *before sending anything* if the kernel can't do this for `file` (or
+/// this isn't Linux), in which case the caller should fall back to [`copy`].
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub fn sendfile(socket: &TcpStream, file: &File, offset: u64, len: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    extern "C" { fn sendfile(out_fd: i32, in_fd: i32, offset: *mut i64, count: usize) -> isize; } // off_t = i64 on 64-bit
+    const EINTR     : i32 = 4;
+    const EINVAL    : i32 = 22;
+    const ENOSYS    : i32 = 38;
+    const MAX_COUNT : u64 = 0x7fff_f000; // the most sendfile will transfer in one call regardless
+
+    let Ok(mut pos) = i64::try_from(offset) else { return Err(io::ErrorKind::InvalidInput.into()) };
+    let mut remaining = len;
+    while remaining > 0 {
+        let sent = unsafe { sendfile(socket.as_raw_fd(), file.as_raw_fd(), &mut pos, remaining.min(MAX_COUNT) as usize) };
+        match sent {
+            0           => return Err(io::ErrorKind::UnexpectedEof.into()), // file shrank out from under us
+            1 ..        => remaining -= sent as u64,
+            _           => {
+                let err = io::Error::last_os_error();
+                match err.raw_os_error() {
+                    Some(EINTR)                                 => continue,
+                    Some(EINVAL | ENOSYS) if remaining == len   => return Err(io::ErrorKind::Unsupported.into()),
+                    _                                           => return Err(err),
+                }
+            },
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", target_pointer_width = "64")))]
+pub fn sendfile(_socket: &TcpStream, _file: &File, _offset: u64, _len: u64) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+/// Write `len` bytes of `file` starting at `offset` to `out` the portable way.
+pub fn copy(file: &File, offset: u64, len: u64, out: &mut (impl Write + ?Sized)) -> io::Result<()> {
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    let copied = io::copy(&mut BufReader::new(file.take(len)), out)?;
+    if copied < len { return Err(io::ErrorKind::UnexpectedEof.into()) } // file shrank out from under us
+    Ok(())
+}
@@ -0,0 +1,501 @@
+use crate::*;
+use crate::request::*;
+use crate::response::Response;
+
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::sync::Arc;
+use std::io::Read;
+
+
+
+/// Handle a single request, regardless of which HTTP version it arrived over.
+pub fn serve(settings: &Settings, req: &Request, body: &mut dyn RequestBody, res: &mut dyn Response) {
+    let cors = settings.cors.as_ref().map_or(Vec::new(), |cors| cors.headers(req));
+    let mut extra = settings.headers.matching(req.path); // `_headers` / `--header`: for errors too
+    extra.extend(cors.iter().map(|(name, value)| (*name, value.as_str())));
+    if extra.is_empty() {
+        if let Some(reject) = unacceptable(req) { return reject(res) }
+        return route(settings, req, body, res);
+    }
+    let res = &mut headers::WithHeaders { inner: res, extra };
+    if let Some(reject) = unacceptable(req) { return reject(res) }
+    if settings.cors.as_ref().map_or(false, |cors| cors.is_preflight(req)) { // N.B. on any path, before _redirects or WebDAV get a say
+        if res.head(204, &[], Some(0)).is_err() { return }
+        let _ = res.finish();
+        return;
+    }
+    route(settings, req, body, res)
+}
+
+/// Reject requests whose body we couldn't accept as sent, before anything (a redirect, a preflight...) answers them.
+fn unacceptable(req: &Request) -> Option<fn(&mut dyn Response)> {
+    match req.header("Expect") {
+        None => {},
+        Some(expect) if expect.eq_ignore_ascii_case("100-continue") => {}, // see RequestBody::open
+        Some(_) => return Some(response::expectation_failed),
+    }
+    let len = req.header("Content-Length")?;
+    if len.is_empty() || !len.bytes().all(|b| b.is_ascii_digit()) { return Some(response::bad_request) }
+    None
+}
+
+/// Apply the root's `_redirects` (if any) before looking for files.
+fn route(settings: &Settings, req: &Request, body: &mut dyn RequestBody, res: &mut dyn Response) {
+    let rules = settings.mounts.root(&settings.cache).and_then(|root| settings.redirects.get(&root));
+    match rules.and_then(|rules| rules.apply(req.path, req.search, || exists(settings, req.path))) {
+        None                                            => respond(settings, req, body, res),
+        Some(redirects::Action::Redirect(status, to))   => response::redirect(res, status, &to),
+        Some(redirects::Action::Rewrite(path, search))  => {
+            let rewritten = Request { path: &path, search: &search, headers: req.headers.clone(), .. *req };
+            respond(settings, &rewritten, body, res) // N.B. just once: no loops
+        },
+    }
+}
+
+/// Would the URL `path` be served from a file?  (Roughly - only exact names, no multiviews.)
+fn exists(settings: &Settings, path: &str) -> bool {
+    let Some(mut dir) = settings.mounts.root(&settings.cache) else { return false };
+    let trimmed = path.trim_matches('/');
+    let mut segments = Vec::new();
+    for segment in trimmed.split('/').filter(|_| !trimmed.is_empty()) {
+        let Some(segment) = percent::decode_segment(segment) else { return false };
+        segments.push(segment);
+    }
+    if path.ends_with('/') || segments.is_empty() { segments.push(OsString::from("index.html")); }
+    let lossy_path = segments.iter().map(|s| s.to_string_lossy()).collect::<Vec<_>>().join("/");
+    if !settings.rules.allows_all(&lossy_path, false) { return false }
+    let Some(file) = segments.pop() else { return false };
+    for name in segments {
+        let Some(child) = dir.child(&name) else { return false };
+        dir = child;
+    }
+    dir.snapshot().by_name(&file).map_or(false, |e| e.is_file())
+}
+
+fn respond(settings: &Settings, req: &Request, body: &mut dyn RequestBody, res: &mut dyn Response) {
+    let Request { method, path, .. } = *req;
+    let depth = match req.header("Depth") {
+        None | Some("infinity") => None,
+        Some(val) => match val.parse::<u8>() { Ok(val) => Some(val), Err(_) => return response::bad_request(res) },
+    };
+
+    if !path.starts_with('/') { return response::not_found(res) }
+
+    // TODO: escape hatches for magic paths (keepalive requests?)
+
+    if path.contains("//") { return response::not_found(res) } // XXX: excessive validation?
+    if path.contains('\\') { return response::not_found(res) } // XXX: excessive validation?
+    let is_dir = path.ends_with('/');
+    let trimmed_path = path.trim_matches('/');
+
+    // XXX: this is a half-baked safety feature: by enumerating the filesystem for existing paths instead of directly
+    // passing user-controlled paths to system APIs, we hopefully avoid allowing the user to (ab)use system specific
+    // escape hatches like UNC paths, `CON` files, etc. - we also get consistent case sensitive paths on multiple OSes
+    // regardless of the case sensitivity of the underlying OS or filesystem.
+    let mut segments = Vec::new(); // exact file names, which needn't be UTF-8
+    for segment in trimmed_path.split('/').filter(|_| !trimmed_path.is_empty()) {
+        let Some(segment) = percent::decode_segment(segment) else { return response::not_found(res) }; // bans ".", "..", ...
+        segments.push(segment);
+    }
+    let lossy_path = segments.iter().map(|s| s.to_string_lossy()).collect::<Vec<_>>().join("/");
+    if !settings.rules.allows_all(&lossy_path, is_dir) { return response::not_found(res) } // ".git", "node_modules", ...
+
+    let Some(mut dir) = settings.mounts.root(&settings.cache) else { return response::internal_server_error(res) };
+    let file = match segments.pop() {
+        Some(file) if !is_dir   => file,
+        Some(dir)               => { segments.push(dir); OsString::from("index.html") },
+        None                    => OsString::from("index.html"),
+    };
+    let mut href = String::from("/");   // the canonical spelling of the path so far (differs per --case-insensitive)
+    let mut dir_path = String::new();   // ditto, but decoded for --allow/--deny
+    let mut corrected = false;
+    for (i, name) in segments.iter().enumerate() {
+        let snapshot = Arc::clone(dir.snapshot());
+        let entry = match find(settings, &snapshot, &dir_path, name) {
+            Ok(Some(entry))     => entry,
+            Ok(None)            => return response::not_found(res),
+            Err(candidates)     => {
+                let rest = segments[i+1..].iter().chain(Some(&file).filter(|_| !is_dir));
+                return response::multiple_choices(res, &alternatives(&href, &candidates, rest, is_dir));
+            },
+        };
+        corrected |= entry.name_os() != &**name;
+        percent::encode_segment(entry.name_os(), &mut href);
+        href.push('/');
+        dir_path.push_str(entry.name_lossy());
+        dir_path.push('/');
+        if !settings.rules.allows(&dir_path, true) { return response::not_found(res) }
+        let Some(child) = dir.child(entry.name_os()) else { return response::not_found(res) };
+        dir = child;
+    }
+    let snapshot = Arc::clone(dir.snapshot());
+    if is_dir && corrected && settings.case_redirect { return response::moved_permanently(res, &format!("{href}{}", req.search)) }
+
+    if let (true, Some(format)) = (is_dir, download::Format::from_search(req.search)) {
+        let Ok(format) = format else { return response::bad_request(res) };
+        if !matches!(method, b"GET" | b"HEAD") { return response::bad_method(res) }
+        let name = dir_path.trim_end_matches('/').rsplit('/').next().filter(|n| !n.is_empty()).unwrap_or("site");
+        let filename = name.chars().map(|ch| if ch.is_ascii_alphanumeric() || "-_.".contains(ch) { ch } else { '_' }).collect::<String>();
+        let disposition = format!("attachment; filename=\"{filename}.{}\"", format.extension());
+        if res.head(200, &[("Content-Type", format.content_type()), ("Content-Disposition", &disposition)], None).is_err() { return }
+        if method == b"GET" && download::write(&mut &mut *res, settings, name, &dir_path, dir, format).is_err() { return }
+        if res.finish().is_err() { return }
+        return;
+    }
+
+    match method {
+        _ if !is_dir || !settings.webdav => {},
+        b"OPTIONS" => {
+            if res.head(204, &[("Allow", "OPTIONS, PROPFIND, GET, HEAD")], Some(0)).is_err() { return }
+            if res.finish().is_err() { return }
+            return;
+        },
+        b"PROPFIND" => {
+            if discard_body(settings, body, res).is_err() { return } // N.B. we always answer as if for `<allprop/>`
+            if res.head(207, &[("Content-Type", "application/xml; charset=\"utf-8\"")], None).is_err() { return }
+            if webdav::respond_propfind_dir(&mut &mut *res, settings, &href, dir_path.trim_end_matches('/'), dir, depth).is_err() { return }
+            if res.finish().is_err() { return }
+            return;
+        },
+        _ => {},
+    }
+
+    let is_allowed = |e: &fs::dir::Entry| settings.rules.allows(&format!("{dir_path}{}", e.name_lossy()), e.is_dir());
+    let mut variants;
+    let mut negotiated = None;
+    let file_entry = match find(settings, &snapshot, &dir_path, &file) {
+        Ok(Some(entry)) => {
+            if !is_dir && (corrected || entry.name_os() != file) && settings.case_redirect {
+                percent::encode_segment(entry.name_os(), &mut href);
+                return response::moved_permanently(res, &format!("{href}{}", req.search));
+            }
+            entry
+        },
+        Err(candidates) => return response::multiple_choices(res, &alternatives(&href, &candidates, None, false)),
+        Ok(None) if settings.multiviews && matches!(method, b"GET" | b"HEAD") => {
+            variants = negotiate::variants(&snapshot, &file.to_string_lossy(), &settings.mime);
+            variants.retain(|v| is_allowed(v.entry));
+            if variants.is_empty() { return response::not_found(res) }
+            let Some(variant) = negotiate::choose(&variants, req.header("Accept"), req.header("Accept-Language")) else { return response::not_acceptable(res) };
+            negotiated = Some((variant, negotiate::vary(&variants)));
+            variant.entry
+        },
+        Ok(None) => return response::not_found(res),
+    };
+    if !is_allowed(file_entry) { return response::not_found(res) } // e.g. a denied index.html
+    let path = file_entry.path();
+    let Some(vfs) = dir.vfs_of(file_entry) else { return response::not_found(res) };
+    let Some(stat) = vfs.stat(path) else { return response::not_found(res) };
+
+    // N.B. small files are read in their entirety (and cached), larger ones are streamed
+    let cached = settings.files.get(path, &stat);
+    let mut content = None;
+    let mut stream = None;
+    if cached.is_none() {
+        if settings.files.wants(stat.len) {
+            let mut buf = Vec::with_capacity(stat.len as usize);
+            match vfs.read_range(path, 0 .. stat.len).and_then(|mut r| r.read_to_end(&mut buf)) {
+                Ok(_)                                                       => content = Some(buf),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound      => return response::not_found(res),
+                Err(_)                                                      => return response::internal_server_error(res),
+            }
+        } else {
+            let Ok(opened) = vfs.open(path) else { return response::not_found(res) };
+            stream = Some(opened);
+        }
+    }
+
+    let ext_mime = negotiated.map(|(variant, _)| variant.mime).or_else(|| settings.mime.by_extension(file_entry.name_lossy()));
+    let (mime, sniffed) = match &cached {
+        Some(cached) => (cached.content_type.clone(), cached.sniffed),
+        None => {
+            let mut buf = Vec::new();
+            let head = match (&content, &stream) {
+                (Some(content), _) => &content[..content.len().min(mime::SNIFF_LEN)],
+                (None, Some(_)) if needs_head(settings, ext_mime) => { // N.B. opened separately: streams can't seek back
+                    let head = vfs.read_range(path, 0 .. stat.len.min(mime::SNIFF_LEN as u64)).and_then(|mut r| r.read_to_end(&mut buf));
+                    if head.is_err() { return response::internal_server_error(res) }
+                    &buf[..]
+                },
+                _ => &[],
+            };
+            let Some((mime, sniffed)) = content_type(settings, ext_mime, head) else { return response::not_found(res) }; // ban access anything without a mime
+            (mime.into_owned(), sniffed)
+        },
+    };
+    let cached = match (cached, content) {
+        (Some(cached), _)       => Some(cached),
+        (None, Some(content))   => Some(settings.files.insert(path, &stat, vfs.etag(path, &stat), content, mime.clone(), sniffed)),
+        (None, None)            => None,
+    };
+
+    let len = stat.len;
+    let etag = match &cached { Some(cached) => Cow::Borrowed(cached.etag.as_str()), None => Cow::Owned(vfs.etag(path, &stat)) };
+    let range = match req.header("Range") {
+        Some(range) if req.header("If-Range").map_or(true, |ir| ir == &*etag) => byte_range(range, len),
+        _ => None,
+    };
+    let compressible = len >= compress::MIN_SIZE && compress::is_compressible(&mime);
+    let encoding = if compressible && range.is_none() { req.header("Accept-Encoding").and_then(compress::Encoding::negotiate) } else { None };
+    let etag = match encoding { // each encoding is a distinct representation, and needs a distinct strong ETag
+        Some(encoding)  => Cow::Owned(format!("{}-{}\"", etag.trim_end_matches('"'), encoding.name())),
+        None            => etag,
+    };
+    let mut headers = vec![("Content-Type", mime.as_str()), ("ETag", &*etag), ("Accept-Ranges", "bytes")];
+    if sniffed { headers.push(("X-Content-Type-Options", "nosniff")); } // we already did the guesswork, don't second guess it
+    let vary = match (negotiated.map(|(_, vary)| vary), compressible) {
+        (Some(vary), true)  => Some(format!("{vary}, Accept-Encoding")),
+        (Some(vary), false) => Some(vary.into()),
+        (None, true)        => Some("Accept-Encoding".into()),
+        (None, false)       => None,
+    };
+    if let Some(vary) = vary.as_deref() { headers.push(("Vary", vary)); }
+    if let Some(language) = negotiated.and_then(|(v, _)| v.language) { headers.push(("Content-Language", language)); }
+
+    if !matches!(method, b"GET" | b"HEAD") { return response::bad_method(res) }
+    if req.header("If-None-Match").map_or(false, |inm| inm.split(',').map(|t| t.trim()).any(|t| t == "*" || t.trim_start_matches("W/") == &*etag)) {
+        headers.retain(|(name, _)| !name.starts_with("Content-"));
+        if res.head(304, &headers, Some(0)).is_err() { return }
+        if res.finish().is_err() { return }
+        return;
+    }
+
+    let precompressed = encoding.and_then(|encoding| vfs.precompressed(path, encoding));
+    let compressed = match (&precompressed, &cached, encoding) {
+        (None, Some(cached), Some(encoding)) => match settings.files.compressed(cached, encoding) {
+            Ok(compressed)  => Some(compressed),
+            Err(_)          => return response::internal_server_error(res),
+        },
+        _ => None,
+    };
+    let compressed = precompressed.as_deref().or_else(|| compressed.as_deref().map(|c| &c[..]));
+    if let Some(encoding) = encoding { headers.push(("Content-Encoding", encoding.name())); }
+    let (status, range) = match range {
+        None                => (200, 0 .. len),
+        Some(Ok(range))     => (206, range),
+        Some(Err(()))       => return response::range_not_satisfiable(res, len),
+    };
+    let content_range = format!("bytes {}-{}/{len}", range.start, range.end.saturating_sub(1));
+    if status == 206 { headers.push(("Content-Range", &content_range)); }
+    let len = match (&compressed, encoding) {
+        (Some(compressed), _)   => Some(compressed.len() as u64),
+        (None, Some(_))         => None, // compressed on the fly
+        (None, None)            => Some(range.end - range.start),
+    };
+
+    if res.head(status, &headers, len).is_err() { return }
+    if method == b"GET" {
+        let result = match (&cached, &compressed, stream, encoding) {
+            (_, Some(compressed), _, _)             => res.write_all(compressed),
+            (Some(cached), None, _, None)           => res.write_all(&cached.content[range.start as usize .. range.end as usize]),
+            (None, _, Some(opened), None)           => send_range(opened, res, range),
+            (None, _, Some(opened), Some(encoding)) => opened.into_range_reader(0 .. stat.len).and_then(|r| copy_compressed(&mut std::io::BufReader::new(r), res, encoding)),
+            _                                       => unreachable!("bug: nothing to send"),
+        };
+        if result.is_err() { return }
+    }
+    let _ = res.finish();
+}
+
+/// Read and throw away the request body, within `--max-body-size`.  `Err` once we've responded to a bad one.
+fn discard_body(settings: &Settings, body: &mut dyn RequestBody, res: &mut dyn Response) -> Result<(), ()> {
+    let mut body = match body.open(settings.max_body_size) {
+        Ok(body)                            => body,
+        Err(body::Error::BadRequest)        => { response::bad_request(res); return Err(()) },
+        Err(body::Error::TooLarge)          => { response::body_too_large(res, settings.max_body_size); return Err(()) },
+        Err(body::Error::NotImplemented)    => { response::not_implemented(res); return Err(()) },
+    };
+    match std::io::copy(&mut body, &mut std::io::sink()) {
+        Ok(_)                                   => Ok(()),
+        Err(err) if body::is_too_large(&err)    => { response::body_too_large(res, settings.max_body_size); Err(()) },
+        Err(_err)                               => { response::bad_request(res); Err(()) },
+    }
+}
+
+/// Look up `name` in `dir`, falling back on a case-insensitive match per `--case-insensitive`.  `Err(candidates)` if
+/// several (allowed) entries differ from `name` only by case.
+fn find<'s>(settings: &Settings, dir: &'s fs::dir::Snapshot, dir_path: &str, name: &OsStr) -> Result<Option<&'s fs::dir::Entry>, Vec<&'s fs::dir::Entry>> {
+    if let Some(entry) = dir.by_name(name) { return Ok(Some(entry)) }
+    if !settings.case_insensitive { return Ok(None) }
+    let mut candidates = dir.by_folded_name(&name.to_string_lossy()).filter(|e| settings.rules.allows(&format!("{dir_path}{}", e.name_lossy()), e.is_dir()));
+    match (candidates.next(), candidates.next()) {
+        (None, _)               => Ok(None),
+        (Some(entry), None)     => Ok(Some(entry)),
+        (Some(a), Some(b))      => Err([a, b].into_iter().chain(candidates).collect()),
+    }
+}
+
+/// URLs for a `300 Multiple Choices`: `base` + each of `candidates` + the `rest` of the requested path.
+fn alternatives<'a>(base: &str, candidates: &[&fs::dir::Entry], rest: impl IntoIterator<Item = &'a OsString> + Clone, is_dir: bool) -> Vec<String> {
+    candidates.iter().map(|candidate| {
+        let mut href = String::from(base);
+        percent::encode_segment(candidate.name_os(), &mut href);
+        for segment in rest.clone() {
+            href.push('/');
+            percent::encode_segment(segment, &mut href);
+        }
+        if is_dir { href.push('/'); }
+        href
+    }).collect()
+}
+
+/// Does [`content_type`] need to look at the start of the file?
+fn needs_head(settings: &Settings, ext_mime: Option<&str>) -> bool {
+    (ext_mime.is_none() && settings.sniff) || ext_mime.or_else(|| settings.mime.fallback()).map_or(false, |m| mime::essence(m).starts_with("text/"))
+}
+
+/// The `Content-Type` for a file (and whether we sniffed it) given the type implied by its extension, if any, and the
+/// first [`mime::SNIFF_LEN`] bytes of the file (if [`needs_head`].)
+fn content_type<'s>(settings: &'s Settings, ext_mime: Option<&'s str>, head: &[u8]) -> Option<(Cow<'s, str>, bool)> {
+    let mut mime = ext_mime;
+    let mut sniffed = false;
+    if mime.is_none() && settings.sniff {
+        mime = mime::sniff(head);
+        sniffed = mime.is_some();
+    }
+    let mime = mime.or_else(|| settings.mime.fallback())?;
+    Some((mime::with_charset(mime, head), sniffed))
+}
+
+/// The byte range requested by a `Range` header, clamped to a `len` byte file.  `None` if we should just ignore the
+/// header and send the whole thing (malformed, not `bytes`, or multiple ranges - we don't do `multipart/byteranges`),
+/// `Some(Err(()))` if the range lies entirely outside the file.
+fn byte_range(range: &str, len: u64) -> Option<Result<std::ops::Range<u64>, ()>> {
+    let (first, last) = range.strip_prefix("bytes=")?.trim().split_once('-')?;
+    let parse = |n: &str| if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) { n.parse::<u64>().ok() } else { None };
+    let range = match (first.trim(), last.trim()) {
+        ("", suffix)    => len.saturating_sub(parse(suffix)?) .. len,
+        (first, "")     => parse(first)? .. len,
+        (first, last)   => {
+            let (first, last) = (parse(first)?, parse(last)?);
+            if last < first { return None }
+            first .. last.saturating_add(1).min(len)
+        },
+    };
+    Some(if range.start < range.end { Ok(range) } else { Err(()) })
+}
+
+/// Send `range` of an opened file, via `sendfile` where possible.
+fn send_range(opened: fs::file::Opened, res: &mut dyn Response, range: std::ops::Range<u64>) -> std::io::Result<()> {
+    let len = range.end - range.start;
+    match opened {
+        fs::file::Opened::File(file, offset) => res.send_file(&file, offset + range.start, len),
+        stream => {
+            if std::io::copy(&mut std::io::BufReader::new(stream.into_range_reader(range)?), res)? < len { return Err(std::io::ErrorKind::UnexpectedEof.into()) }
+            Ok(())
+        },
+    }
+}
+
+fn copy_compressed(file: &mut impl Read, res: &mut dyn Response, encoding: compress::Encoding) -> std::io::Result<()> {
+    let mut body = compress::Encoder::new(res, encoding)?;
+    std::io::copy(file, &mut body)?;
+    body.finish()?.flush()
+}
+
+
+
+/// What [`serve`] responded with, for tests.
+#[cfg(test)] #[derive(Default)] pub struct Recorded { pub status: u16, pub headers: Vec<(String, String)>, pub body: Vec<u8> }
+
+#[cfg(test)] impl Recorded {
+    pub fn header(&self, name: &str) -> Option<&str> { self.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str()) }
+}
+
+#[cfg(test)] impl std::io::Write for Recorded {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.body.write(buf) }
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+#[cfg(test)] impl Response for Recorded {
+    fn head(&mut self, status: u16, headers: &[(&str, &str)], _len: Option<u64>) -> std::io::Result<()> {
+        self.status = status;
+        self.headers = headers.iter().map(|&(k, v)| (k.into(), v.into())).collect();
+        Ok(())
+    }
+    fn finish(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+/// [`serve`] a request with the given `headers` and `body`, for tests.
+#[cfg(test)] pub fn request(settings: &Settings, method: &str, path: &str, headers: &[(&str, &str)], body: &'static [u8]) -> Recorded {
+    struct Body(&'static [u8]);
+    impl RequestBody for Body {
+        fn open(&mut self, limit: u64) -> Result<Box<dyn Read + '_>, body::Error> {
+            if self.0.len() as u64 > limit { return Err(body::Error::TooLarge) }
+            Ok(Box::new(self.0))
+        }
+    }
+    let (path, search) = path.split_at(path.find('?').unwrap_or(path.len()));
+    let req = Request { method: method.as_bytes(), path, search, version: Version::Http11, headers: headers.to_vec() };
+    let mut res = Recorded::default();
+    serve(settings, &req, &mut Body(body), &mut res);
+    res
+}
+
+/// [`Settings`] serving `files` from memory, for tests.
+#[cfg(test)] pub fn memory_settings(files: &[(&str, &[u8])]) -> Settings {
+    let memory = vfs::Memory::new("memory");
+    for (path, content) in files { memory.insert(path, content.to_vec(), std::time::SystemTime::UNIX_EPOCH); }
+    let mut mounts = mount::Mounts::default();
+    mounts.add_vfs("/", "memory".into(), Box::new(memory)).unwrap();
+    Settings::with_mounts(mounts)
+}
+
+#[test] fn check_serve() {
+    let mut settings = memory_settings(&[("index.html", b"<p>hi</p>"), ("sub/a.txt", b"abcdef")]);
+    let prebuilt = vfs::Memory::new("prebuilt"); // overlaid
+    let precomputed = vfs::Precomputed { etag: Some("\"prebuilt\"".into()), gzip: Some(Cow::Borrowed(b"pretend gzip")), deflate: None };
+    prebuilt.insert_precomputed("big.txt", vec![b'x'; 2000], std::time::SystemTime::UNIX_EPOCH, precomputed);
+    settings.mounts.add_vfs("/", "prebuilt".into(), Box::new(prebuilt)).unwrap();
+
+    let res = request(&settings, "GET", "/", &[], b"");
+    assert_eq!((res.status, &res.body[..]), (200, &b"<p>hi</p>"[..]));
+    assert!(res.headers.iter().any(|(k, v)| k == "Content-Type" && v.starts_with("text/html")));
+    let res = request(&settings, "GET", "/sub/a.txt", &[("Range", "bytes=2-3")], b"");
+    assert_eq!((res.status, &res.body[..]), (206, &b"cd"[..]));
+    assert_eq!(request(&settings, "GET", "/sub/missing.txt", &[], b"").status, 404);
+    let res = request(&settings, "GET", "/big.txt", &[("Accept-Encoding", "gzip")], b"");
+    assert_eq!((res.status, &res.body[..]), (200, &b"pretend gzip"[..]));
+    assert!(res.headers.contains(&("ETag".into(), "\"prebuilt-gzip\"".into())));
+    let res = request(&settings, "GET", "/big.txt", &[("Accept-Encoding", "deflate")], b""); // not precompressed: compressed on demand
+    assert_eq!((res.status, res.body.len() < 100), (200, true));
+    let res = request(&settings, "PROPFIND", "/", &[("Depth", "1")], b"");
+    assert_eq!(res.status, 207);
+    assert!(String::from_utf8(res.body).unwrap().contains("<href>/sub/</href>"));
+
+    assert_eq!(request(&settings, "PUT", "/sub/a.txt", &[("Content-Length", "3")], b"new").status, 405, "no uploads");
+    let propfind = b"<?xml version=\"1.0\"?><propfind xmlns=\"DAV:\"><allprop/></propfind>";
+    assert_eq!(request(&settings, "PROPFIND", "/sub/", &[("Depth", "1")], propfind).status, 207);
+    settings.max_body_size = 16;
+    assert_eq!(request(&settings, "PROPFIND", "/sub/", &[("Depth", "1")], propfind).status, 413);
+}
+
+#[test] fn check_case_insensitive() {
+    let mut settings = memory_settings(&[("sub/a.txt", b"abcdef")]);
+
+    assert_eq!(request(&settings, "GET", "/Sub/a.txt", &[], b"").status, 404, "case sensitive by default");
+    settings.case_insensitive = true;
+    assert_eq!(request(&settings, "GET", "/Sub/A.TXT", &[], b"").body, b"abcdef");
+    settings.case_redirect = true;
+    for (path, location) in [("/Sub/A.TXT?q", "/sub/a.txt?q"), ("/Sub/a.txt", "/sub/a.txt"), ("/sub/A.txt", "/sub/a.txt"), ("/SUB/", "/sub/")] {
+        let res = request(&settings, "GET", path, &[], b"");
+        assert_eq!((res.status, res.header("Location")), (301, Some(location)), "{path}");
+    }
+    assert_eq!(request(&settings, "GET", "/sub/a.txt", &[], b"").status, 200);
+}
+
+#[test] fn check_byte_range() {
+    assert_eq!(byte_range("bytes=0-499",        1000), Some(Ok(0 .. 500)));
+    assert_eq!(byte_range("bytes=500-",         1000), Some(Ok(500 .. 1000)));
+    assert_eq!(byte_range("bytes=-200",         1000), Some(Ok(800 .. 1000)));
+    assert_eq!(byte_range("bytes=-2000",        1000), Some(Ok(0 .. 1000)));
+    assert_eq!(byte_range("bytes=900-2000",     1000), Some(Ok(900 .. 1000)));
+    assert_eq!(byte_range("bytes=1000-",        1000), Some(Err(())));
+    assert_eq!(byte_range("bytes=-0",           1000), Some(Err(())));
+    assert_eq!(byte_range("bytes=0-",           0),    Some(Err(())));
+    assert_eq!(byte_range("bytes=5-4",          1000), None);
+    assert_eq!(byte_range("bytes=0-1,5-6",      1000), None);
+    assert_eq!(byte_range("bytes=+1-2",         1000), None);
+    assert_eq!(byte_range("items=0-1",          1000), None);
+}
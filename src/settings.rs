@@ -5,9 +5,20 @@ use std::path::*;
 pub struct Settings {
     pub open:   bool,
     pub webdav: bool,
+    pub max_body_size: u64,
+    pub mime:   crate::mime::Types,
+    pub sniff:  bool,
+    pub multiviews: bool,
+    pub case_insensitive: bool,
+    pub case_redirect: bool,
+    pub rules:  crate::rules::Rules,
+    pub headers: crate::headers::Headers,
+    pub cors:   Option<crate::cors::Cors>,
+    pub redirects: crate::redirects::Cache,
     pub bind:   IpAddr,
     pub cache:  crate::fs::dir::Cache,
-    pub root:   std::path::PathBuf,
+    pub files:  crate::fs::file::Cache,
+    pub mounts: crate::mount::Mounts,
 }
 
 impl Settings {
@@ -16,16 +27,49 @@ impl Settings {
         let mut help = false;
         let mut open = false;
         let webdav = true;
+        let mut max_body_size = crate::body::DEFAULT_LIMIT;
+        let mut mime = crate::mime::Types::default();
+        let mut sniff = false;
+        let mut multiviews = false;
+        let mut case_insensitive = false;
+        let mut case_redirect = false;
+        let mut dir_cache_entries = crate::fs::dir::Cache::DEFAULT_MAX_ENTRIES;
+        let mut dir_cache_ttl = crate::fs::dir::Cache::DEFAULT_TTL;
+        let mut symlinks = crate::fs::dir::Symlinks::WithinRoot(Vec::new()); // roots filled in once they're all known
+        let mut rules = Vec::<(bool, String)>::new(); // (allow, pattern) - applied after the root's ignore file
+        let mut header_args = Vec::<String>::new(); // `PATH_GLOB: Name: value` - applied after the root's _headers file
+        let mut cross_origin_isolated = Option::<&'static str>::None; // COEP
+        let mut cors_origins = Option::<Vec<String>>::None; // None: no --cors
+        let mut cors_methods = Option::<String>::None;
+        let mut cors_headers = Option::<String>::None;
+        let mut cors_credentials = false;
+        let mut cors_max_age = Option::<u64>::None;
+        let mut cache_size = crate::fs::file::Cache::DEFAULT_MAX_BYTES;
         let mut bind = Option::<IpAddr>::None;
         let mut root = Option::<PathBuf>::None;
+        let mut mount_args = Vec::<(String, PathBuf)>::new();
 
         macro_rules! error   { ($($tt:tt)*) => {{ eprintln!($($tt)*); errors = true; }} }
         macro_rules! warning { ($($tt:tt)*) => {{ eprintln!($($tt)*); }} }
 
-        let mut args = std::env::args_os();
+        let mut args = std::env::args_os().peekable();
         let _exe = args.next();
-        for arg in args {
-            match &*arg.to_string_lossy() {
+        while let Some(arg) = args.next() {
+            let lossy = arg.to_string_lossy();
+            let (flag, inline_value) = match lossy.split_once('=') {
+                Some((flag, value)) if flag.starts_with("--") => (flag, Some(value)),
+                _                                             => (&*lossy, None),
+            };
+            macro_rules! value { () => {
+                match inline_value.map(String::from).or_else(|| args.next().map(|a| a.to_string_lossy().into_owned())) {
+                    Some(value) => value,
+                    None => { error!("error: {flag} expects a value"); continue },
+                }
+            }}
+            macro_rules! optional_value { ($is_value:expr) => { // `--flag`, `--flag=value`, or `--flag value` if the next arg `$is_value` (rather than e.g. the root)
+                inline_value.map(String::from).or_else(|| args.next_if(|a| a.to_str().map_or(false, $is_value)).map(|a| a.to_string_lossy().into_owned()))
+            }}
+            match flag {
                 "--help" => {
                     if !help {
                         help = true;
@@ -37,6 +81,110 @@ impl Settings {
                 },
                 "--open"            => open = true,
                 "--no-open"         => open = false,
+                "--max-body-size"   => {
+                    let value = value!();
+                    match parse_size(&value) {
+                        Some(size)  => max_body_size = size,
+                        None        => error!("error: --max-body-size expects a size like `1048576`, `512K`, `64M`, or `2G`, but got {value:?}"),
+                    }
+                },
+                "--mime-types"      => if let Err(err) = mime.load(Path::new(&value!())) { error!("error: --mime-types: {err}") },
+                "--mime"            => {
+                    let value = value!();
+                    match value.split_once('=') {
+                        Some((ext, ty)) => if let Err(err) = mime.insert(ext, ty) { error!("error: --mime {value:?}: {err}") },
+                        None            => error!("error: --mime expects `ext=type`, but got {value:?}"),
+                    }
+                },
+                "--sniff"           => sniff = true,
+                "--no-sniff"        => sniff = false,
+                "--cache-size"      => {
+                    let value = value!();
+                    match parse_size(&value) {
+                        Some(size)  => cache_size = size,
+                        None        => error!("error: --cache-size expects a size like `0`, `512K`, `64M`, or `2G`, but got {value:?}"),
+                    }
+                },
+                "--dir-cache-entries" => {
+                    let value = value!();
+                    match value.parse::<usize>() {
+                        Ok(n) if n > 0  => dir_cache_entries = n,
+                        _               => error!("error: --dir-cache-entries expects a positive number of directories, but got {value:?}"),
+                    }
+                },
+                "--dir-cache-ttl"   => {
+                    let value = value!();
+                    match value.parse::<u64>() {
+                        Ok(secs)    => dir_cache_ttl = std::time::Duration::from_secs(secs),
+                        Err(_)      => error!("error: --dir-cache-ttl expects a number of seconds, but got {value:?}"),
+                    }
+                },
+                "--symlinks"        => {
+                    let value = value!();
+                    match &*value {
+                        "deny"          => symlinks = crate::fs::dir::Symlinks::Deny,
+                        "follow"        => symlinks = crate::fs::dir::Symlinks::Follow,
+                        "within-root"   => symlinks = crate::fs::dir::Symlinks::WithinRoot(Vec::new()),
+                        _               => error!("error: --symlinks expects `deny`, `follow`, or `within-root`, but got {value:?}"),
+                    }
+                },
+                "--mount"           => {
+                    let value = value!();
+                    match value.split_once('=') {
+                        Some((prefix, dir)) => {
+                            let dir = PathBuf::from(dir);
+                            if !dir.is_dir() && !crate::archive::is_archive(&dir) { error!("error: --mount {value}: `{}` is not a directory, .zip, or .tar", dir.display()) }
+                            mount_args.push((prefix.into(), dir));
+                        },
+                        None                => error!("error: --mount expects `URLPREFIX=DIR`, but got {value:?}"),
+                    }
+                },
+                "--allow"           => rules.push((true,  value!())),
+                "--deny"            => rules.push((false, value!())),
+                "--header"          => header_args.push(value!()),
+                "--cross-origin-isolated" => match optional_value!(|a| a == "require-corp" || a == "credentialless").as_deref() {
+                    None | Some("require-corp") => cross_origin_isolated = Some("require-corp"),
+                    Some("credentialless")      => cross_origin_isolated = Some("credentialless"),
+                    Some(value)                 => error!("error: --cross-origin-isolated={value:?} is invalid, expected require-corp or credentialless"),
+                },
+                "--no-cross-origin-isolated" => cross_origin_isolated = None,
+                "--cors"            => match optional_value!(|a| a == "*" || a.contains("://")) {
+                    None                => cors_origins = Some(vec!["*".into()]),
+                    Some(value)         => {
+                        let origins = value.split(',').filter(|o| !o.trim().is_empty()).map(crate::cors::Cors::parse_origin).collect::<Result<Vec<_>, _>>();
+                        match origins {
+                            Ok(origins) if !origins.is_empty()  => cors_origins = Some(origins),
+                            Ok(_)                               => error!("error: --cors expects a comma separated list of origins"),
+                            Err(err)                            => error!("error: --cors: {err}"),
+                        }
+                    },
+                },
+                "--no-cors"         => cors_origins = None,
+                "--cors-methods"    => match crate::cors::Cors::parse_list(&value!()) {
+                    Ok(methods)     => cors_methods = Some(methods),
+                    Err(err)        => error!("error: --cors-methods: {err}"),
+                },
+                "--cors-headers"    => match crate::cors::Cors::parse_list(&value!()) {
+                    Ok(headers)     => cors_headers = Some(headers),
+                    Err(err)        => error!("error: --cors-headers: {err}"),
+                },
+                "--cors-credentials" => cors_credentials = true,
+                "--cors-max-age"    => {
+                    let value = value!();
+                    match value.parse::<u64>() {
+                        Ok(secs)    => cors_max_age = Some(secs),
+                        Err(_)      => error!("error: --cors-max-age expects a number of seconds, but got {value:?}"),
+                    }
+                },
+                "--case-sensitive"  => (case_insensitive, case_redirect) = (false, false),
+                "--case-insensitive" => match optional_value!(|a| a == "redirect").as_deref() {
+                    None                => (case_insensitive, case_redirect) = (true, false),
+                    Some("redirect")    => (case_insensitive, case_redirect) = (true, true),
+                    Some(value)         => error!("error: --case-insensitive={value:?} is invalid, did you mean --case-insensitive=redirect?"),
+                },
+                "--multiviews"      => multiviews = true,
+                "--no-multiviews"   => multiviews = false,
+                "--default-mime"    => if let Err(err) = mime.set_default(&value!()) { error!("error: --default-mime: {err}") },
                 "--allow-all-ipv4" => {
                     if let Some(_prev) = bind.replace(IpAddr::V4(Ipv4Addr::UNSPECIFIED)) {
                         warning!("warning: multiple --allow-* flags specified, only the last will apply");
@@ -48,35 +196,174 @@ impl Settings {
                     }
                 },
                 flag if flag.starts_with("--") => error!("unrecognized flag {flag:?}"),
+                _ if inline_value.is_some() => error!("unrecognized flag {lossy:?}"),
 
                 _positional_lossy if root.is_none() => {
                     let path = PathBuf::from(arg);
-                    if !path.is_dir() { error!("error: `{}` is not a directory", path.display()) }
+                    if !path.is_dir() && !crate::archive::is_archive(&path) { error!("error: `{}` is not a directory, .zip, or .tar", path.display()) }
                     root = Some(path);
                 },
                 positional => error!("error: expected at most one positional argument - the root directory - but recieved a second, {positional:?}"),
             }
         }
 
+        // the positional root (or CWD if nothing else is mounted) comes first, and thus wins any overlay at `/`
+        // a site embedded at build time takes the place of CWD, mounted alongside anything else
+        let embedded = root.is_none() && !crate::embed::is_empty();
+        let embedded_root = std::env::current_exe().unwrap_or_else(|_err| PathBuf::from("mmuhttpd")); // N.B. a file, so no real path can collide
+        if root.is_some() || (mount_args.is_empty() && !embedded) {
+            let root = root.unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_err| PathBuf::from(".")));
+            mount_args.insert(0, ("/".into(), root));
+        }
+        let mut mounts = crate::mount::Mounts::default();
+        if embedded {
+            if let Err(err) = mounts.add_vfs("/", embedded_root.clone(), Box::new(crate::embed::vfs(embedded_root.clone()))) { error!("error: {err}") }
+        }
+        for (prefix, dir) in mount_args.iter() {
+            if let Err(err) = mounts.add(prefix, dir.clone()) { error!("error: {err}") }
+        }
+        if let crate::fs::dir::Symlinks::WithinRoot(roots) = &mut symlinks {
+            *roots = mounts.roots().filter_map(|root| std::fs::canonicalize(root).ok()).collect(); // N.B. missing roots were already reported
+        }
+        let rules = {
+            let mut r = crate::rules::Rules::default();
+            if let Some(text) = crate::embed::get(crate::rules::IGNORE_FILE).filter(|_| embedded) {
+                let text = String::from_utf8_lossy(text);
+                if let Err(err) = r.parse(&embedded_root.join(crate::rules::IGNORE_FILE), &text) { error!("error: {err}") }
+            }
+            for (_, root) in mount_args.iter().filter(|(prefix, _)| prefix.trim_matches('/').is_empty()) {
+                let ignore_file = root.join(crate::rules::IGNORE_FILE);
+                if ignore_file.exists() {
+                    if let Err(err) = r.load(&ignore_file) { error!("error: {err}") }
+                }
+            }
+            for (allow, pattern) in rules {
+                let result = if allow { r.allow(&pattern) } else { r.deny(&pattern) };
+                if let Err(err) = result { error!("error: --{}: {err}", if allow { "allow" } else { "deny" }) }
+            }
+            r.deny_config_files(&mounts.prefixes()); // N.B. last: can't be --allow ed
+            r
+        };
+        let headers = {
+            let mut h = crate::headers::Headers::default();
+            if let Some(coep) = cross_origin_isolated { h.isolate(coep); } // N.B. first, so _headers and --header can override it for specific paths
+            if let Some(text) = crate::embed::get(crate::headers::FILE).filter(|_| embedded) {
+                let text = String::from_utf8_lossy(text);
+                if let Err(err) = h.parse(&embedded_root.join(crate::headers::FILE), &text) { error!("error: {err}") }
+            }
+            for (_, root) in mount_args.iter().filter(|(prefix, _)| prefix.trim_matches('/').is_empty()) {
+                let headers_file = root.join(crate::headers::FILE);
+                if headers_file.is_file() {
+                    if let Err(err) = h.load(&headers_file) { error!("error: {err}") }
+                }
+            }
+            for arg in header_args {
+                let result = match arg.split_once(": ").and_then(|(pattern, header)| Some((pattern, header.split_once(':')?))) {
+                    Some((pattern, (name, value)))  => h.insert(pattern, name, value),
+                    None                            => Err(format!("expected `PATH_GLOB: Name: value`, but got {arg:?}")),
+                };
+                if let Err(err) = result { error!("error: --header: {err}") }
+            }
+            h
+        };
+
+        let cors = cors_origins.map(|origins| crate::cors::Cors {
+            origins,
+            methods:        cors_methods.take().unwrap_or_else(|| crate::cors::DEFAULT_METHODS.into()),
+            headers:        cors_headers.take(),
+            credentials:    cors_credentials,
+            max_age:        cors_max_age,
+        });
+        if cors.is_none() && (cors_methods.is_some() || cors_headers.is_some() || cors_credentials || cors_max_age.is_some()) {
+            warning!("warning: --cors-* flags have no effect without --cors");
+        }
+        if cors.as_ref().map_or(false, |cors| cors.credentials && cors.origins.iter().any(|o| o == "*")) {
+            error!("error: --cors-credentials needs specific origins (--cors=ORIGIN,...), or any site could act as your visitors");
+        }
+
+        // as a safer default:
+        //  1.  only allow connections over a loopback address
+        //  2.  pick a nonstandard loopback to avoid conflicting with other things on localhost
+        //  3.  pick a nonstandard loopback to avoid exposing something via any reverse proxy forwarding
+        //
+        // default loopbacks:
+        //  ::1         (ipv6)
+        //  127.0.0.x   (linux)
+        //  127.x.y.z   (windows / https://www.rfc-editor.org/rfc/rfc1122 )
+        let bind = bind.unwrap_or_else(|| IpAddr::V4(Ipv4Addr::new(127, 0, 0, 99)));
+        if cross_origin_isolated.is_some() && !bind.is_loopback() {
+            warning!("warning: --cross-origin-isolated: browsers only isolate secure contexts (https:// or localhost), so clients reaching this over plain http:// from other machines won't get SharedArrayBuffer");
+        }
+
         if errors { std::process::exit(1) }
         if help { std::process::exit(0) } // already printed help text
 
         Self {
             open,
             webdav,
-            cache: crate::fs::dir::Cache::new(), // XXX: split off into a "context" type instead of hijacking settings?
-            root: root.unwrap_or_else(|| std::env::current_dir().unwrap_or_else(|_err| PathBuf::from("."))),
+            max_body_size,
+            mime,
+            sniff,
+            multiviews,
+            case_insensitive,
+            case_redirect,
+            rules,
+            headers,
+            cors,
+            redirects: Default::default(),
+            cache: crate::fs::dir::Cache::new(dir_cache_entries, dir_cache_ttl, symlinks),
+            files: crate::fs::file::Cache::new(cache_size), // XXX: split off into a "context" type instead of hijacking settings?
+            mounts,
+            bind,
+        }
+    }
+}
 
-            // as a safer default:
-            //  1.  only allow connections over a loopback address
-            //  2.  pick a nonstandard loopback to avoid conflicting with other things on localhost
-            //  3.  pick a nonstandard loopback to avoid exposing something via any reverse proxy forwarding
-            //
-            // default loopbacks:
-            //  ::1         (ipv6)
-            //  127.0.0.x   (linux)
-            //  127.x.y.z   (windows / https://www.rfc-editor.org/rfc/rfc1122 )
-            bind: bind.unwrap_or_else(|| IpAddr::V4(Ipv4Addr::new(127, 0, 0, 99))),
+impl Settings {
+    /// The defaults (as if run without any flags) serving `mounts`, for tests that shouldn't depend on the command line.
+    #[cfg(test)] pub fn with_mounts(mounts: crate::mount::Mounts) -> Self {
+        Self {
+            open:               false,
+            webdav:             true,
+            max_body_size:      crate::body::DEFAULT_LIMIT,
+            mime:               Default::default(),
+            sniff:              false,
+            multiviews:         false,
+            case_insensitive:   false,
+            case_redirect:      false,
+            rules:              { let mut r = crate::rules::Rules::default(); r.deny_config_files(&mounts.prefixes()); r },
+            headers:            Default::default(),
+            cors:               None,
+            redirects:          Default::default(),
+            bind:               IpAddr::V4(Ipv4Addr::new(127, 0, 0, 99)),
+            cache:              Default::default(),
+            files:              crate::fs::file::Cache::new(crate::fs::file::Cache::DEFAULT_MAX_BYTES),
+            mounts,
         }
     }
 }
+
+/// Parse a byte count with an optional binary `K`/`M`/`G` suffix.
+fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (digits, scale) = match s.as_bytes().last()?.to_ascii_uppercase() {
+        b'K' => (&s[..s.len()-1], 1 << 10),
+        b'M' => (&s[..s.len()-1], 1 << 20),
+        b'G' => (&s[..s.len()-1], 1 << 30),
+        _    => (s, 1),
+    };
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) { return None }
+    digits.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+#[test] fn check_parse_size() {
+    assert_eq!(parse_size("0"),     Some(0));
+    assert_eq!(parse_size("1234"),  Some(1234));
+    assert_eq!(parse_size("512k"),  Some(512 * 1024));
+    assert_eq!(parse_size("64M"),   Some(64 * 1024 * 1024));
+    assert_eq!(parse_size("2G"),    Some(2 * 1024 * 1024 * 1024));
+    assert_eq!(parse_size("M"),     None);
+    assert_eq!(parse_size("-1"),    None);
+    assert_eq!(parse_size("1.5M"),  None);
+    assert_eq!(parse_size("99999999999999999999"), None);
+}
@@ -0,0 +1,211 @@
+//! [`Vfs`]: everything request handling needs from a filesystem, whether that's the real one ([`fs::dir::Cache`]), the
+//! inside of an archive ([`archive::Archive`]), or memory ([`Memory`].)
+//!
+//! Paths are whatever the [`Vfs`] hands out in its [`Snapshot`]s: real paths for the real filesystem, nominal ones
+//! (e.g. `site.zip/sub/a.txt`) otherwise.  They're still unique per file, so they can key [`fs::file::Cache`].
+//!
+//! [`fs::dir::Cache`]:     crate::fs::dir::Cache
+//! [`fs::file::Cache`]:    crate::fs::file::Cache
+//! [`archive::Archive`]:   crate::archive::Archive
+
+use crate::compress::Encoding;
+use crate::fs::dir::{Entry, Snapshot};
+use crate::fs::file::{self, Opened, Stat};
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+
+
+pub trait Vfs: Send + Sync {
+    /// The directory at `path`.  `None` if it doesn't exist (anymore), or isn't a directory.
+    fn list(&self, path: &Path) -> Option<Arc<Snapshot>>;
+
+    /// `None` if `path` isn't (or is no longer) a file.
+    fn stat(&self, path: &Path) -> Option<Stat>;
+
+    fn open(&self, path: &Path) -> io::Result<Opened>;
+
+    /// Bytes `range` of the file at `path`.
+    fn read_range(&self, path: &Path, range: Range<u64>) -> io::Result<Box<dyn Read>> {
+        self.open(path)?.into_range_reader(range)
+    }
+
+    /// A strong ETag for the file at `path`.  By default, derived from its size and mtime.
+    fn etag(&self, path: &Path, stat: &Stat) -> String {
+        let _ = path;
+        file::etag(stat)
+    }
+
+    /// The contents of `path` already compressed with `encoding`, if on hand (e.g. compressed at build time.)
+    fn precompressed(&self, path: &Path, encoding: Encoding) -> Option<Cow<'static, [u8]>> {
+        let _ = (path, encoding);
+        None
+    }
+
+    /// Create or replace `dir/name` with the contents of `body`.  Read-only by default.
+    fn write(&self, dir: &Path, name: &str, body: &mut dyn Read) -> io::Result<()> {
+        let _ = (dir, name, body);
+        Err(io::ErrorKind::PermissionDenied.into())
+    }
+}
+
+
+
+/// A writable filesystem that lives entirely in memory.
+pub struct Memory {
+    root:       PathBuf,
+    writable:   bool,
+    state:      Mutex<MemoryState>,
+}
+
+#[derive(Default)] struct MemoryState {
+    files:  HashMap<PathBuf, MemoryFile>,
+    dirs:   HashMap<PathBuf, MemoryDir>,
+}
+
+struct MemoryFile {
+    content:        Bytes,
+    modified:       SystemTime,
+    precomputed:    Arc<Precomputed>,
+}
+
+struct MemoryDir {
+    modified:   SystemTime,
+    children:   BTreeMap<String, bool>, // name => is_dir
+    snapshot:   Option<Arc<Snapshot>>,  // built on demand, forgotten whenever anything is inserted beneath
+}
+
+#[derive(Clone)] struct Bytes(Arc<Cow<'static, [u8]>>);
+impl AsRef<[u8]> for Bytes { fn as_ref(&self) -> &[u8] { &self.0 } }
+
+/// Things about a [`Memory`] file that were worked out ahead of time (see [`crate::embed`].)
+#[derive(Default)] pub struct Precomputed {
+    pub etag:       Option<String>,
+    pub gzip:       Option<Cow<'static, [u8]>>,
+    pub deflate:    Option<Cow<'static, [u8]>>,
+}
+
+impl Memory {
+    /// An empty filesystem, whose paths all start with `root` (which needn't exist anywhere.)
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        let mut state = MemoryState::default();
+        state.dirs.insert(root.clone(), MemoryDir { modified: SystemTime::UNIX_EPOCH, children: Default::default(), snapshot: None });
+        Self { root, writable: true, state: Mutex::new(state) }
+    }
+
+    /// Refuse [`Vfs::write`]s.  [`Self::insert`] still works.
+    pub fn read_only(self) -> Self { Self { writable: false, .. self } }
+
+    /// Create or replace the file at the `/`-separated `path` (relative to [`Self::root`]), and any missing parents.
+    pub fn insert(&self, path: &str, content: impl Into<Cow<'static, [u8]>>, modified: SystemTime) {
+        self.insert_precomputed(path, content, modified, Precomputed::default())
+    }
+
+    /// [`Self::insert`], plus an ETag and/or compressed variants of `content`.
+    pub fn insert_precomputed(&self, path: &str, content: impl Into<Cow<'static, [u8]>>, modified: SystemTime, precomputed: Precomputed) {
+        let mut state = self.state.lock().expect("bug: Mutex poisoned");
+        let mut dir = self.root.clone();
+        let mut segments = path.split('/').filter(|s| !s.is_empty()).peekable();
+        while let Some(name) = segments.next() {
+            let is_dir = segments.peek().is_some();
+            let parent = state.dirs.get_mut(&dir).expect("bug: parent directory missing");
+            parent.children.insert(name.into(), is_dir);
+            parent.modified = parent.modified.max(modified);
+            parent.snapshot = None;
+            dir.push(name);
+            if is_dir {
+                state.dirs.entry(dir.clone()).or_insert_with(|| MemoryDir { modified, children: Default::default(), snapshot: None });
+            }
+        }
+        state.dirs.remove(&dir); // replaced by a file
+        state.files.insert(dir, MemoryFile { content: Bytes(Arc::new(content.into())), modified, precomputed: Arc::new(precomputed) });
+    }
+}
+
+impl Vfs for Memory {
+    fn list(&self, path: &Path) -> Option<Arc<Snapshot>> {
+        let mut state = self.state.lock().expect("bug: Mutex poisoned");
+        let dir = state.dirs.get_mut(path)?;
+        let snapshot = dir.snapshot.get_or_insert_with(|| {
+            let entries = dir.children.iter().map(|(name, &is_dir)| Entry::synthetic(name, path.join(name), is_dir)).collect();
+            Arc::new(Snapshot::with_entries(dir.modified, dir.modified, path.into(), entries))
+        });
+        Some(Arc::clone(snapshot))
+    }
+
+    fn stat(&self, path: &Path) -> Option<Stat> {
+        let state = self.state.lock().expect("bug: Mutex poisoned");
+        let file = state.files.get(path)?;
+        Some(Stat { len: file.content.as_ref().len() as u64, created: Some(file.modified), modified: Some(file.modified) })
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Opened> {
+        let state = self.state.lock().expect("bug: Mutex poisoned");
+        let file = state.files.get(path).ok_or(io::ErrorKind::NotFound)?;
+        Ok(Opened::Stream(Box::new(io::Cursor::new(file.content.clone()))))
+    }
+
+    fn etag(&self, path: &Path, stat: &Stat) -> String {
+        let state = self.state.lock().expect("bug: Mutex poisoned");
+        state.files.get(path).and_then(|f| f.precomputed.etag.clone()).unwrap_or_else(|| file::etag(stat))
+    }
+
+    fn precompressed(&self, path: &Path, encoding: Encoding) -> Option<Cow<'static, [u8]>> {
+        let state = self.state.lock().expect("bug: Mutex poisoned");
+        let precomputed = &state.files.get(path)?.precomputed;
+        match encoding {
+            Encoding::Gzip      => precomputed.gzip.clone(),
+            Encoding::Deflate   => precomputed.deflate.clone(),
+        }
+    }
+
+    fn write(&self, dir: &Path, name: &str, body: &mut dyn Read) -> io::Result<()> {
+        if !self.writable { return Err(io::ErrorKind::PermissionDenied.into()) }
+        let relative = dir.strip_prefix(&self.root).map_err(|_| io::Error::from(io::ErrorKind::NotFound))?;
+        if !self.state.lock().expect("bug: Mutex poisoned").dirs.contains_key(dir) { return Err(io::ErrorKind::NotFound.into()) }
+        let mut content = Vec::new();
+        body.read_to_end(&mut content)?;
+        let path = relative.iter().map(|s| s.to_string_lossy()).chain(Some(Cow::Borrowed(name))).collect::<Vec<_>>().join("/");
+        self.insert(&path, content, SystemTime::now());
+        Ok(())
+    }
+}
+
+
+
+#[test] fn check_memory() {
+    let t = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1672531200);
+    let fs = Memory::new("mem");
+    fs.insert("index.html", &b"<p>hi</p>"[..], t);
+    fs.insert("sub/a.txt", b"abcdef".to_vec(), t);
+
+    let root = fs.list(Path::new("mem")).unwrap();
+    let mut names = root.entries().map(|e| (e.name_lossy().to_string(), e.is_dir())).collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(names, [("index.html".into(), false), ("sub".into(), true)]);
+    assert!(Arc::ptr_eq(&root, &fs.list(Path::new("mem")).unwrap()), "unchanged listings should be reused");
+
+    let a = root.by_name("sub").map(|e| fs.list(e.path()).unwrap()).unwrap().by_name("a.txt").unwrap().path().to_path_buf();
+    assert_eq!(fs.stat(&a), Some(Stat { len: 6, created: Some(t), modified: Some(t) }));
+    let mut range = String::new();
+    fs.read_range(&a, 2 .. 4).unwrap().read_to_string(&mut range).unwrap();
+    assert_eq!(range, "cd");
+    assert!(fs.stat(Path::new("mem/sub")).is_none(), "directories aren't files");
+    assert!(fs.list(&a).is_none(), "files aren't directories");
+
+    fs.write(Path::new("mem/sub"), "b.txt", &mut &b"new"[..]).unwrap();
+    assert_eq!(fs.list(Path::new("mem/sub")).unwrap().entries().count(), 2);
+    assert!(fs.write(Path::new("mem/missing"), "c.txt", &mut &b""[..]).is_err());
+    assert!(fs.write(Path::new("elsewhere"), "c.txt", &mut &b""[..]).is_err());
+
+    let fs = Memory::new("ro").read_only();
+    fs.insert("a.txt", &b"a"[..], t);
+    assert_eq!(fs.write(Path::new("ro"), "b.txt", &mut &b""[..]).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+}
@@ -1,23 +1,68 @@
+use crate::fs::dir::Snapshot;
+use crate::fs::file::Stat;
+use crate::mount;
+use std::borrow::Cow;
 use std::fmt::Display;
 use std::io::{Write, self};
 use std::time::SystemTime;
 
-pub fn respond_propfind_dir(xml: &mut impl Write, settings: &crate::Settings, root: &str, dir: &crate::fs::dir::Snapshot, depth: Option<u8>) -> io::Result<()> {
-    debug_assert!(root.starts_with("/") && root.ends_with("/"));
+/// Most `<response>`s a single PROPFIND will generate before giving up on the rest of the tree.
+const MAX_ENTRIES : usize = 10_000;
+
+/// Streams a multistatus response for `dir` and (up to `depth` levels of) its descendants into `xml`.
+/// `root` is the (percent-encoded) URL path of `dir`, `root_path` its decoded root-relative path, for `--allow`/`--deny`.
+/// Returns early with an error if `xml` does (e.g. because the client disconnected.)
+pub fn respond_propfind_dir(xml: &mut impl Write, settings: &crate::Settings, root: &str, root_path: &str, dir: mount::Dir, depth: Option<u8>) -> io::Result<()> {
+    debug_assert!(root.starts_with('/') && root.ends_with('/'));
     let depth = depth.unwrap_or(!0);
 
     writeln!(xml, r#"<?xml version="1.0" encoding="utf-8" ?>"#)?;
     writeln!(xml, r#"<multistatus xmlns="DAV:"> "#)?;
-    response_dir(xml, settings, root, dir, depth)?;
+    response_dir(xml, root, dir.snapshot())?;
+
+    // iterative depth-first traversal, so deep trees can't blow the stack
+    struct Frame<'m> { href: String, path: String, dir: mount::Dir<'m>, depth: u8, next: usize }
+    let mut stack = Vec::new();
+    if let Some(depth) = depth.checked_sub(1) { stack.push(Frame { href: root.into(), path: format!("{root_path}/"), dir, depth, next: 0 }); }
+    let mut budget = MAX_ENTRIES;
+
+    while let Some(frame) = stack.last_mut() {
+        let Some(e) = frame.dir.snapshot().entries().nth(frame.next) else { stack.pop(); continue };
+        frame.next += 1;
+        let name = e.name_lossy();
+        let path = format!("{}{name}", frame.path);
+        if !settings.rules.allows(&path, e.is_dir()) { continue }
+        let mut href = frame.href.clone();
+        crate::percent::encode_segment(e.name_os(), &mut href);
+
+        if budget == 0 {
+            response_truncated(xml, &frame.href)?;
+            break;
+        }
+
+        if e.is_dir() {
+            let Some(subdir) = frame.dir.child(e.name_os()) else { continue }; // vanished or unreadable: skip it
+            budget -= 1;
+            href.push('/');
+            response_dir(xml, &href, subdir.snapshot())?;
+            if let Some(depth) = frame.depth.checked_sub(1) { stack.push(Frame { href, path: path + "/", dir: subdir, depth, next: 0 }); }
+        } else if e.is_file() {
+            budget -= 1;
+            response_file(xml, &href, name, frame.dir.vfs_of(e).and_then(|vfs| vfs.stat(e.path())))?;
+        } else {
+            // ...?
+        }
+    }
+
     writeln!(xml, r#"</multistatus>"#)?;
     return Ok(());
 
-    fn response_dir(xml: &mut impl Write, settings: &crate::Settings, root: &str, dir: &crate::fs::dir::Snapshot, depth: u8) -> io::Result<()> {
+    fn response_dir(xml: &mut impl Write, href: &str, dir: &Snapshot) -> io::Result<()> {
         writeln!(xml, r#"  <response>"#)?;
-        writeln!(xml, r#"    <href>{root}</href>"#)?;
+        writeln!(xml, r#"    <href>{href}</href>"#)?;
         writeln!(xml, r#"    <propstat>"#)?;
         writeln!(xml, r#"      <prop>"#)?;
-        writeln!(xml, r#"        <displayname>{}</displayname>"#, dir.path().file_name().map_or("Untitled".into(), |os| os.to_string_lossy()))?;
+        writeln!(xml, r#"        <displayname>{}</displayname>"#, escape(&dir.path().file_name().map_or("Untitled".into(), |os| os.to_string_lossy())))?;
         writeln!(xml, r#"        <resourcetype><collection/></resourcetype>"#)?;
 
         // `dir` gets quite unhappy without creation + modification timestamps, so always provide both
@@ -32,49 +77,54 @@ pub fn respond_propfind_dir(xml: &mut impl Write, settings: &crate::Settings, ro
         writeln!(xml, r#"      <status>HTTP/1.0 200 OK</status>"#)?;
         writeln!(xml, r#"    </propstat>"#)?;
         writeln!(xml, r#"  </response>"#)?;
+        Ok(())
+    }
 
-        if let Some(depth) = depth.checked_sub(1) {
-            for e in dir.entries() {
-                let name = e.name_lossy();
-                if e.is_dir() {
-                    let subdir = settings.cache.read_dir(e.path()).ok_or(io::ErrorKind::Other)?;
-                    response_dir(xml, settings, &format!("{root}{name}/"), &subdir, depth)?;
-                } else if e.is_file() {
-                    writeln!(xml, r#"  <response>"#)?;
-                    writeln!(xml, r#"    <href>{root}{name}</href>"#)?;
-                    writeln!(xml, r#"    <propstat>"#)?;
-                    writeln!(xml, r#"      <prop>"#)?;
-                    writeln!(xml, r#"        <displayname>{name}</displayname>"#)?;
-                    writeln!(xml, r#"        <resourcetype/>"#)?;
-
-                    // `dir` gets quite unhappy without creation + modification timestamps, so always provide both
-                    let meta = e.path().metadata().ok();
-                    let meta = meta.as_ref();
-                    writeln!(xml, r#"        <getcontentlength>{}</getcontentlength>"#, meta.map_or(0, |m| m.len()))?;
-                    writeln!(xml, r#"        <creationdate>{}</creationdate>"#,         meta.and_then(|m| m.created ().ok()).and_then(|t| DateTimeUTC::try_from(t).ok()).unwrap_or_default().creationdate_style()       )?;
-                    writeln!(xml, r#"        <getlastmodified>{}</getlastmodified>"#,   meta.and_then(|m| m.modified().ok()).and_then(|t| DateTimeUTC::try_from(t).ok()).unwrap_or_default().getlastmodified_style()    )?;
-
-                    //writeln!(xml, r#"        <getcontenttype>text/html</getcontenttype>"#)?;
-                    //writeln!(xml, r#"        <getetag>"etag"</getetag>"#)?;
-                    //writeln!(xml, r#"        <supportedlock>"#)?;
-                    //writeln!(xml, r#"          <lockentry><lockscope><exclusive/></lockscope><locktype><write/></locktype></lockentry>"#)?;
-                    //writeln!(xml, r#"          <lockentry><lockscope><shared/></lockscope><locktype><write/></locktype></lockentry>"#)?;
-                    //writeln!(xml, r#"        </supportedlock>"#)?;
-                    writeln!(xml, r#"      </prop>"#)?;
-                    writeln!(xml, r#"      <status>HTTP/1.0 200 OK</status>"#)?;
-                    writeln!(xml, r#"    </propstat>"#)?;
-                    writeln!(xml, r#"  </response>"#)?;
-                } else {
-                    // ...?
-                }
-            }
-        }
+    fn response_file(xml: &mut impl Write, href: &str, name: &str, stat: Option<Stat>) -> io::Result<()> {
+        writeln!(xml, r#"  <response>"#)?;
+        writeln!(xml, r#"    <href>{href}</href>"#)?;
+        writeln!(xml, r#"    <propstat>"#)?;
+        writeln!(xml, r#"      <prop>"#)?;
+        writeln!(xml, r#"        <displayname>{}</displayname>"#, escape(name))?;
+        writeln!(xml, r#"        <resourcetype/>"#)?;
+
+        // `dir` gets quite unhappy without creation + modification timestamps, so always provide both
+        writeln!(xml, r#"        <getcontentlength>{}</getcontentlength>"#, stat.map_or(0, |s| s.len))?;
+        writeln!(xml, r#"        <creationdate>{}</creationdate>"#,         stat.and_then(|s| s.created ).and_then(|t| DateTimeUTC::try_from(t).ok()).unwrap_or_default().creationdate_style()       )?;
+        writeln!(xml, r#"        <getlastmodified>{}</getlastmodified>"#,   stat.and_then(|s| s.modified).and_then(|t| DateTimeUTC::try_from(t).ok()).unwrap_or_default().getlastmodified_style()    )?;
 
+        //writeln!(xml, r#"        <getcontenttype>text/html</getcontenttype>"#)?;
+        //writeln!(xml, r#"        <getetag>"etag"</getetag>"#)?;
+        //writeln!(xml, r#"        <supportedlock>"#)?;
+        //writeln!(xml, r#"          <lockentry><lockscope><exclusive/></lockscope><locktype><write/></locktype></lockentry>"#)?;
+        //writeln!(xml, r#"          <lockentry><lockscope><shared/></lockscope><locktype><write/></locktype></lockentry>"#)?;
+        //writeln!(xml, r#"        </supportedlock>"#)?;
+        writeln!(xml, r#"      </prop>"#)?;
+        writeln!(xml, r#"      <status>HTTP/1.0 200 OK</status>"#)?;
+        writeln!(xml, r#"    </propstat>"#)?;
+        writeln!(xml, r#"  </response>"#)?;
         Ok(())
     }
+
+    /// Tell the client we stopped early, in the style of [RFC 5323 § 2.6](https://www.rfc-editor.org/rfc/rfc5323#section-2.6)
+    fn response_truncated(xml: &mut impl Write, href: &str) -> io::Result<()> {
+        writeln!(xml, r#"  <response>"#)?;
+        writeln!(xml, r#"    <href>{href}</href>"#)?;
+        writeln!(xml, r#"    <status>HTTP/1.1 507 Insufficient Storage</status>"#)?;
+        writeln!(xml, r#"    <error><number-of-matches-within-limits/></error>"#)?;
+        writeln!(xml, r#"    <responsedescription>PROPFIND stopped after {MAX_ENTRIES} entries</responsedescription>"#)?;
+        writeln!(xml, r#"  </response>"#)?;
+        Ok(())
+    }
+}
+
+/// Escape text content for XML.
+fn escape(text: &str) -> Cow<str> {
+    if !text.contains(['&', '<', '>']) { return Cow::Borrowed(text) }
+    Cow::Owned(text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;"))
 }
 
-#[derive(Clone, Copy, Debug)] struct DateTimeUTC {
+#[derive(Clone, Copy, Debug)] pub struct DateTimeUTC {
     pub year:       u32,// 1+ (e.g. 2023)
     pub month_no:   u8, // 1 ..= 12
     pub day_no:     u8, // 1 ..= 31
@@ -113,7 +163,7 @@ impl DateTimeUTC {
     pub fn getlastmodified_style(&self) -> impl Display {
         let Self { year, month_no, day_no, hour, minute, second, dow } = *self;
         let month = ["", "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"].get(usize::from(month_no)).copied().unwrap_or("");
-        debug_assert!(month != "");
+        debug_assert!(!month.is_empty());
         let dow = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"][usize::from(dow)];
         format!("{dow}, {day_no} {month} {year} {hour:02}:{minute:02}:{second:02} GMT")
     }